@@ -14,16 +14,15 @@
 
 //! The altscreen module defines the representation of the alt screen.
 
-use std::collections::VecDeque;
+use std::{cmp::Ordering, collections::VecDeque};
 
 use crate::{
     cell::Cell,
+    error::GridError,
     line::{self, Line},
-    term::{self, AsTermInput, OriginMode, Pos, ScrollRegion},
+    term::{AsTermInput, OriginMode, Pos, ScrollRegion},
 };
 
-use anyhow::{anyhow, Context};
-
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct AltScreen {
     /// The entire grid the altscreen. size.height should always equal
@@ -58,18 +57,17 @@ impl AltScreen {
         size: crate::Size,
         mut cursor: Pos,
         cell: Cell,
-    ) -> anyhow::Result<Pos> {
+    ) -> Result<Pos, GridError> {
         if size.width < 1 {
-            return Err(anyhow!("cannot write to zero width terminal grid"));
+            return Err(GridError::ZeroWidth);
         }
 
+        // Just like the scrollback, we wrap before writing a cell rather
+        // than after, so the cursor can sit on the last column with a wrap
+        // pending and a \r right after leaves it on the same row.
         let cell_width = cell.width() as usize;
-        self.buf[cursor.row]
-            .set_cell(size.width, cursor.col, cell)
-            .context("setting cell in alt screen")?;
-
-        cursor.col += cell_width;
-        if cursor.col >= size.width {
+        if cursor.col + cell_width > size.width {
+            self.buf[cursor.row].is_wrapped = true;
             cursor.row += 1;
             cursor.col = 0;
 
@@ -79,9 +77,17 @@ impl AltScreen {
             // where it is in this case.
             if cursor.row >= size.height {
                 self.scroll_down(1);
+                cursor.row = size.height - 1;
+            }
+        }
+
+        self.buf[cursor.row].set_cell(size.width, cursor.col, cell)?;
+        for i in 1..cell_width {
+            if cursor.col + i < size.width {
+                self.buf[cursor.row].set_cell(size.width, cursor.col + i, Cell::wide_pad())?;
             }
         }
-        cursor.clamp_to(size);
+        cursor.col += cell_width;
 
         Ok(cursor)
     }
@@ -89,11 +95,11 @@ impl AltScreen {
     pub fn scroll_down(&mut self, rows: usize) {
         match self.scroll_region {
             ScrollRegion::TrackSize => {
-                for _ in 0..rows {
+                // Scrolling by more than the height just clears the screen.
+                for _ in 0..std::cmp::min(rows, self.buf.len()) {
                     self.buf.pop_front();
                     self.buf.push_back(Line::new());
                 }
-                return;
             }
             ScrollRegion::Window { top, bottom } => {
                 if rows > bottom - top {
@@ -125,27 +131,67 @@ impl AltScreen {
     /// Resize the alt screen. This does not perform any reflow logic,
     /// instead just trimming any cells that are no longer within the
     /// screen.
-    pub fn resize(&mut self, new_size: crate::Size) {
+    ///
+    /// Rows are added or dropped at the edge of the screen indicated by
+    /// `anchor`, except that the row the cursor is on is never dropped.
+    /// If keeping the cursor row requires it, rows get dropped from the
+    /// other edge instead.
+    ///
+    /// Returns the number of rows the retained content moved down by
+    /// (negative if it moved up) so that the caller can shift any cursors
+    /// to keep pointing at the same content.
+    pub fn resize(
+        &mut self,
+        new_size: crate::Size,
+        anchor: crate::AltScreenAnchor,
+        cursor_row: usize,
+    ) -> isize {
         for line in self.buf.iter_mut() {
             line.truncate(new_size.width);
         }
 
         let old_height = self.buf.len();
-        if new_size.height > old_height {
-            for _ in 0..(new_size.height - old_height) {
-                self.buf.push_back(Line::new());
+        match new_size.height.cmp(&old_height) {
+            Ordering::Greater => {
+                let n = new_size.height - old_height;
+                for _ in 0..n {
+                    match anchor {
+                        crate::AltScreenAnchor::Top => self.buf.push_back(Line::new()),
+                        crate::AltScreenAnchor::Bottom => self.buf.push_front(Line::new()),
+                    }
+                }
+                match anchor {
+                    crate::AltScreenAnchor::Top => 0,
+                    crate::AltScreenAnchor::Bottom => n as isize,
+                }
             }
-        } else if new_size.height < old_height {
-            for _ in 0..(old_height - new_size.height) {
-                self.buf.pop_back();
+            Ordering::Less => {
+                let n = old_height - new_size.height;
+                let drop_from_top = match anchor {
+                    crate::AltScreenAnchor::Top => (cursor_row + 1).saturating_sub(new_size.height),
+                    crate::AltScreenAnchor::Bottom => std::cmp::min(n, cursor_row),
+                };
+                let drop_from_top = std::cmp::min(n, drop_from_top);
+                for _ in 0..drop_from_top {
+                    self.buf.pop_front();
+                }
+                for _ in 0..(n - drop_from_top) {
+                    self.buf.pop_back();
+                }
+                -(drop_from_top as isize)
             }
+            Ordering::Equal => 0,
         }
-        // no-op if they have the same height
     }
 
-    pub fn get_line_mut(&mut self, row: usize) -> &mut Line {
-        assert!(row <= self.buf.len());
-        &mut self.buf[row]
+    /// Get the line at the given row, if the row is on the screen.
+    pub fn get_line(&self, row: usize) -> Option<&Line> {
+        self.buf.get(row)
+    }
+
+    /// Get the line at the given row, if the row is on the screen.
+    pub fn get_line_mut(&mut self, row: usize) -> Option<&mut Line> {
+        self.buf.get_mut(row)
     }
 
     //
@@ -153,7 +199,9 @@ impl AltScreen {
     //
 
     pub fn erase_to_end(&mut self, cursor: Pos) {
-        self.buf[cursor.row].truncate(cursor.col);
+        if let Some(line) = self.buf.get_mut(cursor.row) {
+            line.truncate(cursor.col);
+        }
 
         let end = match (self.origin_mode, &self.scroll_region) {
             (OriginMode::ScrollRegion, ScrollRegion::Window { bottom, .. }) => *bottom,
@@ -161,7 +209,7 @@ impl AltScreen {
         };
 
         for i in (cursor.row + 1)..end {
-            self.buf[i].truncate(0);
+            self.buf[i].clear();
         }
     }
 
@@ -171,10 +219,12 @@ impl AltScreen {
             _ => 0,
         };
 
-        for i in start..cursor.row {
-            self.buf[i].truncate(0);
+        for i in start..std::cmp::min(cursor.row, self.buf.len()) {
+            self.buf[i].clear();
+        }
+        if let Some(line) = self.buf.get_mut(cursor.row) {
+            line.erase(line::Section::StartTo(cursor.col));
         }
-        self.buf[cursor.row].erase(line::Section::StartTo(cursor.col));
     }
 
     pub fn erase(&mut self) {
@@ -184,68 +234,33 @@ impl AltScreen {
         };
 
         for i in start..end {
-            self.buf[i].truncate(0);
+            self.buf[i].clear();
         }
     }
 
     pub fn insert_lines(&mut self, cursor: &Pos, n: usize) {
-        let (top, bottom) = match self.scroll_region {
-            ScrollRegion::TrackSize => (0, self.buf.len()),
-            ScrollRegion::Window { top, bottom } => {
-                if cursor.row < top || bottom <= cursor.row {
-                    // Insert Line does nothing when the cursor is outside
-                    // the scroll region.
-                    return;
-                }
-                (top, bottom)
-            }
-        };
-
-        // We want to solve for `shuffle_lines` in:
-        //
-        // ```
-        // (cursor.row - top) + min(n, bottom - cursor.row) + shuffle_lines =
-        //    (bottom - top)
-        // ```
-        let lines_to_insert = std::cmp::min(n, bottom - cursor.row);
-        let shuffle_lines = (bottom - top) - lines_to_insert - (cursor.row - top);
-        for i in 0..shuffle_lines {
-            // By using std::mem::replace rather than cloning we can avoid a little
-            // work on the second pass.
-            let bottom_offset = bottom - 1 - i;
-            self.buf[bottom_offset] =
-                std::mem::replace(&mut self.buf[bottom_offset - lines_to_insert], Line::new());
+        if let Some((top, bottom)) = self.line_shift_bounds(cursor) {
+            line::insert_lines(&mut self.buf.make_contiguous()[top..bottom], cursor.row - top, n);
         }
+    }
 
-        // clober any lines that are not handled by the initial pass.
-        for i in shuffle_lines..lines_to_insert {
-            self.buf[cursor.row + i] = Line::new();
+    pub fn delete_lines(&mut self, cursor: &Pos, n: usize) {
+        if let Some((top, bottom)) = self.line_shift_bounds(cursor) {
+            line::delete_lines(&mut self.buf.make_contiguous()[top..bottom], cursor.row - top, n);
         }
     }
 
-    pub fn delete_lines(&mut self, cursor: &Pos, n: usize) {
+    /// The rows that IL and DL shuffle, or None if the cursor is outside
+    /// the scroll region, in which case those commands do nothing.
+    fn line_shift_bounds(&self, cursor: &Pos) -> Option<(usize, usize)> {
         let (top, bottom) = match self.scroll_region {
             ScrollRegion::TrackSize => (0, self.buf.len()),
-            ScrollRegion::Window { top, bottom } => {
-                if cursor.row < top || bottom <= cursor.row {
-                    // Delete Line does nothing when the cursor is outside
-                    // the scroll region.
-                    return;
-                }
-                (top, bottom)
-            }
+            ScrollRegion::Window { top, bottom } => (top, std::cmp::min(bottom, self.buf.len())),
         };
-
-        let lines_to_delete = std::cmp::min(n, bottom - cursor.row);
-        let shuffle_lines = (bottom - top) - lines_to_delete - (cursor.row - top);
-
-        for i in 0..shuffle_lines {
-            self.buf[cursor.row + i] =
-                std::mem::replace(&mut self.buf[cursor.row + lines_to_delete + i], Line::new());
-        }
-        for i in shuffle_lines..lines_to_delete {
-            self.buf[cursor.row + i] = Line::new();
+        if cursor.row < top || bottom <= cursor.row {
+            return None;
         }
+        Some((top, bottom))
     }
 }
 
@@ -260,12 +275,7 @@ impl std::fmt::Display for AltScreen {
 
 impl AsTermInput for AltScreen {
     fn term_input_into(&self, buf: &mut Vec<u8>) {
-        for (i, line) in self.buf.iter().enumerate() {
-            line.term_input_into(buf);
-            if i != self.buf.len() - 1 {
-                term::Crlf::default().term_input_into(buf);
-            }
-        }
+        line::term_input_lines_into(self.buf.iter(), buf);
 
         self.scroll_region.term_input_into(buf);
     }
@@ -13,18 +13,76 @@
 // limitations under the License.
 
 use smallvec::{smallvec, SmallVec};
-use std::sync::OnceLock;
+use std::sync::{Arc, OnceLock};
+use tracing::debug;
 use unicode_width::UnicodeWidthChar;
 
 use crate::term::{self, AsTermInput};
 
 static EMPTY_CELL: OnceLock<Cell> = OnceLock::new();
+static DEFAULT_ATTRS: OnceLock<Arc<term::Attrs>> = OnceLock::new();
 
 // A shared empty cell const. Should be used to generate empty cell
 // references when needed to avoid duplicating empty cells to reference
 // everywhere.
 pub fn empty() -> &'static Cell {
-    EMPTY_CELL.get_or_init(|| Cell::empty())
+    EMPTY_CELL.get_or_init(Cell::empty)
+}
+
+// The shared default attrs. Most cells have no attributes at all, so
+// they all point here rather than allocating their own.
+fn default_attrs() -> Arc<term::Attrs> {
+    DEFAULT_ATTRS.get_or_init(|| Arc::new(term::Attrs::default())).clone()
+}
+
+fn share_attrs(attrs: term::Attrs) -> Arc<term::Attrs> {
+    if attrs == term::Attrs::default() {
+        default_attrs()
+    } else {
+        Arc::new(attrs)
+    }
+}
+
+/// The max number of distinct attrs an `AttrsInterner` keeps around.
+const INTERNER_ENTRIES: usize = 32;
+
+/// Hands out shared copies of attributes so that cells with the same
+/// attributes all point at a single allocation. Output tends to come in
+/// runs that share a handful of styles, so we only remember the most
+/// recently used ones rather than every style ever seen.
+#[derive(Clone, Default)]
+pub struct AttrsInterner {
+    entries: Vec<Arc<term::Attrs>>,
+}
+
+impl AttrsInterner {
+    /// Get a shared copy of the given attrs.
+    pub fn intern(&mut self, attrs: &term::Attrs) -> Arc<term::Attrs> {
+        if *attrs == term::Attrs::default() {
+            return default_attrs();
+        }
+        if let Some(entry) = self.entries.iter().rev().find(|e| ***e == *attrs) {
+            return entry.clone();
+        }
+
+        if self.entries.len() >= INTERNER_ENTRIES {
+            // Entries no cell points at any more are free to go, and
+            // failing that we evict the oldest.
+            self.entries.retain(|e| Arc::strong_count(e) > 1);
+            if self.entries.len() >= INTERNER_ENTRIES {
+                self.entries.remove(0);
+            }
+        }
+        let entry = Arc::new(attrs.clone());
+        self.entries.push(entry.clone());
+        entry
+    }
+
+    /// The number of distinct attrs currently interned.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
 }
 
 /// A cell in a terminal.
@@ -45,8 +103,12 @@ pub struct Cell {
     /// Indicates a special type of empty cell inserted after wide chars
     /// to keep the grid correctly aligned.
     wide_padding: bool,
-    /// The attributes of the cell.
-    attrs: term::Attrs,
+    /// The attributes of the cell. These are shared with other cells that
+    /// have the same attributes (see `AttrsInterner`).
+    attrs: Arc<term::Attrs>,
+    /// Set for cells written while DECSCA character protection was on.
+    /// Selective erase (DECSED / DECSEL) leaves protected cells alone.
+    protected: bool,
 }
 
 // Prove that a `SmallVec<[char, 2]>` is just as memory efficient as a
@@ -62,6 +124,12 @@ static_assertions::const_assert!(
 impl Cell {
     /// Create a new cell wrapping the given char.
     pub fn new(c: char, attrs: term::Attrs) -> Self {
+        Self::with_shared_attrs(c, share_attrs(attrs))
+    }
+
+    /// Create a new cell wrapping the given char, with attrs that were
+    /// already interned.
+    pub(crate) fn with_shared_attrs(c: char, attrs: Arc<term::Attrs>) -> Self {
         let width = match UnicodeWidthChar::width(c) {
             None => panic!("control chars cannot create cells"),
             Some(0) => panic!("zero width chars cannot create cells"),
@@ -74,21 +142,33 @@ impl Cell {
             empty: false,
             wide_padding: false,
             attrs,
+            protected: false,
         }
     }
 
+    /// A blank cell with default attrs, the same as a cell that was
+    /// never written. It shows up as a space in dumps, with no attribute
+    /// codes.
     pub fn empty() -> Self {
         Cell {
             grapheme_cluster: smallvec![],
             width: 0,
             empty: true,
             wide_padding: false,
-            attrs: term::Attrs::default(),
+            attrs: default_attrs(),
+            protected: false,
         }
     }
 
     pub fn empty_with_attrs(attrs: term::Attrs) -> Self {
-        Cell { grapheme_cluster: smallvec![], width: 0, empty: true, wide_padding: false, attrs }
+        Cell {
+            grapheme_cluster: smallvec![],
+            width: 0,
+            empty: true,
+            wide_padding: false,
+            attrs: share_attrs(attrs),
+            protected: false,
+        }
     }
 
     pub fn wide_pad() -> Self {
@@ -97,14 +177,24 @@ impl Cell {
             width: 0,
             empty: true,
             wide_padding: true,
-            attrs: term::Attrs::default(),
+            attrs: default_attrs(),
+            protected: false,
         }
     }
 
-    /// Append a modifier char to the grapheme_cluster.
+    /// Append a char to the grapheme_cluster. This is usually a zero width
+    /// modifier, but chars joined by a ZWJ get added this way too. Either
+    /// way the width of the cell stays the width of its base char. Once
+    /// the base char has `MAX_PENDING_MARKS` chars joined onto it, any
+    /// more get dropped so a stream of marks can't grow the cell without
+    /// bound.
     pub fn add_char(&mut self, c: char) {
-        assert!(UnicodeWidthChar::width(c).unwrap_or(0) > 0, "non-zero width char added to cell");
+        assert!(UnicodeWidthChar::width(c).is_some(), "control char added to cell");
 
+        if self.grapheme_cluster.len() > crate::MAX_PENDING_MARKS {
+            debug!("add_char: dropping {c:?} joined onto a full cell");
+            return;
+        }
         self.grapheme_cluster.push(c);
     }
 
@@ -112,20 +202,135 @@ impl Cell {
         self.width
     }
 
-    pub fn is_empty(&self) -> bool {
+    /// Indicates if this is a blank that looks no different from a cell
+    /// that was never written, so it can be left out of a dump. Blanks
+    /// with a background color or other attrs still show up on screen,
+    /// and protected ones would lose their protection.
+    pub(crate) fn is_plain_blank(&self) -> bool {
+        self.is_empty() && !self.wide_padding && !self.protected
+    }
+
+    /// Indicates that this cell has no glyph, regardless of its attrs.
+    pub(crate) fn is_blank(&self) -> bool {
         self.empty
     }
 
+    /// The number of screen columns this cell accounts for. Blank cells
+    /// still take up their column, while the pads after a wide cell are
+    /// already counted by the wide cell itself.
+    pub(crate) fn columns(&self) -> usize {
+        if self.wide_padding {
+            0
+        } else {
+            std::cmp::max(self.width as usize, 1)
+        }
+    }
+
+    /// Indicates that this cell holds a character that takes up more than
+    /// one column. The columns after a wide cell are filled with
+    /// placeholder cells (see `is_wide_pad`).
+    pub fn is_wide(&self) -> bool {
+        self.width > 1
+    }
+
+    /// Indicates that this cell is a placeholder for the trailing column
+    /// of a wide character stored in a cell to its left.
+    pub fn is_wide_pad(&self) -> bool {
+        self.wide_padding
+    }
+
+    /// Indicates that this cell has no glyph and default attrs. A blank
+    /// left behind by an erase with a background color set is not empty,
+    /// since it still shows up on screen.
+    pub fn is_empty(&self) -> bool {
+        self.empty && !self.attrs.has_attrs()
+    }
+
+    pub fn is_protected(&self) -> bool {
+        self.protected
+    }
+
+    pub fn set_protected(&mut self, protected: bool) {
+        self.protected = protected;
+    }
+
     pub fn attrs(&self) -> &term::Attrs {
         &self.attrs
     }
+
+    /// The chars making up the grapheme cluster displayed in this cell.
+    /// Empty for blank cells.
+    pub fn chars(&self) -> &[char] {
+        &self.grapheme_cluster
+    }
+
+    pub fn fg(&self) -> term::Color {
+        self.attrs.fgcolor
+    }
+
+    pub fn bg(&self) -> term::Color {
+        self.attrs.bgcolor
+    }
+
+    pub fn is_bold(&self) -> bool {
+        self.attrs.font_weight == Some(term::FontWeight::Bold)
+    }
+
+    pub fn is_faint(&self) -> bool {
+        self.attrs.font_weight == Some(term::FontWeight::Faint)
+    }
+
+    pub fn is_italic(&self) -> bool {
+        self.attrs.italic
+    }
+
+    pub fn is_underlined(&self) -> bool {
+        self.attrs.underline.is_some()
+    }
+
+    pub fn is_inverse(&self) -> bool {
+        self.attrs.inverse
+    }
+
+    pub fn is_blinking(&self) -> bool {
+        self.attrs.blink.is_some()
+    }
+
+    pub fn is_concealed(&self) -> bool {
+        self.attrs.conceal
+    }
+
+    pub fn is_strikethrough(&self) -> bool {
+        self.attrs.strikethrough
+    }
+
+    /// Write this cell to the buffer along with any control codes needed
+    /// to get from `prev_attrs` to the attributes of this cell. Returns the
+    /// attrs in effect after the cell is written, which should be passed
+    /// as `prev_attrs` when writing the next cell in a run.
+    pub fn term_input_with_attrs_into<'a>(
+        &'a self,
+        buf: &mut Vec<u8>,
+        prev_attrs: &'a term::Attrs,
+    ) -> &'a term::Attrs {
+        let mut current_attrs = prev_attrs;
+        if *self.attrs != *current_attrs {
+            for code in current_attrs.transition_to(&self.attrs) {
+                code.term_input_into(buf);
+            }
+            current_attrs = &self.attrs;
+        }
+        self.term_input_into(buf);
+
+        current_attrs
+    }
 }
 
 impl AsTermInput for Cell {
     fn term_input_into(&self, buf: &mut Vec<u8>) {
-        // N.B. while cells store attributes, they are not responsible
-        // for generating control codes to display them. Instead, lines
-        // produce attribute control codes at cell boundaries.
+        // N.B. while cells store attributes, this does not generate
+        // control codes to display them. Use `term_input_with_attrs_into`
+        // to write a cell as part of an attribute run.
         let mut utf8_buf = [0u8; 4];
         for c in self.grapheme_cluster.iter() {
             let utf8_slice = c.encode_utf8(&mut utf8_buf);
@@ -155,6 +360,17 @@ impl std::fmt::Display for Cell {
     }
 }
 
+// Only the parts of a cell that show up on screen get hashed, so a wide
+// pad hashes the same as a blank and protection is ignored. Equal cells
+// still always hash the same.
+impl std::hash::Hash for Cell {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.grapheme_cluster.hash(state);
+        self.width.hash(state);
+        self.attrs.hash(state);
+    }
+}
+
 impl std::fmt::Debug for Cell {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "<")?;
@@ -176,3 +392,94 @@ impl std::fmt::Debug for Cell {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bold_after_plain() {
+        let plain = Cell::new('a', term::Attrs::default());
+        let bold = Cell::new(
+            'b',
+            term::Attrs { font_weight: Some(term::FontWeight::Bold), ..term::Attrs::default() },
+        );
+
+        let mut buf = vec![];
+        let blank_attrs = term::Attrs::default();
+        let attrs = plain.term_input_with_attrs_into(&mut buf, &blank_attrs);
+        assert_eq!(attrs, &blank_attrs);
+        let attrs = bold.term_input_with_attrs_into(&mut buf, attrs);
+        assert_eq!(attrs, bold.attrs());
+
+        let mut expected = vec![b'a'];
+        term::control_codes().bold.term_input_into(&mut expected);
+        expected.push(b'b');
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn same_attrs_no_codes() {
+        let attrs =
+            term::Attrs { font_weight: Some(term::FontWeight::Bold), ..term::Attrs::default() };
+        let cell = Cell::new('x', attrs.clone());
+
+        let mut buf = vec![];
+        cell.term_input_with_attrs_into(&mut buf, &attrs);
+        assert_eq!(buf, b"x");
+    }
+
+    #[test]
+    fn empty_cell_is_plain_space() {
+        let empty = Cell::empty();
+        assert!(empty.is_empty());
+        assert_eq!(&empty, super::empty());
+
+        let mut buf = vec![];
+        empty.term_input_with_attrs_into(&mut buf, &term::Attrs::default());
+        assert_eq!(buf, b" ");
+
+        let bg = Cell::empty_with_attrs(term::Attrs {
+            bgcolor: term::Color::Idx(4),
+            ..term::Attrs::default()
+        });
+        assert!(!bg.is_empty());
+        assert!(bg.is_blank());
+        assert_ne!(empty, bg);
+    }
+
+    #[test]
+    fn interned_runs_share_attrs() {
+        let mut interner = AttrsInterner::default();
+        let bold =
+            term::Attrs { font_weight: Some(term::FontWeight::Bold), ..term::Attrs::default() };
+        let cells: Vec<Cell> =
+            (0..1000).map(|_| Cell::with_shared_attrs('x', interner.intern(&bold))).collect();
+
+        assert_eq!(interner.len(), 1);
+        assert!(cells.iter().all(|c| Arc::ptr_eq(&c.attrs, &cells[0].attrs)));
+        assert_eq!(cells[0], Cell::new('x', bold));
+
+        // Default attrs never take up an entry.
+        let plain = Cell::with_shared_attrs('y', interner.intern(&term::Attrs::default()));
+        assert_eq!(interner.len(), 1);
+        assert!(Arc::ptr_eq(&plain.attrs, &Cell::empty().attrs));
+    }
+
+    #[test]
+    fn interner_evicts_unused() {
+        let mut interner = AttrsInterner::default();
+        let mut kept = vec![];
+        for font in 0..(INTERNER_ENTRIES as u8 * 2) {
+            let attrs = term::Attrs { font, ..term::Attrs::default() };
+            let shared = interner.intern(&attrs);
+            if font % 2 == 0 {
+                kept.push(shared);
+            }
+            assert!(interner.len() <= INTERNER_ENTRIES);
+        }
+        for shared in kept.iter().rev().take(4) {
+            assert!(Arc::ptr_eq(shared, &interner.intern(shared)));
+        }
+    }
+}
@@ -0,0 +1,42 @@
+// Copyright 2026 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The error module defines the errors that grid operations can produce.
+//! Grid writes happen for every printed char, so these are plain enums
+//! rather than anyhow errors, which allocate to hold their context.
+
+/// A failed operation on a grid or one of its lines.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum GridError {
+    /// The grid has no columns to write to.
+    ZeroWidth,
+    /// The column is past the edge of the line.
+    ColOutOfBounds { col: usize, width: usize },
+    /// The row should have been backed by a line, but was not.
+    MissingLine(usize),
+}
+
+impl std::fmt::Display for GridError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GridError::ZeroWidth => write!(f, "cannot write to zero width terminal grid"),
+            GridError::ColOutOfBounds { col, width } => {
+                write!(f, "{col} out of bounds (width={width})")
+            }
+            GridError::MissingLine(row) => write!(f, "unexpectedly missing line at row {row}"),
+        }
+    }
+}
+
+impl std::error::Error for GridError {}
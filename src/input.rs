@@ -0,0 +1,183 @@
+// Copyright 2026 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The input module encodes key presses into the bytes a program running
+//! in the terminal expects to read for them. Which bytes those are depends
+//! on modes the program has set, so the encoding lives next to the state
+//! that tracks them.
+
+/// A key that does not just send a plain char.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Key {
+    Up,
+    Down,
+    Right,
+    Left,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// The main Enter (Return) key.
+    Enter,
+    /// A function key, F1 through F12. Others encode to nothing.
+    F(u8),
+    /// A digit on the numeric keypad.
+    KeypadDigit(u8),
+    KeypadEnter,
+    KeypadPlus,
+    KeypadMinus,
+    KeypadMultiply,
+    KeypadDivide,
+    KeypadDecimal,
+}
+
+/// The modifier keys held down along with a key.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+impl Modifiers {
+    /// The xterm modifier param, or None with no modifiers held.
+    fn param(&self) -> Option<u16> {
+        let bits = self.shift as u16 | (self.alt as u16) << 1 | (self.ctrl as u16) << 2;
+        if bits == 0 {
+            None
+        } else {
+            Some(bits + 1)
+        }
+    }
+}
+
+/// The modes that change how keys get encoded.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct KeyModes {
+    /// DECCKM, application cursor keys.
+    pub app_cursor: bool,
+    /// DECKPAM, application keypad.
+    pub app_keypad: bool,
+    /// The active kitty keyboard flags.
+    pub kitty_flags: u16,
+    /// LNM, newline mode, which makes Enter send CR LF.
+    pub newline_mode: bool,
+}
+
+/// The kitty flag asking for keys to be sent unambiguously, which among
+/// other things gives keypad keys their own codes.
+const KITTY_DISAMBIGUATE: u16 = 1;
+
+/// Encode the given key press, or give an empty vec for keys with no
+/// encoding.
+///
+/// Keys follow xterm. Cursor keys, Home, End and F1 through F4 use SS3
+/// when unmodified (cursor keys only in application cursor mode), and
+/// otherwise the CSI form with a modifier param. Keypad keys send plain
+/// chars unless application keypad mode is on, in which case they send
+/// SS3 codes, ignoring modifiers. When the kitty disambiguate flag is set,
+/// keypad keys instead get their own CSI u codes. Enter sends CR, or CR LF
+/// in newline mode, which goes for a plain keypad Enter too.
+pub(crate) fn encode_key(key: Key, modifiers: Modifiers, modes: KeyModes) -> Vec<u8> {
+    if let Some((kitty_code, plain, ss3)) = keypad_codes(key) {
+        if modes.kitty_flags & KITTY_DISAMBIGUATE != 0 {
+            return csi(kitty_code, modifiers.param(), b'u');
+        }
+        if modes.app_keypad {
+            return vec![0x1b, b'O', ss3];
+        }
+        if key == Key::KeypadEnter {
+            return enter(modes);
+        }
+        return vec![plain];
+    }
+
+    match key {
+        Key::Up | Key::Down | Key::Right | Key::Left | Key::Home | Key::End => {
+            let final_byte = match key {
+                Key::Up => b'A',
+                Key::Down => b'B',
+                Key::Right => b'C',
+                Key::Left => b'D',
+                Key::Home => b'H',
+                _ => b'F',
+            };
+            match modifiers.param() {
+                Some(m) => csi(1, Some(m), final_byte),
+                None if modes.app_cursor => vec![0x1b, b'O', final_byte],
+                None => vec![0x1b, b'[', final_byte],
+            }
+        }
+        Key::F(n @ 1..=4) => {
+            let final_byte = b'P' + (n - 1);
+            match modifiers.param() {
+                Some(m) => csi(1, Some(m), final_byte),
+                None => vec![0x1b, b'O', final_byte],
+            }
+        }
+        Key::F(n) => {
+            let code = match n {
+                5 => 15,
+                6..=10 => n as u16 + 11,
+                11 | 12 => n as u16 + 12,
+                _ => return vec![],
+            };
+            csi(code, modifiers.param(), b'~')
+        }
+        Key::Insert => csi(2, modifiers.param(), b'~'),
+        Key::Delete => csi(3, modifiers.param(), b'~'),
+        Key::PageUp => csi(5, modifiers.param(), b'~'),
+        Key::PageDown => csi(6, modifiers.param(), b'~'),
+        Key::Enter => enter(modes),
+        _ => vec![],
+    }
+}
+
+/// What the Enter key sends.
+fn enter(modes: KeyModes) -> Vec<u8> {
+    if modes.newline_mode {
+        b"\r\n".to_vec()
+    } else {
+        b"\r".to_vec()
+    }
+}
+
+/// The (kitty code, plain char, SS3 final byte) for keypad keys.
+fn keypad_codes(key: Key) -> Option<(u16, u8, u8)> {
+    Some(match key {
+        Key::KeypadDigit(d @ 0..=9) => (57399 + d as u16, b'0' + d, b'p' + d),
+        Key::KeypadDecimal => (57409, b'.', b'n'),
+        Key::KeypadDivide => (57410, b'/', b'o'),
+        Key::KeypadMultiply => (57411, b'*', b'j'),
+        Key::KeypadMinus => (57412, b'-', b'm'),
+        Key::KeypadPlus => (57413, b'+', b'k'),
+        Key::KeypadEnter => (57414, b'\r', b'M'),
+        _ => return None,
+    })
+}
+
+/// `CSI code ; modifier final`, leaving off the modifier if there is
+/// none.
+fn csi(code: u16, modifier: Option<u16>, final_byte: u8) -> Vec<u8> {
+    let mut buf = vec![0x1b, b'['];
+    buf.extend_from_slice(itoa::Buffer::new().format(code).as_bytes());
+    if let Some(m) = modifier {
+        buf.push(b';');
+        buf.extend_from_slice(itoa::Buffer::new().format(m).as_bytes());
+    }
+    buf.push(final_byte);
+    buf
+}
@@ -15,12 +15,9 @@
 use std::collections::BTreeMap;
 
 use crate::{
-    cell::Cell,
+    cell::AttrsInterner,
     screen::{SavedCursor, Screen},
-    term::{
-        AsTermInput, BlinkStyle, ControlCodes, FontWeight, FrameStyle, LinkTarget, OriginMode,
-        Region, UnderlineStyle,
-    },
+    term::{AsTermInput, ControlCodes, OriginMode, Region},
 };
 
 use bitvec::{bitvec, vec::BitVec};
@@ -29,6 +26,8 @@ use tracing::{debug, trace, warn};
 
 mod altscreen;
 mod cell;
+mod error;
+mod input;
 mod line;
 mod screen;
 mod scrollback;
@@ -39,10 +38,90 @@ mod term;
 #[cfg(feature = "internal-test")]
 pub mod term;
 
+pub use cell::Cell;
+pub use input::{Key, Modifiers};
+pub use term::{
+    Attrs, BlinkStyle, Color, CursorShape, CursorStyle, FontWeight, FrameStyle, LinkTarget, Pos,
+    Rgb, UnderlineStyle,
+};
+
 /// A representation of a terminal.
 pub struct Term {
     parser: vte::Parser,
+    osc_limiter: OscLimiter,
     state: State,
+    /// What the screen looked like as of the last `flush_damage`, or None
+    /// if the next flush has to redraw everything.
+    flushed: Option<Frame>,
+}
+
+/// A copy of the visible screen, kept around to work out which rows
+/// changed between flushes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Frame {
+    alt: bool,
+    size: Size,
+    lines: Vec<line::Line>,
+    cursor: Pos,
+    pending_wrap: bool,
+    cursor_attrs: term::Attrs,
+}
+
+impl Frame {
+    /// Indicates if any line went back to single size since `prev`.
+    /// Redrawing a line doesn't undo its double width or height on the
+    /// other end, but the clear in a full redraw does.
+    fn drops_line_size(&self, prev: &Frame) -> bool {
+        prev.lines.iter().zip(self.lines.iter()).any(|(p, l)| {
+            p.line_size != term::LineSize::Single && l.line_size == term::LineSize::Single
+        })
+    }
+}
+
+/// If more than this percentage of the rows are dirty, `flush_damage`
+/// just redraws the whole screen, which is about as cheap and more robust.
+const MAX_DIRTY_PERCENT: usize = 50;
+
+/// The most bytes of OSC payload we are willing to buffer. Nothing
+/// legitimate comes close, and vte will happily buffer forever.
+const MAX_OSC_LEN: usize = 64 * 1024;
+
+/// Watches the input for OSC strings so that oversized ones can be cut
+/// off before they reach the parser.
+#[derive(Default)]
+struct OscLimiter {
+    after_esc: bool,
+    /// The number of payload bytes seen so far if we are in an OSC.
+    osc_len: Option<usize>,
+}
+
+impl OscLimiter {
+    /// Returns false if the byte is part of an OSC payload that has
+    /// grown past `MAX_OSC_LEN` and should not be fed to the parser.
+    fn admit(&mut self, byte: u8) -> bool {
+        let after_esc = std::mem::replace(&mut self.after_esc, false);
+        match byte {
+            // ESC always ends an OSC, and might start a new one.
+            b'\x1b' => {
+                self.after_esc = true;
+                self.osc_len = None;
+            }
+            b']' if after_esc => self.osc_len = Some(0),
+            // BEL, CAN and SUB
+            b'\x07' | b'\x18' | b'\x1a' => self.osc_len = None,
+            _ => {
+                if let Some(len) = &mut self.osc_len {
+                    *len += 1;
+                    if *len == MAX_OSC_LEN + 1 {
+                        warn!("OSC longer than {MAX_OSC_LEN} bytes, dropping it");
+                    }
+                    return *len <= MAX_OSC_LEN;
+                }
+            }
+        }
+
+        true
+    }
 }
 
 impl Term {
@@ -55,7 +134,35 @@ impl Term {
     /// less than size.height, it will be automatically adjusted
     /// to be equal to size.height.
     pub fn new(scrollback_lines: usize, size: Size) -> Self {
-        Term { parser: vte::Parser::new(), state: State::new(scrollback_lines, size) }
+        Term {
+            parser: vte::Parser::new(),
+            osc_limiter: OscLimiter::default(),
+            state: State::new(scrollback_lines, size),
+            flushed: None,
+        }
+    }
+
+    /// Create a new terminal and restore the contents previously
+    /// generated by a call to `contents`. This is the intended way to
+    /// rebuild a terminal when reattaching to a saved session.
+    ///
+    /// The contents must begin with the reset prefix that `contents`
+    /// emits. Since a fresh terminal is already in the reset state, the
+    /// prefix is skipped rather than parsed. If the prefix is missing
+    /// the whole buffer is processed as normal input.
+    pub fn from_contents(scrollback_lines: usize, size: Size, contents: &[u8]) -> Self {
+        let mut term = Term::new(scrollback_lines, size);
+        let prefix = Self::reset_prefix();
+        match contents.strip_prefix(prefix.as_slice()) {
+            Some(rest) => {
+                term.process(rest);
+            }
+            None => {
+                warn!("from_contents: contents do not start with the reset prefix");
+                term.process(contents);
+            }
+        }
+        term
     }
 
     /// Get the current terminal size.
@@ -75,6 +182,155 @@ impl Term {
         self.state.resize(size);
     }
 
+    /// Change just the height of the visible window, leaving the width
+    /// alone. Unlike `resize`, this never reflows the scrollback, so
+    /// stored lines keep their exact wrapping. This is handy when the
+    /// embedder takes a few rows for itself, such as for a status bar.
+    /// Width changes still need to go through `resize`.
+    pub fn set_viewport_height(&mut self, height: usize) {
+        if height > self.scrollback_lines() {
+            self.set_scrollback_lines(height);
+        }
+
+        self.state.set_viewport_height(height);
+    }
+
+    /// Reset the terminal to its initial state, the same way an RIS
+    /// (`ESC c`) in the input stream does.
+    pub fn reset(&mut self) {
+        self.parser = vte::Parser::new();
+        self.osc_limiter = OscLimiter::default();
+        self.state.reset();
+        self.flushed = None;
+    }
+
+    /// The bytes a program running in the terminal expects to read when
+    /// the given key is pressed, taking application cursor keys mode
+    /// (DECCKM), application keypad mode (DECKPAM), newline mode (LNM) and
+    /// the kitty keyboard flags into account. Keys with no encoding give an empty vec.
+    pub fn encode_key(&self, key: Key, modifiers: Modifiers) -> Vec<u8> {
+        let modes = input::KeyModes {
+            app_cursor: self.state.app_cursor,
+            app_keypad: self.state.app_keypad,
+            kitty_flags: self.state.screen().keyboard_flags(),
+            newline_mode: self.state.newline_mode,
+        };
+        input::encode_key(key, modifiers, modes)
+    }
+
+    /// Drop all the scrollback above the visible window, keeping what is
+    /// on screen. This does what `CSI 3 J` does on most terminals without
+    /// needing to feed an escape sequence through. Prompt marks on the
+    /// dropped lines go away with them. The alt screen has no scrollback
+    /// of its own, but this still clears the main screen's while the alt
+    /// screen is up.
+    pub fn clear_scrollback(&mut self) {
+        self.state.clear_scrollback();
+    }
+
+    /// Get the cell at the given row and column of the visible screen,
+    /// where (0, 0) is the upper left. Returns None if the position is
+    /// outside the screen.
+    pub fn cell_at(&self, row: usize, col: usize) -> Option<&Cell> {
+        self.state.screen().get_cell(term::Pos { row, col })
+    }
+
+    /// The full grapheme cluster displayed at the given position of the
+    /// visible screen, including any combining marks or ZWJ joined chars.
+    /// The trailing columns of a wide char give the same cluster as its
+    /// first column. Blank cells give an empty string, and positions off
+    /// the screen give None.
+    pub fn grapheme_at(&self, pos: Pos) -> Option<String> {
+        Some(self.owning_cell(pos)?.chars().iter().collect())
+    }
+
+    /// A copy of the attributes of the cell at the given position of the
+    /// visible screen, or None if the position is off the screen. Like
+    /// `grapheme_at`, the trailing columns of a wide char give the
+    /// attributes of its first column.
+    pub fn attrs_at(&self, pos: Pos) -> Option<Attrs> {
+        Some(self.owning_cell(pos)?.attrs().clone())
+    }
+
+    /// The cell at the given position, or the wide char it pads out.
+    fn owning_cell(&self, pos: Pos) -> Option<&Cell> {
+        let screen = self.state.screen();
+        let mut pos = pos;
+        let mut cell = screen.get_cell(pos)?;
+        while cell.is_wide_pad() && pos.col > 0 {
+            pos.col -= 1;
+            cell = screen.get_cell(pos)?;
+        }
+        Some(cell)
+    }
+
+    /// Indicates if the cursor is on the last row of the visible screen,
+    /// where the next line feed scrolls the screen rather than moving
+    /// the cursor (unless a scroll region ending higher up is set).
+    pub fn visible_cursor_row_is_last(&self) -> bool {
+        let screen = self.state.screen();
+        screen.cursor.row + 1 >= screen.size.height
+    }
+
+    /// Indicates if the alt screen is currently active.
+    pub fn is_alt_screen(&self) -> bool {
+        matches!(self.state.screen_mode, ScreenMode::Alt)
+    }
+
+    /// Set the scroll region of the active screen to rows [top, bottom),
+    /// zero indexed, just like `CSI top+1 ; bottom r` would. The bottom
+    /// is clamped to the screen height and the cursor goes home. Returns
+    /// false, leaving the old region in place, if the region would span
+    /// fewer than two rows.
+    pub fn set_scroll_region(&mut self, top: usize, bottom: usize) -> bool {
+        self.state.set_scroll_window(top, bottom)
+    }
+
+    /// Get the scroll region of the active screen as zero indexed rows
+    /// [top, bottom), or None if no region is set and the whole screen
+    /// scrolls.
+    pub fn scroll_region(&self) -> Option<(usize, usize)> {
+        match self.state.screen().scroll_region(false) {
+            term::ScrollRegion::TrackSize => None,
+            term::ScrollRegion::Window { top, bottom } => Some((top, bottom)),
+        }
+    }
+
+    /// Set the edge of the alt screen that stays put when the terminal
+    /// height changes while a full screen app is running. Defaults
+    /// to `AltScreenAnchor::Top`.
+    pub fn set_alt_screen_anchor(&mut self, anchor: AltScreenAnchor) {
+        self.state.alt_screen_anchor = anchor;
+    }
+
+    /// Set how non-ASCII input bytes are interpreted. Programs can also
+    /// switch modes themselves with `ESC % @` (Latin-1) and `ESC % G`
+    /// (UTF-8). Defaults to `CharsetMode::Utf8`.
+    pub fn set_charset_mode(&mut self, mode: CharsetMode) {
+        self.state.charset_mode = mode;
+    }
+
+    /// Get the current input charset mode.
+    pub fn charset_mode(&self) -> CharsetMode {
+        self.state.charset_mode
+    }
+
+    /// Interpret nroff style overstrikes, as produced by `man` and friends
+    /// when they are not writing to a terminal. With this on, a char
+    /// printed over the same char after a backspace (`a BS a`) makes it
+    /// bold, and a char printed over an underscore (`_ BS a`) makes it
+    /// underlined. Off by default, in which case the second char simply
+    /// replaces the first.
+    pub fn set_overstrike_mode(&mut self, overstrike_mode: bool) {
+        self.state.overstrike_mode = overstrike_mode;
+    }
+
+    /// Indicates if nroff style overstrikes are interpreted. See
+    /// `set_overstrike_mode`.
+    pub fn overstrike_mode(&self) -> bool {
+        self.state.overstrike_mode
+    }
+
     /// Get the current number of lines of stored scrollback.
     pub fn scrollback_lines(&self) -> usize {
         self.state.scrollback.scrollback_lines().expect("scrollback screen to have lines")
@@ -91,10 +347,267 @@ impl Term {
         self.state.scrollback.set_scrollback_lines(scrollback_lines);
     }
 
+    /// Get the cap on how many cells a single logical line may span, if
+    /// there is one.
+    pub fn max_line_len(&self) -> Option<usize> {
+        self.state.scrollback.max_line_len()
+    }
+
+    /// Cap how many cells a single logical line (a run of soft wrapped
+    /// rows) may span. Output that would wrap past the cap starts a new
+    /// logical line instead, as if a newline had been sent, so a program
+    /// that never emits one can't make reflow and dumps arbitrarily
+    /// expensive. The cap is rounded down to a whole number of rows, but
+    /// a line always gets at least one row. None (the default) means no
+    /// cap.
+    pub fn set_max_line_len(&mut self, max_line_len: Option<usize>) {
+        self.state.scrollback.set_max_line_len(max_line_len);
+    }
+
+    /// Take the number of columns the application most recently asked
+    /// for via DECCOLM (`CSI ? 3 h` for 132 columns, `CSI ? 3 l` for 80)
+    /// or the xterm window op `CSI 8 ; rows ; cols t`.
+    ///
+    /// We don't resize ourselves in response to DECCOLM since the real
+    /// size is dictated by the pty. Instead the embedder can decide
+    /// whether to honor the request by resizing the pty and then calling
+    /// `resize`. Returns None if there has been no request since the last
+    /// call.
+    pub fn take_column_request(&mut self) -> Option<usize> {
+        self.state.column_request.take()
+    }
+
+    /// Take the number of lines the application most recently asked for
+    /// via DECSLPP (`CSI n t` with n of at least 24) or the xterm window
+    /// op `CSI 8 ; rows ; cols t`. Just like with `take_column_request`,
+    /// honoring the request is up to the embedder. Returns None if there
+    /// has been no request since the last call.
+    pub fn take_height_request(&mut self) -> Option<usize> {
+        self.state.height_request.take()
+    }
+
+    /// The (foreground, background) colors a renderer should use for the
+    /// cell at the given position of the visible screen. Indexed colors go
+    /// through the palette (including any OSC 4 overrides), default colors
+    /// come from OSC 10 and 11, and the two get swapped for inverse cells.
+    /// Reverse video mode swaps the defaults for the whole screen, so an
+    /// inverse cell in reverse video mode shows the normal defaults again.
+    ///
+    /// Colors set to specs we can't parse (like X11 color names) fall back
+    /// to what they would be without the override. Returns None if the
+    /// position is outside the screen.
+    pub fn resolved_colors(&self, pos: Pos) -> Option<(Rgb, Rgb)> {
+        let attrs = self.state.screen().get_cell(pos)?.attrs();
+        let mut fg = self.state.resolve_color(attrs.fgcolor, true);
+        let mut bg = self.state.resolve_color(attrs.bgcolor, false);
+        if attrs.inverse {
+            std::mem::swap(&mut fg, &mut bg);
+        }
+        Some((fg, bg))
+    }
+
+    /// Indicates if the whole screen is currently in reverse video mode
+    /// (DECSCNM, `CSI ? 5 h`). Cell attributes are stored as written, so
+    /// anything computing effective colors needs to swap the foreground
+    /// and background of every cell when this is set.
+    pub fn reverse_video(&self) -> bool {
+        self.state.reverse_video
+    }
+
+    /// Indicates if newline mode (LNM, `CSI 20 h`) is on. In this mode
+    /// a line feed also does a carriage return, and the Enter key should
+    /// send CR LF rather than just CR.
+    pub fn newline_mode(&self) -> bool {
+        self.state.newline_mode
+    }
+
+    /// The cursor shape most recently set via DECSCUSR (`CSI n SP q`).
+    /// RIS and DECSTR put it back to the default blinking block.
+    pub fn cursor_style(&self) -> CursorStyle {
+        self.state.cursor_style
+    }
+
+    /// Indicates if reverse wraparound (`CSI ? 45 h`) is on, in which case
+    /// a backspace in the first column moves to the end of the row above.
+    pub fn reverse_wraparound(&self) -> bool {
+        self.state.reverse_wraparound
+    }
+
+    /// Indicates if the cursor blink mode (`CSI ? 12 h`) is on. This is
+    /// tracked separately from the blinking part of `cursor_style`, and
+    /// xterm blinks the cursor if either one asks for it.
+    pub fn cursor_blink_mode(&self) -> bool {
+        self.state.cursor_blink
+    }
+
+    /// Indicates if bracketed paste mode (`CSI ? 2004 h`) is on, in which
+    /// case pasted text should be wrapped in `CSI 200 ~` and `CSI 201 ~`
+    /// before it gets sent to the program.
+    pub fn paste_mode(&self) -> bool {
+        self.state.in_paste_mode
+    }
+
+    /// Indicates if insert mode (IRM, `CSI 4 h`) is on. In this mode
+    /// printed chars push the rest of the line right rather than
+    /// overwriting it.
+    pub fn insert_mode(&self) -> bool {
+        self.state.insert_mode
+    }
+
+    /// The xterm modifyOtherKeys level set via `CSI > 4 ; n m`, or 0
+    /// if it is disabled.
+    pub fn modify_other_keys(&self) -> u16 {
+        self.state.modify_other_keys
+    }
+
+    /// The shell integration marks (`OSC 133`) that have been seen on the
+    /// main screen, oldest first, along with the line each was on. Use
+    /// `line_id_at` to find which visible row a mark is on.
+    pub fn prompt_marks(&self) -> &[(LineId, PromptMark)] {
+        &self.state.prompt_marks
+    }
+
+    /// The id of the line at the given row of the main screen, or None
+    /// if nothing has been written that far down yet.
+    pub fn line_id_at(&self, row: usize) -> Option<LineId> {
+        self.state.scrollback.visible_line_id(row)
+    }
+
+    /// The value of a user variable set via iTerm2's
+    /// `OSC 1337 ; SetUserVar=name=value`. The value is returned just as
+    /// it was sent, which by convention is base64 encoded.
+    pub fn user_var(&self, name: &[u8]) -> Option<&[u8]> {
+        self.state.user_vars.get(name).map(|v| v.as_slice())
+    }
+
+    /// The working directory, as set by `OSC 7` or
+    /// `OSC 1337 ; CurrentDir=`.
+    pub fn working_dir(&self) -> Option<&[u8]> {
+        self.state.working_dir.as_ref().map(|d| d.dir.as_slice())
+    }
+
+    /// The host the working directory is on, as given by the host part
+    /// of an `OSC 7 ; file://host/path` URI. None if no working dir has
+    /// been set or it came without a host.
+    pub fn working_dir_host(&self) -> Option<&[u8]> {
+        self.state.working_dir.as_ref().map(|d| d.host.as_slice()).filter(|h| !h.is_empty())
+    }
+
+    /// Indicates if the working dir or its host has changed since the
+    /// last call, clearing the flag. Setting the same dir again does not
+    /// count as a change.
+    pub fn take_working_dir_changed(&mut self) -> bool {
+        std::mem::take(&mut self.state.working_dir_changed)
+    }
+
+    /// The terminal title, as set by `OSC 0` or `OSC 2`.
+    pub fn title(&self) -> Option<&[u8]> {
+        self.state.title.as_deref()
+    }
+
+    /// The terminal icon name, as set by `OSC 0` or `OSC 1`.
+    pub fn icon_name(&self) -> Option<&[u8]> {
+        self.state.icon_name.as_deref()
+    }
+
+    /// Indicates if the title or icon name has changed since the last
+    /// call, clearing the flag. This is cheaper than comparing `title`
+    /// against a saved copy every frame.
+    pub fn take_title_changed(&mut self) -> bool {
+        std::mem::take(&mut self.state.title_changed)
+    }
+
+    /// Take any replies to queries (such as Primary DA) that have been
+    /// generated since the last call. If no real terminal is attached to
+    /// answer queries, the embedder should write these back to the pty
+    /// so the program that asked gets an answer.
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.state.responses)
+    }
+
     /// Process the given chunk of input. This should be the data read off
     /// a pty running a shell.
-    pub fn process(&mut self, buf: &[u8]) {
-        self.parser.advance(&mut self.state, buf);
+    ///
+    /// The returned summary says what happened along the way that an
+    /// event loop might want to act on, so it does not have to poll for
+    /// each of them after every chunk.
+    pub fn process(&mut self, buf: &[u8]) -> ProcessSummary {
+        self.state.summary = ProcessSummary::default();
+
+        // Feed the parser the input in runs, leaving out the tail of any
+        // oversized OSC. The parser still dispatches the part it already
+        // buffered once the OSC terminates, so we flag it to be dropped.
+        let mut start = 0;
+        for (i, byte) in buf.iter().enumerate() {
+            if !self.osc_limiter.admit(*byte) {
+                self.advance(&buf[start..i]);
+                self.state.drop_osc = true;
+                start = i + 1;
+            }
+        }
+        self.advance(&buf[start..]);
+        self.state.flush_ascii_run();
+
+        self.state.summary.responses_pending = !self.state.responses.is_empty();
+        self.state.summary
+    }
+
+    fn advance(&mut self, mut buf: &[u8]) {
+        // The parser stops early whenever the input switches charset
+        // modes so that we can pick up the rest of the buffer in the
+        // new mode.
+        while !buf.is_empty() {
+            let n = match self.state.charset_mode {
+                CharsetMode::Utf8 => self.parser.advance_until_terminated(&mut self.state, buf),
+                CharsetMode::Latin1 => self.advance_latin1(buf),
+            };
+            self.state.charset_mode_switched = false;
+            buf = &buf[n..];
+        }
+    }
+
+    /// Feed Latin-1 input to the parser by transcoding it to UTF-8 a byte
+    /// at a time. Returns the number of bytes consumed, which will be less
+    /// than the whole buffer if the input switched charset modes.
+    fn advance_latin1(&mut self, buf: &[u8]) -> usize {
+        let mut utf8_buf = [0u8; 4];
+        for (i, byte) in buf.iter().enumerate() {
+            match byte {
+                0x00..=0x7f => self.parser.advance(&mut self.state, &[*byte]),
+                // C1 controls have no printable representation.
+                0x80..=0x9f => debug!("dropping C1 control byte {byte} in latin-1 mode"),
+                _ => {
+                    let utf8 = char::from(*byte).encode_utf8(&mut utf8_buf);
+                    self.parser.advance(&mut self.state, utf8.as_bytes());
+                }
+            }
+            if self.state.charset_mode_switched {
+                return i + 1;
+            }
+        }
+
+        buf.len()
+    }
+
+    /// Write the given text at the cursor with the given attributes,
+    /// advancing the cursor and wrapping just as printed output would.
+    /// This bypasses the parser entirely, so any control chars in `text`
+    /// are dropped rather than interpreted, and the cursor attrs set by
+    /// SGR are left untouched. This is handy for injecting content like
+    /// status lines without having to encode escape sequences.
+    pub fn write_styled(&mut self, text: &str, attrs: Attrs) {
+        let attrs = self.state.attrs_interner.intern(&attrs);
+        let screen = self.state.screen_mut();
+        screen.snap_to_bottom();
+        for c in text.chars() {
+            if !matches!(unicode_width::UnicodeWidthChar::width(c), Some(w) if w > 0) {
+                debug!("write_styled: dropping zero width or control char {c:?}");
+                continue;
+            }
+            if let Err(e) = screen.write_at_cursor(Cell::with_shared_attrs(c, attrs.clone())) {
+                warn!("write_styled: writing char at cursor: {e:?}");
+            }
+        }
     }
 
     /// Get the current contents of the terminal encoded via terminal
@@ -102,16 +615,183 @@ impl Term {
     /// a reset code, so inputing this to any terminal emulator will
     /// reset the emulator to the contents of this Term instance.
     pub fn contents(&self, dump_region: ContentRegion) -> Vec<u8> {
+        let mut buf = Self::reset_prefix();
+        self.state.dump_contents_into(&mut buf, dump_region, &[]);
+
+        buf
+    }
+
+    /// Get the text in the given region of the terminal with all
+    /// formatting stripped. Lines are separated by newlines, except that
+    /// lines which were soft wrapped are joined back together. Trailing
+    /// blanks at the end of each line are trimmed.
+    pub fn text(&self, dump_region: ContentRegion, options: TextOptions) -> String {
+        let mut buf = String::new();
+        self.state.screen().text_into(&mut buf, dump_region, options);
+        buf
+    }
+
+    /// Get the plain text of a single logical line, counting from 0 at the
+    /// top of the scrollback, or None if there is no such line. Soft
+    /// wrapped rows are joined back together and trailing blanks are
+    /// trimmed, just like in `text`, so this gives the same lines as
+    /// `text(ContentRegion::All, ..)` without building the whole thing.
+    pub fn logical_line(&self, idx: usize) -> Option<String> {
+        self.state.screen().logical_line(idx, TextOptions::default())
+    }
+
+    /// Get the bytes needed to bring a terminal showing the screen as of
+    /// the last call up to date, and remember the current screen for next
+    /// time. Only rows that changed get redrawn, each one by moving to its
+    /// start, writing it out and erasing the rest of the line, after which
+    /// the cursor and its attrs are put back. If nothing changed at all
+    /// this is empty.
+    ///
+    /// The first call, and any call after a resize, a screen switch or a
+    /// reset, instead gives a full `contents(ContentRegion::Screen)` dump.
+    /// So does a call where most rows changed (say after a scroll).
+    /// Changes to modes or scrollback don't count as damage, so callers
+    /// that care about those should do a full `contents` dump instead.
+    pub fn flush_damage(&mut self) -> Vec<u8> {
+        let screen = self.state.screen();
+        let frame = Frame {
+            alt: matches!(self.state.screen_mode, ScreenMode::Alt),
+            size: screen.size,
+            lines: screen.visible_lines(),
+            cursor: screen.cursor,
+            pending_wrap: screen.pending_wrap,
+            cursor_attrs: self.state.cursor_attrs.clone(),
+        };
+
+        let dirty_rows: Option<Vec<usize>> = match &self.flushed {
+            Some(prev)
+                if prev.alt == frame.alt
+                    && prev.size == frame.size
+                    && !frame.drops_line_size(prev) =>
+            {
+                Some(
+                    (0..frame.lines.len())
+                        .filter(|row| prev.lines[*row] != frame.lines[*row])
+                        .collect(),
+                )
+            }
+            _ => None,
+        };
+
+        let buf = match dirty_rows {
+            Some(_) if self.flushed.as_ref() == Some(&frame) => vec![],
+            Some(rows) if rows.len() * 100 <= frame.lines.len() * MAX_DIRTY_PERCENT => {
+                // The last flush left the cursor attrs in effect, but the
+                // rows expect to start out with blank attrs.
+                let mut buf = vec![];
+                term::control_codes().clear_attrs.term_input_into(&mut buf);
+                let lines = rows.iter().map(|row| (*row, &frame.lines[*row]));
+                screen.dump_rows_into(&mut buf, lines);
+                for code in term::Attrs::default().transition_to(&frame.cursor_attrs) {
+                    code.term_input_into(&mut buf);
+                }
+                buf
+            }
+            _ => self.contents(ContentRegion::Screen),
+        };
+
+        self.flushed = Some(frame);
+        buf
+    }
+
+    /// Get a copy of the cells in the given region as a rectangular grid.
+    /// The outer vec holds rows from top to bottom and each row holds
+    /// exactly `size.width` cells from left to right, so `grid[row][col]`
+    /// with (0, 0) in the upper left of the region. Blank space, including
+    /// rows that have never been written, is filled with empty cells, and
+    /// wide chars are followed by their wide pad cells just like in
+    /// `cell_at`.
+    pub fn snapshot(&self, dump_region: ContentRegion) -> Vec<Vec<Cell>> {
+        self.state.screen().snapshot(dump_region)
+    }
+
+    /// Get the cells of the visible screen that differ from `prev`, which
+    /// should be an earlier `snapshot(ContentRegion::Screen)`. This is the
+    /// cell level counterpart of `flush_damage`, for UIs that draw cells
+    /// themselves rather than feeding escape codes to a real terminal.
+    /// Entries come in row major order. If `prev` has different
+    /// dimensions than the screen (say after a resize), every cell is
+    /// returned.
+    pub fn cell_diff(&self, prev: &[Vec<Cell>]) -> Vec<(Pos, Cell)> {
+        let size = self.size();
+        let same_size = prev.len() == size.height && prev.iter().all(|row| row.len() == size.width);
+        let mut diff = vec![];
+        for (row, cells) in self.snapshot(ContentRegion::Screen).into_iter().enumerate() {
+            for (col, cell) in cells.into_iter().enumerate() {
+                if !same_size || prev[row][col] != cell {
+                    diff.push((Pos { row, col }, cell));
+                }
+            }
+        }
+        diff
+    }
+
+    /// Get a hash of the glyphs and attributes in the given region, for
+    /// cheaply checking whether anything changed between two frames
+    /// without generating and comparing full dumps. Blank space hashes
+    /// the same whether or not it has ever been written to, and the
+    /// cursor position is not included. The hasher is deterministic, but
+    /// hashes are only meant to be compared within a single build.
+    pub fn content_hash(&self, dump_region: ContentRegion) -> u64 {
+        self.state.screen().content_hash(dump_region)
+    }
+
+    /// Like `contents`, but with the given cells drawn over the visible
+    /// screen. This is meant for things like status bars that should be
+    /// displayed without ever becoming part of the terminal state, so the
+    /// overlay never makes it into the scrollback or later dumps. Overlay
+    /// positions outside of the visible screen are ignored.
+    ///
+    /// Only the rows the overlay lands on get copied, so this costs about
+    /// as much as a `contents` call.
+    pub fn render_with_overlay(
+        &self,
+        dump_region: ContentRegion,
+        overlay: &[(Pos, Cell)],
+    ) -> Vec<u8> {
+        let mut buf = Self::reset_prefix();
+        self.state.dump_contents_into(&mut buf, dump_region, overlay);
+        buf
+    }
+
+    /// The codes that every `contents` dump starts with.
+    fn reset_prefix() -> Vec<u8> {
         let mut buf = vec![];
         term::control_codes().clear_attrs.term_input_into(&mut buf);
         term::ControlCodes::cursor_position(1, 1).term_input_into(&mut buf);
         term::control_codes().clear_screen.term_input_into(&mut buf);
-        self.state.dump_contents_into(&mut buf, dump_region);
-
         buf
     }
 }
 
+/// Notable things that happened during a `Term::process` call.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub struct ProcessSummary {
+    /// There are replies waiting in `Term::take_responses` that should be
+    /// written back to the pty. These may have been queued by an earlier
+    /// call that was never drained.
+    pub responses_pending: bool,
+    /// The input rang the bell (BEL).
+    pub bell: bool,
+    /// The input changed the title or icon name. This does not touch the
+    /// flag behind `Term::take_title_changed`.
+    pub title_changed: bool,
+}
+
+/// Options controlling how `Term::text` renders cells as plain text.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub struct TextOptions {
+    /// If set, concealed cells (SGR 8) show their real contents. Otherwise
+    /// they are replaced with spaces so things like password prompts
+    /// don't leak into logs.
+    pub reveal_concealed: bool,
+}
+
 /// A section of the screen to dump.
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum ContentRegion {
@@ -121,6 +801,10 @@ pub enum ContentRegion {
     Screen,
     /// The bottom N lines, including (N - height) lines of scrollback.
     BottomLines(usize),
+    /// The line the cursor is on, along with up to `above` lines above it
+    /// and `below` lines below it from the visible window. The restored
+    /// cursor lands on its line within the dumped rows.
+    CursorContext { above: usize, below: usize },
 }
 
 impl std::fmt::Display for Term {
@@ -129,6 +813,51 @@ impl std::fmt::Display for Term {
     }
 }
 
+/// A stable identifier for a line in the main screen scrollback. Lines
+/// are numbered in the order they were added, so ids keep pointing at the
+/// same line as it scrolls up. Reflowing the scrollback on resize splits
+/// and joins lines, so ids are only approximate after a width change.
+pub type LineId = u64;
+
+/// A shell integration mark, as sent via `OSC 133`.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum PromptMark {
+    /// The prompt is about to be printed (`OSC 133 ; A`).
+    PromptStart,
+    /// The prompt is done and the user is typing a command (`OSC 133 ; B`).
+    CommandStart,
+    /// The command has been entered and its output follows (`OSC 133 ; C`).
+    OutputStart,
+    /// The command finished, with its exit status if the shell reported
+    /// one (`OSC 133 ; D [; status]`).
+    CommandFinished(Option<i32>),
+}
+
+/// How bytes outside of the ASCII range are interpreted.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum CharsetMode {
+    /// Input is decoded as UTF-8.
+    #[default]
+    Utf8,
+    /// Each byte is its own char, so 0xA0 through 0xFF map directly
+    /// to the Latin-1 code points. Some legacy programs expect this.
+    Latin1,
+}
+
+/// Which edge of the alt screen to keep in place when it changes
+/// height. No matter which anchor is in use, the row the cursor
+/// is on is never dropped when shrinking, so the rows nearest
+/// the cursor survive a resize.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum AltScreenAnchor {
+    /// Rows are added and dropped at the bottom of the screen.
+    #[default]
+    Top,
+    /// Rows are added and dropped at the top of the screen, which
+    /// keeps content like status lines attached to the bottom edge.
+    Bottom,
+}
+
 /// The size of the terminal.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub struct Size {
@@ -136,7 +865,20 @@ pub struct Size {
     pub height: usize,
 }
 
+impl Size {
+    /// The number of cells in a grid of this size.
+    pub fn area(&self) -> usize {
+        self.width * self.height
+    }
+
+    /// Indicates that a grid of this size has no cells at all.
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+}
+
 /// The complete terminal state. An internal implementation detail.
+#[derive(Clone)]
 struct State {
     /// The state for the normal terminal screen.
     scrollback: Screen,
@@ -144,17 +886,34 @@ struct State {
     altscreen: Screen,
     /// The currently active screen mode.
     screen_mode: ScreenMode,
+    /// Which edge of the alt screen to hold in place on resize.
+    alt_screen_anchor: AltScreenAnchor,
     /// The current cursor attrs. These are shared between the scrollback
     /// and alt screens, which is why they are stored here rather than
     /// with the curors themsevles.
     cursor_attrs: term::Attrs,
+    /// Shares the attrs of printed cells so runs with the same style
+    /// don't each carry their own copy.
+    attrs_interner: AttrsInterner,
     /// The terminal title, as set by `OSC 0` and `OSC 2`.
     title: Option<SmallVec<[u8; 8]>>,
     /// The terminal icon name, as set by `OSC 0` and `OSC 1`.
     icon_name: Option<SmallVec<[u8; 8]>>,
+    /// Shell integration marks, oldest first. Marks pointing at lines
+    /// that have dropped out of the scrollback get pruned as new marks
+    /// come in.
+    prompt_marks: Vec<(LineId, PromptMark)>,
+    /// Set whenever the title or icon name changes, and cleared by
+    /// `Term::take_title_changed`.
+    title_changed: bool,
     /// The terminal working directory (some terminal emulators use this
     /// to know what directory to start new shells in).
     working_dir: Option<WorkingDir>,
+    /// Set whenever `working_dir` changes, and cleared by
+    /// `Term::take_working_dir_changed`.
+    working_dir_changed: bool,
+    /// User variables set via iTerm2's `OSC 1337 ; SetUserVar=name=value`.
+    user_vars: BTreeMap<Vec<u8>, Vec<u8>>,
     /// A table mapping color index to a particular color spec.
     /// This is set by OSC 4. We use a tree for deterministic output
     /// to make testing easier. A hash would work just as well.
@@ -162,25 +921,125 @@ struct State {
     /// Color overrides for things like foreground and background.
     /// These slots extend from OSC 10 to OSC 19.
     functional_colors: [Option<Vec<u8>>; 10],
+    /// The VT conformance level set by DECSCL (`CSI level ; c1 " p`),
+    /// from 61 (VT100) up to 65 (VT500).
+    conformance_level: u16,
+    /// Indicates if responses should use 8-bit C1 controls rather than
+    /// ESC prefixed 7-bit ones. Set along with the conformance level, or
+    /// on its own by S7C1T and S8C1T.
+    eight_bit_controls: bool,
+    /// Tracks if newly written cells are protected from selective erase.
+    /// Controlled via DECSCA (`CSI n " q`).
+    protect_chars: bool,
+    /// Zero width chars that showed up with no cell before them to join,
+    /// say a combining accent at the very start of a line. They get
+    /// attached to the next char printed.
+    pending_marks: Vec<char>,
+    /// The last char written to its own cell, which REP repeats.
+    last_printed: Option<char>,
+    /// A run of printable ASCII that has been printed but not yet written
+    /// to the screen. Most output is plain text, so rather than writing it
+    /// a cell at a time we queue it up and write it a row at a time once
+    /// something other than more plain text comes along.
+    ascii_run: Vec<u8>,
     /// Tracks if the cursor is currently hidden. Controlled
     /// via the `CSI ? 25 {h,l}` codes.
     cursor_hidden: bool,
-    /// Tracks application keypad mode state. Controlled via
-    /// `CSI ? 1 {h,l}`.
-    application_keypad_mode_enabled: bool,
+    /// The cursor shape as set by DECSCUSR (`CSI n SP q`).
+    cursor_style: term::CursorStyle,
+    /// The cursor blink mode, controlled via `CSI ? 12 {h,l}`.
+    cursor_blink: bool,
+    /// Reverse wraparound mode, controlled via `CSI ? 45 {h,l}`. When
+    /// set a backspace in the first column moves to the last column of
+    /// the row above.
+    reverse_wraparound: bool,
+    /// DECLRMM (`CSI ? 69 h`), which lets DECSLRM set left and right
+    /// margins. The margins themselves live on each screen. They only
+    /// confine the column editing ops (ICH, DCH, DECIC, DECDC, SL, SR,
+    /// DECBI and DECFI), printing and cursor movement ignore them.
+    lr_margin_mode: bool,
+    /// What has happened so far in the current `Term::process` call.
+    summary: ProcessSummary,
+    /// Set when the current OSC got too long, so its truncated payload
+    /// should be dropped rather than dispatched.
+    drop_osc: bool,
+    /// The setting being requested by an in progress DECRQSS, collected
+    /// as the DCS data comes in.
+    status_string_request: Option<Vec<u8>>,
+    /// Tracks application cursor keys mode (DECCKM), which picks how
+    /// arrow keys get encoded. Controlled via `CSI ? 1 {h,l}`, which the
+    /// control codes call application keypad mode.
+    app_cursor: bool,
+    /// Tracks application keypad mode (DECKPAM), which makes the numeric
+    /// keypad send SS3 codes rather than plain chars. Controlled via
+    /// `ESC =` and `ESC >`.
+    app_keypad: bool,
     /// Tracks paste mode. Controlled via `CSI ? 2004 {h,l}`.
     in_paste_mode: bool,
+    /// Tracks newline mode (LNM), in which a line feed also returns the
+    /// carriage. Controlled via `CSI 20 {h,l}`.
+    newline_mode: bool,
+    /// Tracks insert mode (IRM), in which printed chars push the rest of
+    /// the line right rather than overwriting it. Controlled via
+    /// `CSI 4 {h,l}`.
+    insert_mode: bool,
+    /// Tracks whole screen reverse video mode (DECSCNM). Controlled
+    /// via `CSI ? 5 {h,l}`.
+    reverse_video: bool,
+    /// The xterm modifyOtherKeys level. Controlled via `CSI > 4 ; n m`
+    /// and reset by `CSI > m`.
+    modify_other_keys: u16,
+    /// How non-ASCII input bytes get interpreted. Controlled via
+    /// `ESC % @` and `ESC % G`.
+    charset_mode: CharsetMode,
+    /// Set when the input switches charset modes so that the parser
+    /// bails out and lets `Term::process` pick up the rest of the input
+    /// in the new mode.
+    charset_mode_switched: bool,
+    /// Indicates if a char printed over another after a backspace should
+    /// embolden or underline it, rather than replace it.
+    overstrike_mode: bool,
+    /// Set by a backspace in overstrike mode, so the next printed char
+    /// knows it is striking over the cell under the cursor.
+    overstriking: bool,
+    /// The charsets designated into G0 and G1 via `ESC ( <c>` and
+    /// `ESC ) <c>`.
+    charsets: [term::Charset; 2],
+    /// Which of `charsets` is currently shifted into GL and used to
+    /// translate printed characters.
+    gl_charset: usize,
+    /// The column count most recently requested via DECCOLM
+    /// (`CSI ? 3 {h,l}`) that has not yet been taken by the embedder.
+    column_request: Option<usize>,
+    /// The line count most recently requested via DECSLPP or XTWINOPS
+    /// that has not yet been taken by the embedder.
+    height_request: Option<usize>,
+    /// Replies to queries that are waiting to be taken by the embedder
+    /// via `Term::take_responses`.
+    responses: Vec<u8>,
     /// Tab stop columns. By default, these are spaced 8 cols apart
     /// starting at col 9, but they can be directly manipulated by certain
     /// control codes as well.
     tabstops: BitVec,
 }
 
+#[derive(Clone, PartialEq)]
 struct WorkingDir {
     host: SmallVec<[u8; 8]>,
     dir: SmallVec<[u8; 8]>,
 }
 
+impl WorkingDir {
+    /// Split a `file://host/path` URI into its host and path. The path
+    /// is kept as sent, percent escapes and all.
+    fn from_file_uri(uri: &[u8]) -> Option<Self> {
+        let rest = uri.strip_prefix(b"file://")?;
+        let split = rest.iter().position(|b| *b == b'/').unwrap_or(rest.len());
+        let (host, dir) = rest.split_at(split);
+        Some(WorkingDir { host: SmallVec::from_slice(host), dir: SmallVec::from_slice(dir) })
+    }
+}
+
 impl std::fmt::Display for State {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.screen_mode {
@@ -204,15 +1063,48 @@ impl State {
             scrollback: Screen::scrollback(scrollback_lines, size),
             altscreen: Screen::alt(size),
             screen_mode: ScreenMode::Scrollback,
+            alt_screen_anchor: AltScreenAnchor::default(),
             cursor_attrs: term::Attrs::default(),
+            attrs_interner: AttrsInterner::default(),
             title: None,
             icon_name: None,
+            prompt_marks: vec![],
+            title_changed: false,
             working_dir: None,
+            working_dir_changed: false,
             palette_overrides: BTreeMap::new(),
+            user_vars: BTreeMap::new(),
             functional_colors: [NONE_VEC; 10],
+            conformance_level: DEFAULT_CONFORMANCE_LEVEL,
+            eight_bit_controls: false,
+            protect_chars: false,
+            pending_marks: vec![],
+            last_printed: None,
+            ascii_run: vec![],
             cursor_hidden: false,
-            application_keypad_mode_enabled: false,
+            cursor_style: term::CursorStyle::default(),
+            cursor_blink: false,
+            reverse_wraparound: false,
+            lr_margin_mode: false,
+            summary: ProcessSummary::default(),
+            drop_osc: false,
+            status_string_request: None,
+            app_cursor: false,
+            app_keypad: false,
             in_paste_mode: false,
+            newline_mode: false,
+            insert_mode: false,
+            reverse_video: false,
+            modify_other_keys: 0,
+            charset_mode: CharsetMode::default(),
+            charset_mode_switched: false,
+            overstrike_mode: false,
+            overstriking: false,
+            charsets: [term::Charset::default(); 2],
+            gl_charset: 0,
+            column_request: None,
+            height_request: None,
+            responses: vec![],
             tabstops: bitvec![0; size.width],
         };
         st.fill_tabstops(0, size.width);
@@ -233,6 +1125,154 @@ impl State {
         }
     }
 
+    /// Reset to the initial state, as if the terminal had just been
+    /// created. This drops the alt screen, so we always come out of a
+    /// reset on the main screen. Embedder configuration like the
+    /// scrollback size, alt screen anchor and charset mode survives, as
+    /// do any replies the embedder has not yet taken.
+    fn reset(&mut self) {
+        let size = self.screen().size;
+        let scrollback_lines = self.scrollback.scrollback_lines().unwrap_or(size.height);
+        let alt_screen_anchor = self.alt_screen_anchor;
+        let charset_mode = self.charset_mode;
+        let overstrike_mode = self.overstrike_mode;
+        let max_line_len = self.scrollback.max_line_len();
+        let responses = std::mem::take(&mut self.responses);
+
+        *self = State::new(scrollback_lines, size);
+        self.scrollback.set_max_line_len(max_line_len);
+        self.alt_screen_anchor = alt_screen_anchor;
+        self.charset_mode = charset_mode;
+        self.overstrike_mode = overstrike_mode;
+        self.responses = responses;
+    }
+
+    /// Handle DECSTR (`CSI ! p`). Unlike a full reset, this leaves the
+    /// screen contents and cursor position alone and only puts modes
+    /// back to their defaults.
+    fn soft_reset(&mut self) {
+        self.tabstops.fill(false);
+        let width = self.screen().size.width;
+        self.fill_tabstops(0, width);
+        self.cursor_hidden = false;
+        self.app_cursor = false;
+        self.app_keypad = false;
+        self.insert_mode = false;
+        self.cursor_style = term::CursorStyle::default();
+
+        warn!("DECSTR only partially handled");
+    }
+
+    /// Write a char into a fresh cell at the cursor, pushing the rest of
+    /// the line right first in insert mode. This is also what REP uses to
+    /// repeat the char.
+    fn write_char(&mut self, c: char) {
+        let overstruck =
+            if std::mem::take(&mut self.overstriking) { self.overstrike_attrs(c) } else { None };
+        let attrs = self.attrs_interner.intern(overstruck.as_ref().unwrap_or(&self.cursor_attrs));
+        let mut cell = Cell::with_shared_attrs(c, attrs);
+        for mark in self.pending_marks.drain(..) {
+            cell.add_char(mark);
+        }
+        cell.set_protected(self.protect_chars);
+        self.last_printed = Some(c);
+
+        let insert_mode = self.insert_mode;
+        let screen = self.screen_mut();
+        screen.snap_to_bottom();
+        // With a wrap pending the char lands at the start of the next
+        // line, so there is nothing on this line to push over. The blanks
+        // get written over right away, so their attrs don't matter.
+        if insert_mode && !screen.pending_wrap {
+            screen.insert_chars(&Attrs::default(), std::cmp::max(cell.width() as usize, 1));
+        }
+        if let Err(e) = screen.write_at_cursor(cell) {
+            warn!("writing char at cursor: {e:?}");
+        }
+    }
+
+    /// Write `c` out `n` times, as REP does. Past enough repeats to fill
+    /// the screen and all of the scrollback the earlier ones are all
+    /// overwritten, so we only write that many, plus enough to land the
+    /// cursor in the same column.
+    fn repeat_char(&mut self, c: char, n: usize) {
+        let screen = self.screen();
+        let size = screen.size;
+        let limit = size.width * (size.height + screen.scrollback_lines().unwrap_or(0));
+        let n = if n > limit { limit + (n - limit) % std::cmp::max(size.width, 1) } else { n };
+
+        if self.can_start_ascii_run(c) {
+            self.ascii_run.extend(std::iter::repeat(c as u8).take(n));
+            self.flush_ascii_run();
+        } else {
+            (0..n).for_each(|_| self.write_char(c));
+        }
+    }
+
+    /// Indicates if `c` can be queued up in `ascii_run` rather than being
+    /// written right away. Anything that cares about the cell the char
+    /// lands on, or the one before it, has to take the slow path.
+    fn can_start_ascii_run(&self, c: char) -> bool {
+        (c == ' ' || c.is_ascii_graphic())
+            && !self.insert_mode
+            && !self.overstriking
+            && self.pending_marks.is_empty()
+    }
+
+    /// Write out any queued up plain text. This must happen before
+    /// anything else touches the screen.
+    fn flush_ascii_run(&mut self) {
+        let Some(last) = self.ascii_run.last() else {
+            return;
+        };
+        self.last_printed = Some(char::from(*last));
+
+        let run = std::mem::take(&mut self.ascii_run);
+        let attrs = self.attrs_interner.intern(&self.cursor_attrs);
+        let protected = self.protect_chars;
+        let screen = self.screen_mut();
+        screen.snap_to_bottom();
+        if let Err(e) = screen.write_ascii_run(&run, attrs, protected) {
+            warn!("writing ascii run at cursor: {e:?}");
+        }
+
+        // Hang on to the allocation for the next run.
+        self.ascii_run = run;
+        self.ascii_run.clear();
+    }
+
+    /// The attrs for `c` struck over the cell under the cursor, or None
+    /// if that is not an overstrike we know about. Striking a char over
+    /// itself is bold and striking it over an underscore is underline.
+    /// These build on the attrs already in the cell, so `_ BS a BS a`
+    /// gives a bold and underlined `a`.
+    fn overstrike_attrs(&self, c: char) -> Option<Attrs> {
+        let screen = self.screen();
+        let cell = screen.get_cell(screen.cursor)?;
+        let mut attrs = cell.attrs().clone();
+        if cell.chars() == [c] {
+            attrs.font_weight = Some(term::FontWeight::Bold);
+        } else if cell.chars() == ['_'] {
+            attrs.underline = Some(term::UnderlineStyle::Single);
+        } else {
+            return None;
+        }
+        Some(attrs)
+    }
+
+    /// Handle DECSCL. The second param picks 7-bit controls when it is 1
+    /// and 8-bit ones otherwise, except that a VT100 has no 8-bit controls
+    /// at all. Like xterm, this also does a soft reset.
+    fn set_conformance_level(&mut self, level: u16, c1: Option<&[u16]>) {
+        if !(61..=65).contains(&level) {
+            warn!("ignoring DECSCL with unknown level {level}");
+            return;
+        }
+        self.conformance_level = level;
+        self.eight_bit_controls = level > 61 && !matches!(c1, Some([1]));
+        self.soft_reset();
+    }
+
     fn resize(&mut self, size: Size) {
         let orig_len = self.tabstops.len();
         self.tabstops.resize(size.width, false);
@@ -240,8 +1280,14 @@ impl State {
             self.fill_tabstops(orig_len, size.width);
         }
 
-        self.scrollback.resize(size);
-        self.altscreen.resize(size);
+        self.scrollback.resize(size, self.alt_screen_anchor);
+        self.altscreen.resize(size, self.alt_screen_anchor);
+    }
+
+    fn set_viewport_height(&mut self, height: usize) {
+        let size = Size { height, ..self.screen().size };
+        self.scrollback.resize_window(size, self.alt_screen_anchor);
+        self.altscreen.resize_window(size, self.alt_screen_anchor);
     }
 
     /// Fill in the default tabstops within the given range.
@@ -301,12 +1347,26 @@ impl State {
         }
     }
 
-    fn dump_contents_into(&self, buf: &mut Vec<u8>, dump_region: ContentRegion) {
+    fn dump_contents_into(
+        &self,
+        buf: &mut Vec<u8>,
+        dump_region: ContentRegion,
+        overlay: &[(Pos, Cell)],
+    ) {
+        // DECSCL implies a soft reset, so it has to come before any of the
+        // modes it would clobber.
+        if self.conformance_level != DEFAULT_CONFORMANCE_LEVEL || self.eight_bit_controls {
+            ControlCodes::set_conformance_level(self.conformance_level, self.eight_bit_controls)
+                .term_input_into(buf);
+        }
         self.dump_tabstops(buf);
+        if self.lr_margin_mode {
+            term::control_codes().enable_lr_margin_mode.term_input_into(buf);
+        }
 
         match self.screen_mode {
-            ScreenMode::Scrollback => self.scrollback.dump_contents_into(buf, dump_region),
-            ScreenMode::Alt => self.altscreen.dump_contents_into(buf, dump_region),
+            ScreenMode::Scrollback => self.scrollback.dump_contents_into(buf, dump_region, overlay),
+            ScreenMode::Alt => self.altscreen.dump_contents_into(buf, dump_region, overlay),
         }
 
         let controls = term::control_codes();
@@ -344,6 +1404,9 @@ impl State {
             ControlCodes::set_working_dir(working_dir.host.clone(), working_dir.dir.clone())
                 .term_input_into(buf);
         }
+        for (name, value) in self.user_vars.iter() {
+            ControlCodes::set_user_var(name, value).term_input_into(buf);
+        }
 
         if !self.palette_overrides.is_empty() {
             ControlCodes::set_color_indices(
@@ -354,15 +1417,56 @@ impl State {
             .term_input_into(buf);
         }
 
+        if self.protect_chars {
+            ControlCodes::select_character_protection(true).term_input_into(buf);
+        }
         if self.cursor_hidden {
             controls.hide_cursor.term_input_into(buf);
         }
-        if self.application_keypad_mode_enabled {
-            controls.enable_application_keypad_mode.term_input_into(buf);
+        if self.cursor_style != term::CursorStyle::default() {
+            ControlCodes::set_cursor_style(self.cursor_style.param()).term_input_into(buf);
+        }
+        if self.cursor_blink {
+            controls.enable_cursor_blink.term_input_into(buf);
+        }
+        if self.reverse_wraparound {
+            controls.enable_reverse_wraparound.term_input_into(buf);
+        }
+        if self.app_cursor {
+            controls.enable_application_cursor_keys.term_input_into(buf);
+        }
+        if self.app_keypad {
+            controls.keypad_application_mode.term_input_into(buf);
         }
         if self.in_paste_mode {
             controls.enable_paste_mode.term_input_into(buf);
         }
+        if self.newline_mode {
+            controls.enable_newline_mode.term_input_into(buf);
+        }
+        if self.insert_mode {
+            controls.enable_insert_mode.term_input_into(buf);
+        }
+        if self.reverse_video {
+            controls.enable_reverse_video.term_input_into(buf);
+        }
+        if self.modify_other_keys != 0 {
+            ControlCodes::set_modify_other_keys(self.modify_other_keys).term_input_into(buf);
+        }
+        for flags in self.screen().keyboard_flags_stack() {
+            ControlCodes::push_keyboard_flags(*flags).term_input_into(buf);
+        }
+        for (slot, charset) in self.charsets.iter().enumerate() {
+            if *charset != term::Charset::default() {
+                ControlCodes::designate_charset(slot, *charset).term_input_into(buf);
+            }
+        }
+        if self.charset_mode == CharsetMode::Latin1 {
+            controls.select_latin1.term_input_into(buf);
+        }
+        if self.gl_charset == 1 {
+            controls.shift_out.term_input_into(buf);
+        }
 
         // Generate fused functional color commands from any runs in the
         // functional colors table.
@@ -389,13 +1493,231 @@ impl State {
         }
     }
 
+    /// Handle a DECCOLM switch to the given number of columns. Real
+    /// terminals clear the screen, reset the scroll region and home the
+    /// cursor on a column mode switch, so we do the same even though we
+    /// leave the actual resize up to the embedder.
+    fn switch_column_mode(&mut self, cols: usize) {
+        self.column_request = Some(cols);
+
+        let screen = self.screen_mut();
+        screen.set_scroll_region(term::ScrollRegion::TrackSize);
+        screen.erase(false);
+        screen.cursor = term::Pos { row: 0, col: 0 };
+    }
+
+    /// Reply to a DECRQSS request for the setting named by `request`.
+    fn reply_status_string(&mut self, request: &[u8]) {
+        let mut data = vec![];
+        match request {
+            // DECSTBM
+            b"r" => {
+                let screen = self.screen();
+                let (top, bottom) =
+                    screen.scroll_region(false).as_region(&screen.size).row_bounds();
+                data.extend_from_slice(itoa::Buffer::new().format(top + 1).as_bytes());
+                data.push(b';');
+                data.extend_from_slice(itoa::Buffer::new().format(bottom).as_bytes());
+                data.push(b'r');
+            }
+            // SGR
+            b"m" => {
+                data.extend_from_slice(&self.cursor_attrs.sgr_params());
+                data.push(b'm');
+            }
+            // DECSCUSR
+            b" q" => {
+                let style = self.cursor_style.param();
+                data.extend_from_slice(itoa::Buffer::new().format(style).as_bytes());
+                data.extend_from_slice(b" q");
+            }
+            _ => {
+                warn!("unhandled DECRQSS request {:?}", String::from_utf8_lossy(request));
+                self.respond(&ControlCodes::status_string_reply(false, &[]));
+                return;
+            }
+        }
+        self.respond(&ControlCodes::status_string_reply(true, &data));
+    }
+
+    /// Set the scroll region to rows [top, bottom) of the active screen,
+    /// clamping the bottom to the screen height. Shared by DECSTBM and
+    /// `Term::set_scroll_region`.
+    fn set_scroll_window(&mut self, top: usize, bottom: usize) -> bool {
+        let screen = self.screen_mut();
+        let bottom = std::cmp::min(bottom, screen.size.height);
+        // A scroll region has to span at least two lines, anything
+        // smaller is ignored and the old region stays in effect.
+        if top + 1 >= bottom {
+            return false;
+        }
+        screen.set_scroll_region(term::ScrollRegion::Window { top, bottom });
+        // DECSTBM homes the cursor, which is the top of the new region in
+        // origin mode.
+        screen.set_cursor(term::Pos { row: 1, col: 1 });
+        true
+    }
+
+    fn set_working_dir(&mut self, working_dir: WorkingDir) {
+        if self.working_dir.as_ref() != Some(&working_dir) {
+            self.working_dir = Some(working_dir);
+            self.working_dir_changed = true;
+        }
+    }
+
+    /// Handle the payload of an iTerm2 `OSC 1337` command, which takes
+    /// the form `Command=args`.
+    fn iterm2_command(&mut self, payload: &[u8]) {
+        let (cmd, args) = match payload.iter().position(|b| *b == b'=') {
+            Some(i) => (&payload[..i], &payload[i + 1..]),
+            None => (payload, &[][..]),
+        };
+        match cmd {
+            b"CurrentDir" => {
+                let host = self.working_dir.as_ref().map(|d| d.host.clone()).unwrap_or_default();
+                self.set_working_dir(WorkingDir { host, dir: args.into() });
+            }
+            b"SetUserVar" => match args.iter().position(|b| *b == b'=') {
+                Some(i) => {
+                    self.user_vars.insert(args[..i].to_vec(), args[i + 1..].to_vec());
+                }
+                None => warn!("OSC 1337 SetUserVar with no value"),
+            },
+            b"File" | b"MultipartFile" | b"FilePart" | b"FileEnd" => {
+                debug!("ignoring OSC 1337 file transfer")
+            }
+            _ => debug!("ignoring OSC 1337 command {:?}", String::from_utf8_lossy(cmd)),
+        }
+    }
+
+    /// Drop the main screen's scrollback, along with any prompt marks that
+    /// pointed into it.
+    fn clear_scrollback(&mut self) {
+        self.scrollback.clear_scrollback();
+        self.prune_prompt_marks();
+    }
+
+    /// Forget prompt marks on lines that are no longer stored.
+    fn prune_prompt_marks(&mut self) {
+        if let Some(oldest) = self.scrollback.oldest_line_id() {
+            self.prompt_marks.retain(|(id, _)| *id >= oldest);
+        }
+    }
+
+    /// Record a shell integration mark on the line the cursor is on.
+    fn add_prompt_mark(&mut self, mark: PromptMark) {
+        if !matches!(self.screen_mode, ScreenMode::Scrollback) {
+            debug!("ignoring {mark:?} on the alt screen");
+            return;
+        }
+
+        let row = self.scrollback.cursor.row;
+        let Some(id) = self.scrollback.line_id(row) else {
+            warn!("no line id for row {row} when adding {mark:?}");
+            return;
+        };
+        self.prune_prompt_marks();
+        self.prompt_marks.push((id, mark));
+    }
+
+    fn set_title(&mut self, title: &[u8]) {
+        if self.title.as_deref() != Some(title) {
+            self.title = Some(title.into());
+            self.title_changed = true;
+            self.summary.title_changed = true;
+        }
+    }
+
+    fn set_icon_name(&mut self, icon_name: &[u8]) {
+        if self.icon_name.as_deref() != Some(icon_name) {
+            self.icon_name = Some(icon_name.into());
+            self.title_changed = true;
+            self.summary.title_changed = true;
+        }
+    }
+
+    fn switch_charset_mode(&mut self, mode: CharsetMode) {
+        if self.charset_mode != mode {
+            self.charset_mode = mode;
+            self.charset_mode_switched = true;
+        }
+    }
+
+    /// Save the cursor position and attrs of the current screen, along
+    /// with the origin mode and charset state (DECSC).
+    fn save_cursor(&mut self) {
+        let attrs = self.cursor_attrs.clone();
+        let (charsets, gl_charset) = (self.charsets, self.gl_charset);
+        let screen = self.screen_mut();
+        screen.saved_cursor = SavedCursor {
+            pos: screen.cursor,
+            attrs,
+            origin_mode: screen.origin_mode(),
+            charsets,
+            gl_charset,
+        };
+    }
+
+    /// Restore everything `save_cursor` saved on the current screen
+    /// (DECRC).
+    fn restore_cursor(&mut self) {
+        let screen = self.screen_mut();
+        let saved = screen.saved_cursor.clone();
+        screen.cursor = saved.pos;
+        screen.pending_wrap = false;
+        screen.set_origin_mode(saved.origin_mode);
+        self.cursor_attrs = saved.attrs;
+        self.charsets = saved.charsets;
+        self.gl_charset = saved.gl_charset;
+    }
+
+    /// Queue a reply to a query, in whichever control representation
+    /// (7 or 8-bit) the program asked for.
+    fn respond(&mut self, reply: &term::ControlCode) {
+        if self.eight_bit_controls {
+            reply.term_input_8bit_into(&mut self.responses);
+        } else {
+            reply.term_input_into(&mut self.responses);
+        }
+    }
+
+    /// Queue up our answer to a Primary DA query.
+    fn reply_primary_device_attributes(&mut self) {
+        self.respond(&term::control_codes().primary_device_attributes_reply);
+    }
+
+    /// Turn a cell color into the rgb value that gets displayed for it,
+    /// taking the palette, default colors and reverse video into account.
+    fn resolve_color(&self, color: term::Color, fg: bool) -> Rgb {
+        match color {
+            term::Color::Rgb(r, g, b) => Rgb { r, g, b },
+            term::Color::Idx(i) => self
+                .palette_overrides
+                .get(&(i as usize))
+                .and_then(|spec| Rgb::parse_spec(spec))
+                .unwrap_or_else(|| Rgb::xterm_palette(i)),
+            term::Color::Default => {
+                // OSC 10 and 11 are the first two functional colors.
+                let (idx, fallback) = if fg != self.reverse_video {
+                    (0, Rgb::DEFAULT_FG)
+                } else {
+                    (1, Rgb::DEFAULT_BG)
+                };
+                self.functional_colors[idx]
+                    .as_ref()
+                    .and_then(|spec| Rgb::parse_spec(spec))
+                    .unwrap_or(fallback)
+            }
+        }
+    }
+
     /// Set a run within the functional colors table starting at the given
     /// index. This implements OSC 10 through OSC 19.
-    fn set_functional_color<'a, I>(&mut self, mut idx: usize, mut params_iter: I)
+    fn set_functional_color<'a, I>(&mut self, mut idx: usize, params_iter: I)
     where
         I: Iterator<Item = &'a &'a [u8]>,
     {
-        while let Some(color_spec) = params_iter.next() {
+        for color_spec in params_iter {
             if idx >= self.functional_colors.len() {
                 return;
             }
@@ -409,7 +1731,10 @@ impl State {
     }
 }
 
+const ZERO_WIDTH_JOINER: char = '\u{200d}';
+
 /// Indicates which screen mode is active.
+#[derive(Clone)]
 enum ScreenMode {
     Scrollback,
     Alt,
@@ -418,34 +1743,66 @@ enum ScreenMode {
 impl vte::Perform for State {
     fn print(&mut self, c: char) {
         trace!("print: {}", c);
-        let attrs = self.cursor_attrs.clone();
-        let screen = self.screen_mut();
-        screen.snap_to_bottom();
-        if let Err(e) = screen.write_at_cursor(Cell::new(c, attrs)) {
-            warn!("writing char at cursor: {e:?}");
+        let c = self.charsets[self.gl_charset].translate(c);
+
+        // Plain ASCII right after more plain ASCII can't join onto
+        // anything, so it just gets queued up.
+        if !self.ascii_run.is_empty() && (c == ' ' || c.is_ascii_graphic()) {
+            self.ascii_run.push(c as u8);
+            return;
+        }
+        self.flush_ascii_run();
+
+        // Zero width chars (combining marks and such) modify the char
+        // before them, and a ZWJ glues the next char onto the cluster too,
+        // so these join the previous cell rather than getting their own.
+        let joins_prev = match unicode_width::UnicodeWidthChar::width(c) {
+            Some(0) => true,
+            Some(_) => self
+                .screen_mut()
+                .prev_cell_mut()
+                .is_some_and(|cell| cell.chars().last() == Some(&ZERO_WIDTH_JOINER)),
+            None => {
+                debug!("print: dropping control char {c:?}");
+                return;
+            }
+        };
+        if joins_prev {
+            if let Some(cell) = self.screen_mut().prev_cell_mut() {
+                cell.add_char(c);
+            } else if self.pending_marks.len() < MAX_PENDING_MARKS {
+                self.pending_marks.push(c);
+            } else {
+                debug!("print: dropping {c:?} with no char to join");
+            }
+            return;
+        }
+
+        if self.can_start_ascii_run(c) {
+            self.ascii_run.push(c as u8);
+        } else {
+            self.write_char(c);
         }
     }
 
     fn execute(&mut self, byte: u8) {
         trace!("execute: byte {}", byte);
+        self.flush_ascii_run();
+        self.overstriking = false;
         match byte {
             b'\n' => {
+                let newline_mode = self.newline_mode;
                 let screen = self.screen_mut();
-                let (scroll_top, scroll_bottom) =
-                    screen.scroll_region(false).as_region(&screen.size).row_bounds();
-                let within_scroll =
-                    scroll_top <= screen.cursor.row && screen.cursor.row < scroll_bottom;
-                screen.cursor.row += 1;
-                if within_scroll {
-                    if screen.cursor.row >= scroll_bottom {
-                        screen.scroll_down(1);
-                        screen.cursor.row -= 1;
-                    }
-                } else {
-                    screen.clamp();
+                if newline_mode {
+                    screen.cursor.col = 0;
                 }
+                screen.index();
+            }
+            b'\r' => {
+                let screen = self.screen_mut();
+                screen.cursor.col = 0;
+                screen.pending_wrap = false;
             }
-            b'\r' => self.screen_mut().cursor.col = 0,
             b'\t' => {
                 let mut col = self.screen().cursor.col;
                 col += 1;
@@ -459,11 +1816,26 @@ impl vte::Perform for State {
             }
             b'\x08' => {
                 // backspace
+                let reverse_wraparound = self.reverse_wraparound;
                 let screen = self.screen_mut();
-                screen.cursor.col = screen.cursor.col.saturating_sub(1);
+                if reverse_wraparound && screen.cursor.col == 0 {
+                    if screen.cursor.row > 0 {
+                        screen.cursor.row -= 1;
+                        screen.cursor.col = screen.size.width.saturating_sub(1);
+                    }
+                } else {
+                    screen.cursor.col = screen.cursor.col.saturating_sub(1);
+                }
+                screen.pending_wrap = false;
+                self.overstriking = self.overstrike_mode;
             }
-            // bell, ignore
-            b'\x07' => {}
+            // SO (Shift Out), invoke G1 into GL
+            b'\x0e' => self.gl_charset = 1,
+            // SI (Shift In), invoke G0 into GL
+            b'\x0f' => self.gl_charset = 0,
+            // BEL, which we have nothing to do with beyond telling the
+            // embedder about it.
+            b'\x07' => self.summary.bell = true,
             _ => {
                 warn!("execute: unhandled byte {}", byte);
             }
@@ -471,18 +1843,34 @@ impl vte::Perform for State {
     }
 
     fn hook(&mut self, _params: &vte::Params, intermediates: &[u8], ignore: bool, action: char) {
-        debug!(
-            "unhandled hook{}: {intermediates:?} {action}",
-            if ignore { " (ignored)" } else { "" }
-        );
+        self.flush_ascii_run();
+        match (intermediates, action) {
+            // DECRQSS (Request Selection or Setting)
+            ([b'$'], 'q') if !ignore => self.status_string_request = Some(vec![]),
+            _ => debug!(
+                "unhandled hook{}: {intermediates:?} {action}",
+                if ignore { " (ignored)" } else { "" }
+            ),
+        }
     }
 
     fn put(&mut self, byte: u8) {
-        trace!("unhandled put: {byte}");
+        self.flush_ascii_run();
+        match &mut self.status_string_request {
+            // No setting name is more than a couple of bytes, so there is
+            // no need to buffer an unbounded amount of junk.
+            Some(request) if request.len() < 8 => request.push(byte),
+            Some(_) => {}
+            None => trace!("unhandled put: {byte}"),
+        }
     }
 
     fn unhook(&mut self) {
-        debug!("unhandled unhook");
+        self.flush_ascii_run();
+        match self.status_string_request.take() {
+            Some(request) => self.reply_status_string(&request),
+            None => debug!("unhandled unhook"),
+        }
     }
 
     // OSC commands are of the form
@@ -496,23 +1884,27 @@ impl vte::Perform for State {
     #[rustfmt::skip]
     fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
         trace!("osc_dispatch: {:?}", params);
+        self.flush_ascii_run();
+        if std::mem::take(&mut self.drop_osc) {
+            return;
+        }
 
         let mut params_iter = params.iter();
         match params_iter.next() {
             // Title manipulation
             Some([b'0']) => if let Some(title) = params_iter.next() {
-                self.title = Some(title.to_vec().into());
-                self.icon_name = Some(title.to_vec().into());
+                self.set_title(title);
+                self.set_icon_name(title);
             } else {
                 warn!("OSC 0 with no title param");
             },
             Some([b'1']) => if let Some(icon_name) = params_iter.next() {
-                self.icon_name = Some(icon_name.to_vec().into());
+                self.set_icon_name(icon_name);
             } else {
                 warn!("OSC 1 with no icon_name param");
             },
             Some([b'2']) => if let Some(title) = params_iter.next() {
-                self.title = Some(title.to_vec().into());
+                self.set_title(title);
             } else {
                 warn!("OSC 2 with no title param");
             },
@@ -536,7 +1928,9 @@ impl vte::Perform for State {
                     Err(e) => warn!("OSC 4: invalid idx '{idx:?}': {e}"),
                 }
             },
-            Some([b'1', b'0', b'4']) => while let Some(idx) = params_iter.next() {
+            // With no indices, OSC 104 resets the whole palette.
+            Some([b'1', b'0', b'4']) if params.len() == 1 => self.palette_overrides.clear(),
+            Some([b'1', b'0', b'4']) => for idx in params_iter {
                 match std::str::from_utf8(idx) {
                     Ok(s) => match s.parse::<usize>() {
                         Ok(i) => {
@@ -548,14 +1942,18 @@ impl vte::Perform for State {
                 }
             },
 
-            // Working dir
-            Some([b'7']) => if let (Some(host), Some(dir)) = (params_iter.next(), params_iter.next()) {
-                self.working_dir = Some(WorkingDir {
-                    host: host.to_vec().into(),
-                    dir: dir.to_vec().into(),
-                });
-            } else {
-                warn!("OSC 7 with fewer than 2 params");
+            // Working dir. Shells send a `file://host/path` URI, while our
+            // own dumps use separate host and dir params.
+            Some([b'7']) => match (params_iter.next(), params_iter.next()) {
+                (Some(host), Some(dir)) => self.set_working_dir(WorkingDir {
+                    host: SmallVec::from_slice(host),
+                    dir: SmallVec::from_slice(dir),
+                }),
+                (Some(uri), None) => match WorkingDir::from_file_uri(uri) {
+                    Some(working_dir) => self.set_working_dir(working_dir),
+                    None => warn!("OSC 7 with a non file:// URI"),
+                },
+                _ => warn!("OSC 7 with no params"),
             },
 
             // Links. Depending on params, OSC 8 both starts and ends links.
@@ -563,10 +1961,7 @@ impl vte::Perform for State {
                 if params.is_empty() && url.is_empty() {
                     self.cursor_attrs.link_target = None;
                 } else {
-                    self.cursor_attrs.link_target = Some(LinkTarget {
-                        params: SmallVec::from_slice(params),
-                        url: SmallVec::from_slice(url),
-                    });
+                    self.cursor_attrs.link_target = Some(LinkTarget::new(params, url));
                 }
             } else {
                 self.cursor_attrs.link_target = None;
@@ -575,11 +1970,32 @@ impl vte::Perform for State {
             // Functional colors (foreground, background and whatnot).
             Some([b'1', x]) if b'0' <= *x && *x <= b'9' =>
                 self.set_functional_color((*x - b'0') as usize, params_iter),
+            Some([b'1', b'1', x]) if b'0' <= *x && *x <= b'9' =>
+                self.functional_colors[(*x - b'0') as usize] = None,
+
+            // iTerm2 extensions
+            Some([b'1', b'3', b'3', b'7']) => match params_iter.next() {
+                Some(payload) => self.iterm2_command(payload),
+                None => warn!("OSC 1337 with no payload"),
+            },
 
             Some([b'5', b'2']) => debug!("ignoring OSC 52 (clipboard)"),
             Some([b'9']) => debug!("ignoring OSC 9 (desktop notification)"),
             Some([b'7', b'7', b'7']) => debug!("ignoring OSC 777"),
-            Some([b'1', b'3', b'3']) => debug!("ignoring OSC 133 (iterm2 marks)"),
+            // Shell integration marks
+            Some([b'1', b'3', b'3']) => match params_iter.next() {
+                Some([b'A']) => self.add_prompt_mark(PromptMark::PromptStart),
+                Some([b'B']) => self.add_prompt_mark(PromptMark::CommandStart),
+                Some([b'C']) => self.add_prompt_mark(PromptMark::OutputStart),
+                Some([b'D']) => {
+                    let status = params_iter
+                        .next()
+                        .and_then(|s| std::str::from_utf8(s).ok())
+                        .and_then(|s| s.parse::<i32>().ok());
+                    self.add_prompt_mark(PromptMark::CommandFinished(status));
+                }
+                mark => warn!("unhandled OSC 133 mark {mark:?}"),
+            },
             Some([b'3', b'0', b'0', b'8']) => debug!("ignoring OSC 3008 (systemd context signaling)"),
 
             _ => warn!("unhandled 'OSC {:?} {}'", params, if bell_terminated {
@@ -604,6 +2020,7 @@ impl vte::Perform for State {
         ignore: bool,
         action: char,
     ) {
+        self.flush_ascii_run();
         if ignore {
             warn!("malformed CSI seq");
             return;
@@ -616,48 +2033,52 @@ impl vte::Perform for State {
         let mut params_iter = params.iter();
 
         match action {
+            // SR (Scroll Right)
+            'A' if intermediates == [b' '] => {
+                let n = param_or(&mut params_iter, 1) as usize;
+                let attrs = self.cursor_attrs.erase_attrs();
+                self.screen_mut().scroll_right(&attrs, n);
+            }
             // CUU (Cursor Up)
             'A' => {
                 let n = param_or(&mut params_iter, 1) as usize;
                 let screen = self.screen_mut();
-                screen.cursor.row = screen.cursor.row.saturating_sub(n);
+                screen.cursor = screen.cursor.up(n);
                 screen.clamp();
             }
             // CUD (Cursor Down)
             'B' => {
                 let n = param_or(&mut params_iter, 1) as usize;
                 let screen = self.screen_mut();
-                screen.cursor.row += n;
+                screen.cursor = screen.cursor.down(n);
                 screen.clamp();
             }
             // CUF (Cursor Forward)
             'C' => {
                 let n = param_or(&mut params_iter, 1) as usize;
                 let screen = self.screen_mut();
-                screen.cursor.col += n;
+                screen.cursor = screen.cursor.right(n);
                 screen.clamp();
             }
-            // CUF (Cursor Backwards)
+            // CUB (Cursor Backwards)
             'D' => {
                 let n = param_or(&mut params_iter, 1) as usize;
                 let screen = self.screen_mut();
-                screen.cursor.col = screen.cursor.col.saturating_sub(n);
+                screen.cursor = screen.cursor.left(n);
                 screen.clamp();
             }
             // CNL (Cursor Next Line)
             'E' => {
                 let n = param_or(&mut params_iter, 1) as usize;
                 let screen = self.screen_mut();
-                screen.cursor.row += n;
-                screen.cursor.col = 0;
+                screen.cursor = term::Pos { row: screen.cursor.row, col: 0 }.down(n);
                 screen.clamp();
             }
             // CPL (Cursor Prev Line)
             'F' => {
                 let n = param_or(&mut params_iter, 1) as usize;
                 let screen = self.screen_mut();
-                screen.cursor.row = screen.cursor.row.saturating_sub(n);
-                screen.cursor.col = 0;
+                screen.cursor = term::Pos { row: screen.cursor.row, col: 0 }.up(n);
                 screen.clamp();
             }
             // CHA (Cursor Horizontal Absolute)
@@ -669,9 +2090,12 @@ impl vte::Perform for State {
                 screen.cursor.col = n;
                 screen.clamp();
             }
-            // CUP (Cursor Set Position)
-            'H' => {
-                // parse the params and adjust 1 indexing to 0 indexing
+            // CUP (Cursor Position) and HVP (Horizontal and Vertical
+            // Position), which xterm treats identically.
+            'H' | 'f' => {
+                // parse the params and adjust 1 indexing to 0 indexing.
+                // Each param defaults on its own, so `CSI ; 5 H` keeps the
+                // row at 1 rather than shifting 5 into it.
                 let row = param_or(&mut params_iter, 1) as usize;
                 let col = param_or(&mut params_iter, 1) as usize;
                 let screen = self.screen_mut();
@@ -679,36 +2103,68 @@ impl vte::Perform for State {
                 screen.clamp();
             }
             // ED (Erase in Display)
-            'J' => while let Some(code) = params_iter.next() {
+            // DECSED (Selective Erase in Display)
+            'J' if intermediates == [b'?'] => for code in params_iter {
+                let screen = self.screen_mut();
+                let cursor = screen.cursor;
+                let (rows, cursor_section) = match code {
+                    [] | [0] => (cursor.row + 1..screen.size.height, line::Section::ToEnd(cursor.col)),
+                    [1] => (0..cursor.row, line::Section::StartTo(cursor.col)),
+                    [2] => (0..screen.size.height, line::Section::Whole),
+                    _ => {
+                        warn!("unhandled 'CSI ? {code:?} J'");
+                        continue;
+                    }
+                };
+                for row in rows {
+                    screen.selective_erase(row, line::Section::Whole);
+                }
+                screen.selective_erase(cursor.row, cursor_section);
+            }
+            'J' => for code in params_iter {
                 match code {
                     [] | [0] => self.screen_mut().erase_to_end(),
                     [1] => self.screen_mut().erase_from_start(),
                     [2] => self.screen_mut().erase(false),
-                    [3] => self.screen_mut().erase(true),
+                    [3] => {
+                        self.screen_mut().erase(true);
+                        self.prune_prompt_marks();
+                    }
                     _ => warn!("unhandled 'CSI {code:?} J'"),
                 }
             }
-            // EL (Erase in Line)
-            'K' => while let Some(code) = params_iter.next() {
-                match code {
-                    [] | [0] => {
-                        let screen = self.screen_mut();
-                        let col = screen.cursor.col;
-                        if let Some(l) = screen.get_line_mut() {
-                            l.erase(line::Section::ToEnd(col));
-                        }
-                    }
-                    [1] => {
-                        let screen = self.screen_mut();
-                        let col = screen.cursor.col;
-                        if let Some(l) = screen.get_line_mut() {
-                            l.erase(line::Section::StartTo(col));
-                        }
+            // DECSEL (Selective Erase in Line)
+            'K' if intermediates == [b'?'] => for code in params_iter {
+                let screen = self.screen_mut();
+                let cursor = screen.cursor;
+                let section = match code {
+                    [] | [0] => line::Section::ToEnd(cursor.col),
+                    [1] => line::Section::StartTo(cursor.col),
+                    [2] => line::Section::Whole,
+                    _ => {
+                        warn!("unhandled 'CSI ? {code:?} K'");
+                        continue;
                     }
-                    [2] => if let Some(l) = self.screen_mut().get_line_mut() {
-                        l.erase(line::Section::Whole);
+                };
+                screen.selective_erase(cursor.row, section);
+            }
+            // EL (Erase in Line)
+            'K' => for code in params_iter {
+                let col = self.screen().cursor.col;
+                let section = match code {
+                    [] | [0] => line::Section::ToEnd(col),
+                    [1] => line::Section::StartTo(col),
+                    [2] => line::Section::Whole,
+                    _ => {
+                        warn!("unhandled 'CSI {code:?} K'");
+                        continue;
                     }
-                    _ => warn!("unhandled 'CSI {code:?} K'"),
+                };
+                let attrs = self.cursor_attrs.erase_attrs();
+                let screen = self.screen_mut();
+                let width = screen.size.width;
+                if let Some(l) = screen.get_line_mut() {
+                    l.erase_with_attrs(width, section, &attrs);
                 }
             }
             // IL (Insert Line)
@@ -750,43 +2206,104 @@ impl vte::Perform for State {
                 self.screen_mut().scroll_down(n as usize);
             }
 
+            // SL (Scroll Left)
+            '@' if intermediates == [b' '] => {
+                let n = param_or(&mut params_iter, 1) as usize;
+                let attrs = self.cursor_attrs.erase_attrs();
+                self.screen_mut().scroll_left(&attrs, n);
+            }
+            // REP (Repeat the preceding graphic char)
+            'b' => {
+                let n = param_or(&mut params_iter, 1) as usize;
+                match self.last_printed {
+                    Some(c) => self.repeat_char(c, n),
+                    None => debug!("REP with nothing printed yet"),
+                }
+            }
             // ICH (Insert Character)
             '@' => {
                 let n = param_or(&mut params_iter, 1) as usize;
-
-                let screen = self.screen_mut();
-                let width = screen.size.width;
-                let col = screen.cursor.col;
-                if let Some(l) = screen.get_line_mut() {
-                    l.insert_character(width, col, n);
-                }
+                let attrs = self.cursor_attrs.erase_attrs();
+                self.screen_mut().insert_chars(&attrs, n);
             }
             // DCH (Delete Character)
             'P' => {
                 let n = param_or(&mut params_iter, 1) as usize;
+                let attrs = self.cursor_attrs.erase_attrs();
+                self.screen_mut().delete_chars(&attrs, n);
+            }
 
-                let attrs = self.cursor_attrs.clone();
+            // DECSCA (Select Character Protection Attribute)
+            'q' if intermediates == [b'"'] => match param_or(&mut params_iter, 0) {
+                1 => self.protect_chars = true,
+                0 | 2 => self.protect_chars = false,
+                n => warn!("unhandled DECSCA param {n}"),
+            },
+            // DECSCUSR (Set Cursor Style)
+            'q' if intermediates == [b' '] => {
+                let n = param_or(&mut params_iter, 0);
+                match term::CursorStyle::from_param(n) {
+                    Some(style) => self.cursor_style = style,
+                    None => warn!("unhandled DECSCUSR param {n}"),
+                }
+            }
+            // DECIC (Insert Column)
+            '}' if intermediates == [b'\''] => {
+                let n = param_or(&mut params_iter, 1) as usize;
+                let attrs = self.cursor_attrs.erase_attrs();
+                self.screen_mut().insert_columns(&attrs, n);
+            }
+            // DECDC (Delete Column)
+            '~' if intermediates == [b'\''] => {
+                let n = param_or(&mut params_iter, 1) as usize;
+                let attrs = self.cursor_attrs.erase_attrs();
+                self.screen_mut().delete_columns(&attrs, n);
+            }
+            // DECSLRM (Set Left and Right Margins), which takes over from
+            // SCP while DECLRMM is on.
+            's' if self.lr_margin_mode => {
+                let left = maybe_param(&mut params_iter);
+                let right = maybe_param(&mut params_iter);
 
                 let screen = self.screen_mut();
                 let width = screen.size.width;
-                let col = screen.cursor.col;
-                if let Some(l) = screen.get_line_mut() {
-                    l.delete_character(width, col, &attrs, n);
+                let margin_left = left.map(|l| l as usize - 1).unwrap_or(0);
+                let margin_right = right.map(|r| std::cmp::min(r as usize, width)).unwrap_or(width);
+                // Just like scroll regions, margins have to span at least
+                // two columns.
+                if margin_left + 1 >= margin_right {
+                    warn!("ignoring invalid DECSLRM: left={left:?} right={right:?}");
+                    return;
                 }
+                screen.margins = if margin_left == 0 && margin_right == width {
+                    None
+                } else {
+                    Some((margin_left, margin_right))
+                };
+                screen.set_cursor(term::Pos { row: 1, col: 1 });
             }
-
-            // SCP (Save Cursor Position)
-            's' => {
-                let screen = self.screen_mut();
-                let cursor = screen.cursor.clone();
-                screen.saved_cursor.pos = cursor;
+            // SCP (Save Cursor Position), which xterm treats as DECSC
+            's' => self.save_cursor(),
+            // Kitty keyboard protocol flag stack manipulation
+            'u' if intermediates == [b'>'] => {
+                let flags = param_or(&mut params_iter, 0);
+                self.screen_mut().push_keyboard_flags(flags);
             }
-            // RCP (Restore Cursor Position)
-            'u' => {
-                let screen = self.screen_mut();
-                screen.cursor = screen.saved_cursor.pos;
-                screen.clamp();
+            'u' if intermediates == [b'<'] => {
+                let n = param_or(&mut params_iter, 1) as usize;
+                self.screen_mut().pop_keyboard_flags(n);
             }
+            'u' if intermediates == [b'='] => {
+                let flags = param_or(&mut params_iter, 0);
+                let mode = param_or(&mut params_iter, 1);
+                self.screen_mut().set_keyboard_flags(flags, mode);
+            }
+            'u' if intermediates == [b'?'] => {
+                let flags = self.screen().keyboard_flags();
+                self.respond(&ControlCodes::keyboard_flags_reply(flags));
+            }
+            // RCP (Restore Cursor Position), which xterm treats as DECRC
+            'u' => self.restore_cursor(),
 
             // TBC (Tabulation Clear, CSI 3 g, CSI 0 g, CSI g)
             'g' => {
@@ -804,28 +2321,42 @@ impl vte::Perform for State {
             }
 
             'h' => match intermediates {
-                [b'?'] => while let Some(code) = params_iter.next() {
+                [b'?'] => for code in params_iter {
                     match code {
-                        [1] => self.application_keypad_mode_enabled = true,
+                        [1] => self.app_cursor = true,
+                        [3] => self.switch_column_mode(132),
+                        [5] => self.reverse_video = true,
                         [6] => self.screen_mut().set_origin_mode(OriginMode::ScrollRegion),
+                        [12] => self.cursor_blink = true,
                         [25] => self.cursor_hidden = false,
+                        [45] => self.reverse_wraparound = true,
+                        [69] => self.lr_margin_mode = true,
                         // enable alt screen
                         [1049] => {
+                            // Save the main cursor (like DECSC) before the
+                            // switch so it is the main screen's that gets
+                            // saved. Re-entering while already on the alt
+                            // screen just clears it again.
+                            if let ScreenMode::Scrollback = self.screen_mode {
+                                self.save_cursor();
+                            }
                             // The alt-screen gets reset upon entry, so we need to
-                            // clobber it here.
+                            // clobber it here. A fresh screen has its cursor
+                            // homed.
                             self.altscreen = Screen::alt(self.altscreen.size);
                             self.screen_mode = ScreenMode::Alt;
                         }
                         [2004] => self.in_paste_mode = true,
-
-                        _ => {
-                            warn!(
-                                "Unhandled CSI l command: CSI {:?} {:?} l",
-                                intermediates,
-                                params.iter().collect::<Vec<&[u16]>>()
-                            );
-                            return;
-                        }
+                        // Keep going so that the other modes in a combined
+                        // set like `CSI ? 1000 ; 2004 h` still take effect.
+                        _ => warn!("Unhandled CSI h mode: CSI {:?} {:?} h", intermediates, code),
+                    }
+                }
+                [] => for code in params_iter {
+                    match code {
+                        [4] => self.insert_mode = true,
+                        [20] => self.newline_mode = true,
+                        _ => warn!("Unhandled CSI h mode: CSI {:?} h", code),
                     }
                 }
                 _ => warn!(
@@ -835,21 +2366,36 @@ impl vte::Perform for State {
                 ),
             }
             'l' => match intermediates {
-                [b'?'] => while let Some(code) = params_iter.next() {
+                [b'?'] => for code in params_iter {
                     match code {
-                        [1] => self.application_keypad_mode_enabled = false,
+                        [1] => self.app_cursor = false,
+                        [3] => self.switch_column_mode(80),
+                        [5] => self.reverse_video = false,
                         [6] => self.screen_mut().set_origin_mode(OriginMode::Term),
+                        [12] => self.cursor_blink = false,
                         [25] => self.cursor_hidden = true,
-                        [1049] => self.screen_mode = ScreenMode::Scrollback,
-                        [2004] => self.in_paste_mode = false,
-                        _ => {
-                            warn!(
-                                "Unhandled CSI l command: CSI {:?} {:?} l",
-                                intermediates,
-                                params.iter().collect::<Vec<&[u16]>>()
-                            );
-                            return;
+                        [45] => self.reverse_wraparound = false,
+                        [69] => {
+                            self.lr_margin_mode = false;
+                            self.scrollback.margins = None;
+                            self.altscreen.margins = None;
                         }
+                        [1049] => if let ScreenMode::Alt = self.screen_mode {
+                            // The alt screen contents get dropped on the
+                            // next entry anyway, and the main cursor comes
+                            // back as it was saved on entry (like DECRC).
+                            self.screen_mode = ScreenMode::Scrollback;
+                            self.restore_cursor();
+                        },
+                        [2004] => self.in_paste_mode = false,
+                        _ => warn!("Unhandled CSI l mode: CSI {:?} {:?} l", intermediates, code),
+                    }
+                }
+                [] => for code in params_iter {
+                    match code {
+                        [4] => self.insert_mode = false,
+                        [20] => self.newline_mode = false,
+                        _ => warn!("Unhandled CSI l mode: CSI {:?} l", code),
                     }
                 }
                 _ => warn!(
@@ -858,40 +2404,64 @@ impl vte::Perform for State {
                     params.iter().collect::<Vec<&[u16]>>()
                 ),
             },
+            // Primary and Tertiary DA (Device Attributes)
+            'c' => match (intermediates, maybe_param(&mut params_iter)) {
+                ([], None) => self.reply_primary_device_attributes(),
+                ([b'='], None) => {
+                    self.respond(&term::control_codes().tertiary_device_attributes_reply)
+                }
+                _ => warn!(
+                    "Unhandled DA command: CSI {:?} {:?} c",
+                    intermediates,
+                    params.iter().collect::<Vec<&[u16]>>()
+                ),
+            },
             // DSR (Device Status Report)
-            'n' => while let Some(param) = params_iter.next() {
-                match param {
-                    // TODO: We might want to store this to assert against the
-                    // terminal output stream once we start scanning that.
-                    // We'll need to implement terminal output stream scanning
-                    // in order to properly handle kitty extensions at some
-                    // point (since we need to know if the real terminal
-                    // responded with a code indicating that it supported the
-                    // extensions in order to determine how we should interpret
-                    // control codes).
-                    [6] => debug!("ignoring DSR (CSI 6 n), that's the real terminal's job"),
-                    _ => {}
+            'n' => for param in params_iter {
+                if let [6] = param {
+                    // CPR (Cursor Position Report). We answer this like the
+                    // other queries so that callers with no real terminal
+                    // attached still get a reply.
+                    let pos = self.screen().reported_cursor();
+                    self.respond(&term::ControlCodes::cursor_position_report(
+                        u16::try_from(pos.row).unwrap_or(u16::MAX),
+                        u16::try_from(pos.col).unwrap_or(u16::MAX),
+                    ));
                 }
             },
 
+            // XTMODKEYS (Set/reset key modifier options)
+            'm' if intermediates == [b'>'] => {
+                // Resource 0 is a real resource, so go by the raw params
+                // rather than letting 0 mean the default. vte can't tell
+                // `CSI > m` from `CSI > 0 m` though, so a lone 0 still
+                // means no resource was given.
+                let mut raw = params_iter.map(|p| p.first().copied().unwrap_or(0));
+                match (raw.next(), raw.next()) {
+                    // With no resource given, every option gets reset.
+                    (None, _) | (Some(0 | 4), None) => self.modify_other_keys = 0,
+                    (Some(4), Some(level)) => self.modify_other_keys = level,
+                    (Some(resource), _) => {
+                        debug!("ignoring key modifier option for resource {resource}")
+                    }
+                }
+            }
             // cell attribute manipulation
             'm' => while let Some(param) = params_iter.next() {
                 match param {
                     [] | [0] => self.cursor_attrs = term::Attrs::default(),
 
                     // Underline Handling
-                    // TODO: there are lots of other underline styles. To fix,
-                    // we need to update attrs.
+                    // The colon form picks a style (kitty extension), and
+                    // `4:0` turns underlining off just like 24 does.
                     //
-                    // Kitty extensions:
-                    //      CSI 4 : 3 m => curly
-                    //      CSI 4 : 2 m => double
-                    //
-                    // Other:
-                    //      CSI 58 ; 2 ; r ; g ; b m => RGB colored underline
-                    [4] => self.cursor_attrs.underline = Some(UnderlineStyle::Single),
-                    [21] => self.cursor_attrs.underline = Some(UnderlineStyle::Double),
-                    [24] => self.cursor_attrs.underline = None,
+                    // TODO: colored underlines (CSI 58 ; 2 ; r ; g ; b m).
+                    [4] | [4, 1] => self.cursor_attrs.underline = Some(UnderlineStyle::Single),
+                    [21] | [4, 2] => self.cursor_attrs.underline = Some(UnderlineStyle::Double),
+                    [4, 3] => self.cursor_attrs.underline = Some(UnderlineStyle::Curly),
+                    [4, 4] => self.cursor_attrs.underline = Some(UnderlineStyle::Dotted),
+                    [4, 5] => self.cursor_attrs.underline = Some(UnderlineStyle::Dashed),
+                    [24] | [4, 0] => self.cursor_attrs.underline = None,
 
                     // Font Weight Handling.
                     [1] => self.cursor_attrs.font_weight = Some(FontWeight::Bold),
@@ -928,6 +2498,9 @@ impl vte::Perform for State {
                     [53] => self.cursor_attrs.overline = true,
                     [55] => self.cursor_attrs.overline = false,
 
+                    // Font Handling.
+                    [n @ 10..=19] => self.cursor_attrs.font = (n - 10) as u8,
+
                     // Background Color Handling.
                     [49] => self.cursor_attrs.bgcolor = term::Color::Default,
                     [n] if 40 <= *n && *n < 48 => match (*n - 40).try_into() {
@@ -1006,40 +2579,56 @@ impl vte::Perform for State {
             }
             'p' => match intermediates {
                 // DECSTR (DEC Soft Terminal Reset)
-                [b'!'] => {
-                    self.tabstops.fill(false);
-                    let width = self.screen().size.width;
-                    self.fill_tabstops(0, width);
-
-                    warn!("DECSTR only partially handled");
-                }
+                [b'!'] => self.soft_reset(),
+                // DECSCL (Select Conformance Level)
+                [b'"'] => self.set_conformance_level(
+                    param_or(&mut params_iter, DEFAULT_CONFORMANCE_LEVEL),
+                    params_iter.next(),
+                ),
                 _ => warn!(
-                    "Unhandled CSI l command: CSI {:?} {:?} l",
+                    "Unhandled CSI p command: CSI {:?} {:?} p",
                     intermediates,
                     params.iter().collect::<Vec<&[u16]>>()
                 ),
             },
+            // XTWINOPS (Window Manipulation) and DECSLPP (Set Lines Per
+            // Page). We only pick out the size requests and leave the
+            // actual resize up to the embedder. The rest are window
+            // manager ops and reports that have no meaning for us.
+            't' if intermediates.is_empty() => match param_or(&mut params_iter, 0) {
+                // Resize the text area, where a 0 keeps the current size.
+                8 => {
+                    let rows = param_or(&mut params_iter, 0) as usize;
+                    let cols = param_or(&mut params_iter, 0) as usize;
+                    if rows > 0 {
+                        self.height_request = Some(rows);
+                    }
+                    if cols > 0 {
+                        self.column_request = Some(cols);
+                    }
+                }
+                lines if lines >= 24 => self.height_request = Some(lines as usize),
+                op => warn!("unhandled window op CSI {op} t"),
+            },
+
             // DECSTBM (Set Scroll Region)
             'r' => {
                 let top = maybe_param(&mut params_iter);
                 let bottom = maybe_param(&mut params_iter);
 
                 let screen = self.screen_mut();
-                screen.set_scroll_region(match (top, bottom) {
-                    (None, None) => term::ScrollRegion::TrackSize,
-                    (Some(t), None) => term::ScrollRegion::Window {
-                        top: t.saturating_sub(1) as usize,
-                        bottom: screen.size.height,
-                    },
-                    (None, Some(b)) => term::ScrollRegion::Window {
-                        top: 0,
-                        bottom: b as usize,
-                    },
-                    (Some(t), Some(b)) => term::ScrollRegion::Window {
-                        top: t.saturating_sub(1) as usize,
-                        bottom: b as usize,
-                    }
-                });
+                if top.is_none() && bottom.is_none() {
+                    screen.set_scroll_region(term::ScrollRegion::TrackSize);
+                    screen.set_cursor(term::Pos { row: 1, col: 1 });
+                    return;
+                }
+
+                let height = screen.size.height;
+                let region_top = top.map(|t| t as usize - 1).unwrap_or(0);
+                let region_bottom = bottom.map(|b| b as usize).unwrap_or(height);
+                if !self.set_scroll_window(region_top, region_bottom) {
+                    warn!("ignoring invalid DECSTBM: top={top:?} bottom={bottom:?}");
+                }
             }
 
             _ => {
@@ -1049,6 +2638,7 @@ impl vte::Perform for State {
     }
 
     fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8) {
+        self.flush_ascii_run();
         if ignore {
             warn!("malformed ESC seq");
             return;
@@ -1057,31 +2647,49 @@ impl vte::Perform for State {
 
         match (intermediates, byte) {
             // save cursor (ESC 7)
-            ([], b'7') => {
-                let attrs = self.cursor_attrs.clone();
-                let screen = self.screen_mut();
-                let pos = screen.cursor.clone();
-                screen.saved_cursor = SavedCursor { pos, attrs };
-            }
+            ([], b'7') => self.save_cursor(),
             // restore cursor (ESC 8)
-            ([], b'8') => {
-                let screen = self.screen_mut();
-                screen.cursor = screen.saved_cursor.pos;
-                self.cursor_attrs = screen.saved_cursor.attrs.clone();
-            }
+            ([], b'8') => self.restore_cursor(),
             // HTS (Horizontal Tabluation Set, ESC H)
             ([], b'H') => {
                 let col = self.screen().cursor.col;
                 self.tabstops.set(col, true);
             }
             // RIS (Reset to Initial State)
-            ([], b'c') => {
-                self.tabstops.fill(false);
-                let width = self.screen().size.width;
-                self.fill_tabstops(0, width);
-
-                warn!("RIS only partially handled");
+            ([], b'c') => self.reset(),
+            // S7C1T / S8C1T (Send 7-bit / 8-bit C1 Control Characters).
+            // A VT100 level terminal can't send 8-bit controls.
+            ([b' '], b'F') => self.eight_bit_controls = false,
+            ([b' '], b'G') => self.eight_bit_controls = self.conformance_level > 61,
+            // DECKPAM / DECKPNM (Keypad Application / Numeric Mode)
+            ([], b'=') => self.app_keypad = true,
+            ([], b'>') => self.app_keypad = false,
+            // DECDHL / DECSWL / DECDWL (Line Size)
+            ([b'#'], size @ b'3'..=b'6') => {
+                if let Some(line_size) = term::LineSize::from_final_byte(size) {
+                    self.screen_mut().set_line_size(line_size);
+                }
+            }
+            // RI (Reverse Index)
+            ([], b'M') => self.screen_mut().reverse_index(),
+            // DECBI (Back Index)
+            ([], b'6') => {
+                let attrs = self.cursor_attrs.erase_attrs();
+                self.screen_mut().back_index(&attrs);
             }
+            // DECFI (Forward Index)
+            ([], b'9') => {
+                let attrs = self.cursor_attrs.erase_attrs();
+                self.screen_mut().forward_index(&attrs);
+            }
+            // DECID (Identify Terminal), an obsolete form of Primary DA
+            ([], b'Z') => self.reply_primary_device_attributes(),
+            // Select Latin-1 / UTF-8 input
+            ([b'%'], b'@') => self.switch_charset_mode(CharsetMode::Latin1),
+            ([b'%'], b'G') => self.switch_charset_mode(CharsetMode::Utf8),
+            // SCS (Select Character Set) for G0 and G1
+            ([b'('], designator) => self.charsets[0] = term::Charset::from_designator(designator),
+            ([b')'], designator) => self.charsets[1] = term::Charset::from_designator(designator),
             // OSC terminators that get sent to the esc handler as well,
             // we can ignore them.
             ([], 92) => {}
@@ -1091,15 +2699,15 @@ impl vte::Perform for State {
     }
 
     fn terminated(&self) -> bool {
-        false
+        self.charset_mode_switched
     }
 }
 
-fn param_or<'params>(params: &mut vte::ParamsIter<'params>, default: u16) -> u16 {
+fn param_or(params: &mut vte::ParamsIter<'_>, default: u16) -> u16 {
     maybe_param(params).unwrap_or(default)
 }
 
-fn maybe_param<'params>(params: &mut vte::ParamsIter<'params>) -> Option<u16> {
+fn maybe_param(params: &mut vte::ParamsIter<'_>) -> Option<u16> {
     match params.next() {
         Some([0]) => None,
         Some([p]) => Some(*p),
@@ -1108,3 +2716,13 @@ fn maybe_param<'params>(params: &mut vte::ParamsIter<'params>) -> Option<u16> {
 }
 
 const NONE_VEC: Option<Vec<u8>> = None;
+
+/// The most zero width chars we hold on to while waiting for a base char
+/// to attach them to, so a stream of nothing but marks can't grow the
+/// buffer without bound. Cells cap the chars joined onto their base char
+/// at the same number.
+pub(crate) const MAX_PENDING_MARKS: usize = 16;
+
+/// We identify as a VT220 in our primary DA reply, so that is the level
+/// we start out at.
+const DEFAULT_CONFORMANCE_LEVEL: u16 = 62;
@@ -18,11 +18,10 @@
 
 use crate::{
     cell::{self, Cell},
+    error::GridError,
     term::{self, AsTermInput},
 };
 
-use anyhow::anyhow;
-
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Line {
     /// The cells stored in this line.
@@ -31,6 +30,8 @@ pub struct Line {
     /// the terminal width. The following line is part of the same logical
     /// line and should be reflowed together with this line on terminal resize.
     pub is_wrapped: bool,
+    /// The double width or height set on this line, if any.
+    pub line_size: term::LineSize,
 }
 
 impl std::fmt::Display for Line {
@@ -48,23 +49,17 @@ impl std::fmt::Display for Line {
 }
 
 impl AsTermInput for Line {
-    // We start every line with blank attrs, and it is our responsibility
-    // to reset the attrs at the end of each line. We could produce more
-    // optimal output if we fused attr runs across lines, but it is probably
-    // fine to just do it like this and it is better to keep things simple
-    // unless we need to fuse.
+    // A line on its own starts with blank attrs, and it is our
+    // responsibility to reset the attrs at the end. Runs of lines go
+    // through `term_input_lines_into` instead, which carries the attrs
+    // across line breaks.
     fn term_input_into(&self, buf: &mut Vec<u8>) {
         let blank_attrs = term::Attrs::default();
-        let mut current_attrs = &blank_attrs;
+        let mut protected = false;
+        let current_attrs = self.term_input_cells_into(buf, &blank_attrs, &mut protected);
 
-        for cell in self.cells.iter() {
-            if cell.attrs() != current_attrs {
-                for code in current_attrs.transition_to(cell.attrs()) {
-                    code.term_input_into(buf);
-                }
-                current_attrs = cell.attrs();
-            }
-            cell.term_input_into(buf);
+        if protected {
+            term::ControlCodes::select_character_protection(false).term_input_into(buf);
         }
 
         if current_attrs != &blank_attrs {
@@ -75,6 +70,152 @@ impl AsTermInput for Line {
     }
 }
 
+impl Line {
+    /// Write out the line size and cells of this line, given the attrs
+    /// and character protection already in effect. Returns the attrs in
+    /// effect afterwards and updates `protected` to match.
+    fn term_input_cells_into<'a>(
+        &'a self,
+        buf: &mut Vec<u8>,
+        prev_attrs: &'a term::Attrs,
+        protected: &mut bool,
+    ) -> &'a term::Attrs {
+        let mut current_attrs = prev_attrs;
+
+        if self.line_size != term::LineSize::Single {
+            self.line_size.code().term_input_into(buf);
+        }
+
+        // Trailing plain blanks look just like cells that were never
+        // written, so there is no need to spell them out as spaces.
+        // Blanks with a background color (from BCE) are kept.
+        let end = self.cells.iter().rposition(|c| !c.is_plain_blank()).map_or(0, |i| i + 1);
+        for cell in self.cells[..end].iter() {
+            if cell.is_protected() != *protected {
+                *protected = cell.is_protected();
+                term::ControlCodes::select_character_protection(*protected).term_input_into(buf);
+            }
+            current_attrs = cell.term_input_with_attrs_into(buf, current_attrs);
+        }
+
+        current_attrs
+    }
+}
+
+/// Write out a run of lines from top to bottom, separated by `Crlf`.
+/// Both the scrollback and the alt screen use this so that they agree
+/// on where line breaks go in a dump.
+///
+/// Rather than resetting at the end of every line, the attrs carry over
+/// from one line to the next so that a run of cells with the same attrs
+/// spanning several lines only needs one SGR code. The exception is the
+/// background color, since a line feed at the bottom of the screen fills
+/// the new line with the current background on most terminals.
+pub fn term_input_lines_into<'a, I>(lines: I, buf: &mut Vec<u8>)
+where
+    I: IntoIterator<Item = &'a Line>,
+{
+    let blank_attrs = term::Attrs::default();
+    let mut attrs = term::Attrs::default();
+    let mut protected = false;
+    for (i, line) in lines.into_iter().enumerate() {
+        if i != 0 {
+            if attrs.bgcolor != term::Color::Default {
+                let next = term::Attrs { bgcolor: term::Color::Default, ..attrs.clone() };
+                for code in attrs.transition_to(&next) {
+                    code.term_input_into(buf);
+                }
+                attrs = next;
+            }
+            term::Crlf.term_input_into(buf);
+        }
+        attrs = line.term_input_cells_into(buf, &attrs, &mut protected).clone();
+    }
+
+    if protected {
+        term::ControlCodes::select_character_protection(false).term_input_into(buf);
+    }
+
+    if attrs != blank_attrs {
+        for code in attrs.transition_to(&blank_attrs) {
+            code.term_input_into(buf);
+        }
+    }
+}
+
+/// Write out the plain text of a run of lines from top to bottom. Soft
+/// wrapped lines are joined to the line after them, and every other line
+/// ends with a newline.
+pub fn text_lines_into<'a, I>(lines: I, buf: &mut String, options: crate::TextOptions)
+where
+    I: IntoIterator<Item = &'a Line>,
+{
+    for line in lines.into_iter() {
+        line.text_into(buf, options);
+        if !line.is_wrapped {
+            buf.push('\n');
+        }
+    }
+}
+
+/// Get the plain text of the idx'th logical line in a run of lines from
+/// top to bottom, with any soft wraps joined back together. Returns None
+/// if there are not that many logical lines.
+pub fn logical_line_text<'a, I>(lines: I, idx: usize, options: crate::TextOptions) -> Option<String>
+where
+    I: IntoIterator<Item = &'a Line>,
+{
+    let mut lines = lines.into_iter();
+    for _ in 0..idx {
+        while lines.next()?.is_wrapped {}
+    }
+
+    let mut buf = String::new();
+    let mut line = lines.next()?;
+    loop {
+        line.text_into(&mut buf, options);
+        if !line.is_wrapped {
+            break;
+        }
+        match lines.next() {
+            Some(next) => line = next,
+            None => break,
+        }
+    }
+    Some(buf)
+}
+
+/// Insert n blank lines at row `at` of the given region, which is ordered
+/// from top to bottom. Lines at and below `at` move down, and any pushed
+/// past the bottom of the region are dropped. This is the scroll region
+/// shuffle that IL (Insert Line) performs, shared by both grid types.
+pub fn insert_lines(region: &mut [Line], at: usize, n: usize) {
+    if at >= region.len() {
+        return;
+    }
+    let n = std::cmp::min(n, region.len() - at);
+    region[at..].rotate_right(n);
+    for line in region[at..at + n].iter_mut() {
+        *line = Line::new();
+    }
+}
+
+/// Delete n lines at row `at` of the given region, which is ordered from
+/// top to bottom. Lines below the deleted ones move up and blank lines
+/// fill in at the bottom of the region. This is the scroll region shuffle
+/// that DL (Delete Line) performs, shared by both grid types.
+pub fn delete_lines(region: &mut [Line], at: usize, n: usize) {
+    if at >= region.len() {
+        return;
+    }
+    let n = std::cmp::min(n, region.len() - at);
+    region[at..].rotate_left(n);
+    let len = region.len();
+    for line in region[len - n..].iter_mut() {
+        *line = Line::new();
+    }
+}
+
 /// A line contains a list of cells.
 ///
 /// Note that a line can't really be used on its own because the grid
@@ -83,11 +224,44 @@ impl AsTermInput for Line {
 /// to have the grid width passed down by the grid implementation.
 impl Line {
     pub fn new() -> Self {
-        Line { cells: vec![], is_wrapped: false }
+        Line { cells: vec![], is_wrapped: false, line_size: term::LineSize::Single }
+    }
+
+    /// The number of screen columns the cells of this line take up. For a
+    /// line written through the grid this is just the number of cells,
+    /// since wide cells are followed by pads, but it stays right for runs
+    /// of cells that have been stitched together from several lines.
+    pub fn display_width(&self) -> usize {
+        self.cells.iter().map(Cell::columns).sum()
+    }
+
+    /// Write the plain text of this line into the buffer. Blank cells
+    /// become spaces, though trailing blanks are trimmed unless the line
+    /// wraps onto the next one.
+    pub fn text_into(&self, buf: &mut String, options: crate::TextOptions) {
+        let start = buf.len();
+        for cell in self.cells.iter() {
+            if cell.is_wide_pad() {
+                continue;
+            }
+            if cell.is_blank() {
+                buf.push(' ');
+            } else if cell.is_concealed() && !options.reveal_concealed {
+                for _ in 0..cell.width() {
+                    buf.push(' ');
+                }
+            } else {
+                buf.extend(cell.chars());
+            }
+        }
+
+        if !self.is_wrapped {
+            let trimmed_len = start + buf[start..].trim_end_matches(' ').len();
+            buf.truncate(trimmed_len);
+        }
     }
 
     /// Get the cell at the given grid position.
-    #[allow(dead_code)]
     pub fn get_cell(&self, width: usize, col: usize) -> Option<&Cell> {
         if col >= width {
             return None;
@@ -97,19 +271,25 @@ impl Line {
             return Some(cell::empty());
         }
 
-        return Some(&self.cells[col]);
+        Some(&self.cells[col])
     }
 
     /// Set the given column to the given cell.
-    pub fn set_cell(&mut self, width: usize, col: usize, cell: Cell) -> anyhow::Result<()> {
+    pub fn set_cell(&mut self, width: usize, col: usize, cell: Cell) -> Result<(), GridError> {
         if col >= width {
-            return Err(anyhow!("{} out of bounds (width={})", col, width));
+            return Err(GridError::ColOutOfBounds { col, width });
         }
 
+        self.clear_wide_char_at(col);
+
         if col >= self.cells.len() {
-            while self.cells.len() < col {
-                self.cells.push(Cell::empty())
+            // Lines usually get written all the way across, so grab room
+            // for the whole width up front rather than growing a cell at
+            // a time.
+            if self.cells.capacity() == 0 {
+                self.cells.reserve_exact(width);
             }
+            self.cells.resize(col, Cell::empty());
             self.cells.push(cell);
             return Ok(());
         }
@@ -118,6 +298,61 @@ impl Line {
         Ok(())
     }
 
+    /// Overwrite the columns starting at `col` with the given narrow cells.
+    /// This is the same as calling `set_cell` for each of them, minus the
+    /// per cell overhead.
+    pub fn set_cells<I>(&mut self, width: usize, col: usize, cells: I) -> Result<(), GridError>
+    where
+        I: ExactSizeIterator<Item = Cell>,
+    {
+        let end = col + cells.len();
+        if end > width {
+            return Err(GridError::ColOutOfBounds { col: end - 1, width });
+        }
+        if end == col {
+            return Ok(());
+        }
+
+        // Only wide chars hanging over either end of the run can survive
+        // in part, anything in between gets overwritten entirely.
+        self.clear_wide_char_at(col);
+        self.clear_wide_char_at(end - 1);
+
+        if self.cells.capacity() == 0 {
+            self.cells.reserve_exact(width);
+        }
+        if col > self.cells.len() {
+            self.cells.resize(col, Cell::empty());
+        }
+        let overwritten = std::cmp::min(end, self.cells.len());
+        self.cells.splice(col..overwritten, cells);
+        Ok(())
+    }
+
+    /// If the given column is covered by a wide character (either the cell
+    /// holding the character or one of its trailing padding cells), blank
+    /// out every column the wide character covers. There is no way to
+    /// display half of a glyph, so partially overwriting or erasing a wide
+    /// character clobbers the whole thing.
+    fn clear_wide_char_at(&mut self, col: usize) {
+        if col >= self.cells.len() {
+            return;
+        }
+
+        let mut start = col;
+        while start > 0 && self.cells[start].is_wide_pad() {
+            start -= 1;
+        }
+        if !self.cells[start].is_wide() {
+            return;
+        }
+
+        let end = std::cmp::min(start + self.cells[start].width() as usize, self.cells.len());
+        for cell in self.cells[start..end].iter_mut() {
+            *cell = Cell::empty();
+        }
+    }
+
     /// Trim the line to the new width, dropping any cells too far to the right.
     pub fn truncate(&mut self, width: usize) {
         self.cells.truncate(width);
@@ -128,11 +363,13 @@ impl Line {
     pub fn erase(&mut self, section: Section) {
         match section {
             Section::StartTo(col) => {
+                self.clear_wide_char_at(col);
                 for i in 0..std::cmp::min(col + 1, self.cells.len()) {
                     self.cells[i] = Cell::empty();
                 }
             }
             Section::ToEnd(col) => {
+                self.clear_wide_char_at(col);
                 self.truncate(col);
                 self.is_wrapped = false;
             }
@@ -143,11 +380,100 @@ impl Line {
         }
     }
 
-    /// Insert n new blank cells at the current position, dropping
-    /// any cells which spill over width.
-    pub fn insert_character(&mut self, width: usize, col: usize, n: usize) {
-        let empties = vec![Cell::empty(); n];
-        self.cells.splice(col..col, empties);
+    /// Reset the line to a blank single width line. This is what ED does
+    /// to the lines it erases completely, unlike EL, which leaves the line
+    /// size alone.
+    pub fn clear(&mut self) {
+        self.erase(Section::Whole);
+        self.line_size = term::LineSize::Single;
+    }
+
+    /// Erase the given section, skipping over any protected cells. This
+    /// implements the selective erase that DECSED and DECSEL perform.
+    pub fn selective_erase(&mut self, section: Section) {
+        let (start, end) = match section {
+            Section::StartTo(col) => (0, col + 1),
+            Section::ToEnd(col) => (col, self.cells.len()),
+            Section::Whole => (0, self.cells.len()),
+        };
+        for col in start..std::cmp::min(end, self.cells.len()) {
+            let cell = &self.cells[col];
+            if cell.is_protected() || cell.is_wide_pad() {
+                continue;
+            }
+            if cell.is_wide() {
+                self.clear_wide_char_at(col);
+            } else {
+                self.cells[col] = Cell::empty();
+            }
+        }
+    }
+
+    /// Like `erase`, but fills the erased section with blanks carrying
+    /// the given attrs so that a background color shows through.
+    pub fn erase_with_attrs(&mut self, width: usize, section: Section, attrs: &term::Attrs) {
+        self.erase(section);
+        if *attrs == term::Attrs::default() {
+            return;
+        }
+
+        let (start, end) = match section {
+            Section::StartTo(col) => (0, std::cmp::min(col + 1, width)),
+            Section::ToEnd(col) => (col, width),
+            Section::Whole => (0, width),
+        };
+        if start >= end {
+            return;
+        }
+        if self.cells.len() < end {
+            self.cells.resize(end, Cell::empty());
+        }
+        let blank = Cell::empty_with_attrs(attrs.clone());
+        for cell in self.cells[start..end].iter_mut() {
+            *cell = blank.clone();
+        }
+    }
+
+    /// Run an edit on just the part of the line left of the `right` margin
+    /// (exclusive), leaving the cells from `right` onwards where they are.
+    /// The edit should treat `right` as the line width, so that cells get
+    /// shifted up to the margin rather than the edge of the screen.
+    pub fn within_right_margin<F: FnOnce(&mut Line)>(&mut self, right: usize, edit: F) {
+        let tail = if self.cells.len() > right { self.cells.split_off(right) } else { vec![] };
+        edit(self);
+        if !tail.is_empty() {
+            self.cells.resize(right, Cell::empty());
+            self.cells.extend(tail);
+        }
+    }
+
+    /// Insert n new blank cells with the given attributes at the current
+    /// position, dropping any cells which spill over width.
+    pub fn insert_character(&mut self, width: usize, col: usize, attrs: &term::Attrs, n: usize) {
+        self.insert_blanks(width, col, attrs, n);
+    }
+
+    /// Insert n empty cells with the given attributes at the given
+    /// column, pushing cells to the right and dropping any that get
+    /// pushed off the end of the line.
+    pub fn insert_blanks(&mut self, width: usize, col: usize, attrs: &term::Attrs, n: usize) {
+        if col >= width {
+            return;
+        }
+        if col >= self.cells.len() {
+            if *attrs == term::Attrs::default() {
+                // Inserting plain blanks past the end of the line has
+                // no visible effect.
+                return;
+            }
+            self.cells.resize(col, Cell::empty());
+        }
+
+        // Anything past the width gets truncated right away, so there
+        // is no point in allocating it.
+        let n = std::cmp::min(n, width - col);
+        let blanks = vec![Cell::empty_with_attrs(attrs.clone()); n];
+        self.cells.splice(col..col, blanks);
         self.cells.truncate(width);
     }
 
@@ -158,26 +484,38 @@ impl Line {
     ///
     /// This implements DCH (Delete Character).
     pub fn delete_character(&mut self, width: usize, col: usize, attrs: &term::Attrs, n: usize) {
+        if col >= width || (col >= self.cells.len() && *attrs == term::Attrs::default()) {
+            // Nothing to suck left and nothing visible to backfill.
+            return;
+        }
+        if col > self.cells.len() {
+            self.cells.resize(col, Cell::empty());
+        }
+
+        // Columns past the width can't be deleted, and the backfill
+        // covers every deleted column, even ones that were only
+        // logically present past the end of a short cells buffer.
+        let n = std::cmp::min(n, width - col);
         let delete_to = std::cmp::min(self.cells.len(), col + n);
-        let num_to_delete = delete_to - col;
 
         self.cells.drain(col..delete_to);
 
         // Inject the empty cells that were logically already present
         // when the cells buffer was short.
-        while self.cells.len() < width - num_to_delete {
+        while self.cells.len() < width - n {
             self.cells.push(Cell::empty());
         }
 
         // Inject the "backfill" cells that the semantics of DCH call
         // for. These are empty cells with the current attributes set.
-        while self.cells.len() < width {
-            self.cells.push(Cell::empty_with_attrs(attrs.clone()));
+        if self.cells.len() < width {
+            self.cells.resize(width, Cell::empty_with_attrs(attrs.clone()));
         }
     }
 }
 
 /// Specify a region of the line.
+#[derive(Debug, Clone, Copy)]
 pub enum Section {
     StartTo(usize),
     ToEnd(usize),
@@ -188,6 +526,28 @@ pub enum Section {
 mod tests {
     use super::*;
 
+    #[test]
+    fn display_width_mixed() -> anyhow::Result<()> {
+        let width = 10;
+        let mut line = Line::new();
+        line.set_cell(width, 0, Cell::new('a', term::Attrs::default()))?;
+        line.set_cell(width, 1, Cell::new('中', term::Attrs::default()))?;
+        line.set_cell(width, 2, Cell::wide_pad())?;
+        line.set_cell(width, 3, Cell::new('b', term::Attrs::default()))?;
+        line.set_cell(width, 5, Cell::new('文', term::Attrs::default()))?;
+        line.set_cell(width, 6, Cell::wide_pad())?;
+
+        // The blank at column 4 counts, the pads don't.
+        assert_eq!(line.display_width(), 7);
+        assert_eq!(line.display_width(), line.cells.len());
+
+        // Without its pad a wide cell still takes up both columns.
+        line.cells.pop();
+        assert_eq!(line.display_width(), 7);
+        assert_eq!(Line::new().display_width(), 0);
+        Ok(())
+    }
+
     #[test]
     fn new() {
         let line = Line::new();
@@ -218,13 +578,99 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn wide_cell_columns() -> anyhow::Result<()> {
+        let mut line = Line::new();
+        let width = 5;
+        line.set_cell(width, 0, Cell::new('a', term::Attrs::default()))?;
+        line.set_cell(width, 1, Cell::new('😊', term::Attrs::default()))?;
+        line.set_cell(width, 2, Cell::wide_pad())?;
+        line.set_cell(width, 3, Cell::new('b', term::Attrs::default()))?;
+
+        assert!(!line.get_cell(width, 0).unwrap().is_wide());
+        assert!(line.get_cell(width, 1).unwrap().is_wide());
+        assert!(!line.get_cell(width, 1).unwrap().is_wide_pad());
+        assert!(line.get_cell(width, 2).unwrap().is_wide_pad());
+        assert!(!line.get_cell(width, 3).unwrap().is_wide_pad());
+
+        Ok(())
+    }
+
+    #[test]
+    fn overwrite_wide_pad_clears_wide_char() -> anyhow::Result<()> {
+        let mut line = Line::new();
+        let width = 5;
+        line.set_cell(width, 0, Cell::new('😊', term::Attrs::default()))?;
+        line.set_cell(width, 1, Cell::wide_pad())?;
+
+        line.set_cell(width, 1, Cell::new('x', term::Attrs::default()))?;
+        assert!(line.get_cell(width, 0).unwrap().is_empty());
+        assert!(!line.get_cell(width, 0).unwrap().is_wide());
+        assert_eq!(line.get_cell(width, 1), Some(&Cell::new('x', term::Attrs::default())));
+
+        Ok(())
+    }
+
+    #[test]
+    fn set_cells_clears_straddled_wide_chars() -> anyhow::Result<()> {
+        let mut line = Line::new();
+        let width = 6;
+        line.set_cell(width, 0, Cell::new('😊', term::Attrs::default()))?;
+        line.set_cell(width, 1, Cell::wide_pad())?;
+        line.set_cell(width, 2, Cell::new('😊', term::Attrs::default()))?;
+        line.set_cell(width, 3, Cell::wide_pad())?;
+
+        let x = Cell::new('x', term::Attrs::default());
+        line.set_cells(width, 1, vec![x.clone(), x.clone()].into_iter())?;
+        assert_eq!(line.cells, vec![Cell::empty(), x.clone(), x.clone(), Cell::empty()]);
+
+        // Writing past the end of the cells pads out the gap.
+        line.set_cells(width, 5, std::iter::once(x.clone()))?;
+        assert_eq!(line.cells.len(), 6);
+        assert!(line.cells[4].is_empty());
+        assert!(line.set_cells(width, 5, vec![x.clone(), x].into_iter()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn lines_carry_attrs_across_breaks() -> anyhow::Result<()> {
+        let width = 3;
+        let red = term::Attrs { fgcolor: term::Color::Idx(1), ..term::Attrs::default() };
+        let mut lines = vec![];
+        for _ in 0..4 {
+            let mut line = Line::new();
+            for col in 0..width {
+                line.set_cell(width, col, Cell::new('x', red.clone()))?;
+            }
+            lines.push(line);
+        }
+
+        let mut per_line = vec![];
+        for (i, line) in lines.iter().enumerate() {
+            if i != 0 {
+                term::Crlf.term_input_into(&mut per_line);
+            }
+            line.term_input_into(&mut per_line);
+        }
+        let mut fused = vec![];
+        term_input_lines_into(lines.iter(), &mut fused);
+
+        assert!(fused.len() < per_line.len());
+        assert_eq!(fused, b"\x1b[31mxxx\r\nxxx\r\nxxx\r\nxxx\x1b[39m");
+
+        Ok(())
+    }
+
     #[test]
     fn set_oob() -> anyhow::Result<()> {
         let mut line = Line::new();
         let width = 5;
         match line.set_cell(width, 5, Cell::new('a', term::Attrs::default())) {
-            Err(e) => assert!(format!("{e:?}").contains("out of bounds")),
-            _ => assert!(false, "expected out of bounds error"),
+            Err(e @ GridError::ColOutOfBounds { col: 5, width: 5 }) => {
+                assert!(e.to_string().contains("out of bounds"))
+            }
+            _ => panic!("expected out of bounds error"),
         }
 
         Ok(())
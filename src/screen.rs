@@ -17,17 +17,20 @@
 
 use crate::{
     altscreen::AltScreen,
-    cell::Cell,
-    line::Line,
+    cell::{self, Cell},
+    error::GridError,
+    line::{self, Line},
     scrollback::Scrollback,
-    term::{self, AsTermInput, OriginMode, Pos, ScrollRegion},
+    term::{self, AsTermInput, OriginMode, Pos, Region, ScrollRegion},
 };
 
-use tracing::warn;
+use std::sync::Arc;
+
+use tracing::{debug, warn};
 
 /// A screen containts some kind of grid of cells, plus top
 /// level fields that are common to all screen variants.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Screen {
     // The actual storage for lines of cells. This will take
     // different forms depending on which type of screen this
@@ -38,11 +41,28 @@ pub struct Screen {
     /// The current position of the cursor within the in-view window described
     /// by `size`. (0,0) is the upper left.
     pub cursor: Pos,
+    /// Set after a write fills the last column of a line. The cursor
+    /// stays on the last column until the next printable char, which
+    /// wraps to the next line before being written. Any explicit cursor
+    /// movement cancels the pending wrap.
+    pub pending_wrap: bool,
+    /// The left and right margins set by DECSLRM, as a zero indexed
+    /// [left, right) column range. None means the margins are the edges
+    /// of the screen. These can only be set while DECLRMM is on.
+    pub margins: Option<(usize, usize)>,
     // The slot where cursor position info is saved by the SCP/RCP
     // and ESC 7 / ESC 8 commands.
     pub saved_cursor: SavedCursor,
+    /// The kitty keyboard protocol enhancement flag stack. The top of
+    /// the stack is at the end, and an empty stack means no flags are
+    /// enabled. Each screen gets its own stack, per the kitty spec.
+    keyboard_flags: Vec<u16>,
 }
 
+/// The max depth of the kitty keyboard flag stack. Pushing past this
+/// evicts the oldest entry.
+const KEYBOARD_FLAGS_STACK_DEPTH: usize = 16;
+
 impl Screen {
     /// Create a new scrollback mode screen (a regular terminal screen).
     pub fn scrollback(mut scrollback_lines: usize, size: crate::Size) -> Self {
@@ -54,7 +74,10 @@ impl Screen {
             grid: Grid::Scrollback(Scrollback::new(scrollback_lines)),
             size,
             cursor: Pos { row: 0, col: 0 },
+            pending_wrap: false,
+            margins: None,
             saved_cursor: SavedCursor::new(Pos { row: 0, col: 0 }),
+            keyboard_flags: vec![],
         }
     }
 
@@ -64,7 +87,10 @@ impl Screen {
             grid: Grid::AltScreen(AltScreen::new(size)),
             size,
             cursor: Pos { row: 0, col: 0 },
+            pending_wrap: false,
+            margins: None,
             saved_cursor: SavedCursor::new(Pos { row: 0, col: 0 }),
+            keyboard_flags: vec![],
         }
     }
 
@@ -87,6 +113,26 @@ impl Screen {
         }
     }
 
+    /// Return the logical line length cap iff this is a scrollback screen
+    /// and it has one.
+    pub fn max_line_len(&self) -> Option<usize> {
+        if let Grid::Scrollback(scrollback) = &self.grid {
+            scrollback.max_line_len()
+        } else {
+            None
+        }
+    }
+
+    /// Set the logical line length cap. Only works if this is a scrollback
+    /// screen.
+    pub fn set_max_line_len(&mut self, max_line_len: Option<usize>) {
+        if let Grid::Scrollback(scrollback) = &mut self.grid {
+            scrollback.set_max_line_len(max_line_len);
+        } else {
+            warn!("attempt to set max line length on non-scrollback screen");
+        }
+    }
+
     pub fn set_scroll_region(&mut self, scroll_region: ScrollRegion) {
         match &mut self.grid {
             Grid::Scrollback(scrollback) => scrollback.scroll_region = scroll_region,
@@ -94,6 +140,10 @@ impl Screen {
         }
     }
 
+    pub fn origin_mode(&self) -> OriginMode {
+        self.grid.origin_mode()
+    }
+
     pub fn set_origin_mode(&mut self, origin_mode: OriginMode) {
         match &mut self.grid {
             Grid::Scrollback(s) => s.origin_mode = origin_mode,
@@ -105,6 +155,7 @@ impl Screen {
     /// a CUP command, update the cursor position, taking the current origin
     /// mode and scroll region into account.
     pub fn set_cursor(&mut self, pos: Pos) {
+        self.pending_wrap = false;
         match self.grid.origin_mode() {
             OriginMode::Term => {
                 self.cursor.row = pos.row.saturating_sub(1);
@@ -123,37 +174,338 @@ impl Screen {
         }
     }
 
-    pub fn dump_contents_into(&self, buf: &mut Vec<u8>, dump_region: crate::ContentRegion) {
+    /// The cursor position as a 1 indexed pos in the same terms `set_cursor`
+    /// takes, so relative to the top of the scroll region in origin mode.
+    /// This is what a CPR reports.
+    pub fn reported_cursor(&self) -> Pos {
+        let top = match (self.grid.origin_mode(), self.grid.scroll_region()) {
+            (OriginMode::ScrollRegion, ScrollRegion::Window { top, .. }) => *top,
+            _ => 0,
+        };
+        Pos { row: self.cursor.row.saturating_sub(top) + 1, col: self.cursor.col + 1 }
+    }
+
+    /// Write the plain text of the given region into the buffer. The alt
+    /// screen has no scrollback, so it always writes the whole screen.
+    pub fn text_into(
+        &self,
+        buf: &mut String,
+        dump_region: crate::ContentRegion,
+        options: crate::TextOptions,
+    ) {
+        if let crate::ContentRegion::CursorContext { above, below } = dump_region {
+            let rows = self.cursor_context_rows(above, below);
+            line::text_lines_into(rows.filter_map(|row| self.line_at(row)), buf, options);
+            return;
+        }
+
+        match &self.grid {
+            Grid::Scrollback(scrollback) => {
+                line::text_lines_into(scrollback.lines_in(self.size, &dump_region), buf, options)
+            }
+            Grid::AltScreen(altscreen) => line::text_lines_into(altscreen.buf.iter(), buf, options),
+        }
+    }
+
+    /// The plain text of the idx'th logical line of the screen, counting
+    /// from the top of the scrollback.
+    pub fn logical_line(&self, idx: usize, options: crate::TextOptions) -> Option<String> {
+        let all = crate::ContentRegion::All;
         match &self.grid {
             Grid::Scrollback(scrollback) => {
-                scrollback.dump_contents_into(buf, self.size, dump_region)
+                line::logical_line_text(scrollback.lines_in(self.size, &all), idx, options)
+            }
+            Grid::AltScreen(altscreen) => {
+                line::logical_line_text(altscreen.buf.iter(), idx, options)
+            }
+        }
+    }
+
+    /// Copies of the lines making up the visible window, top to bottom,
+    /// with blank lines for rows that have not been written yet.
+    pub fn visible_lines(&self) -> Vec<Line> {
+        (0..self.size.height)
+            .map(|row| self.line_at(row).cloned().unwrap_or_else(Line::new))
+            .collect()
+    }
+
+    /// Redraw just the given (row, line) pairs of the visible window, then
+    /// put the cursor back. Origin mode and any margins get switched off
+    /// around the moves so that they are not relative to the scroll region
+    /// and don't confine the redrawn rows. Each row is erased before it
+    /// gets written, since erasing after a row that fills the width would
+    /// take out its last column.
+    pub fn dump_rows_into<'a, I>(&self, buf: &mut Vec<u8>, rows: I)
+    where
+        I: IntoIterator<Item = (usize, &'a Line)>,
+    {
+        let controls = term::control_codes();
+        let origin_mode = matches!(self.grid.origin_mode(), OriginMode::ScrollRegion);
+        if origin_mode {
+            controls.disable_scroll_region_origin_mode.term_input_into(buf);
+        }
+        if self.margins.is_some() {
+            controls.disable_lr_margin_mode.term_input_into(buf);
+        }
+
+        for (row, line) in rows {
+            term::ControlCodes::cursor_position((row + 1) as u16, 1).term_input_into(buf);
+            controls.erase_line.term_input_into(buf);
+            line.term_input_into(buf);
+        }
+
+        if let Some((left, right)) = self.margins {
+            controls.enable_lr_margin_mode.term_input_into(buf);
+            term::ControlCodes::set_margins((left + 1) as u16, right as u16).term_input_into(buf);
+        }
+        // Same as in a full dump, a pending wrap parks past the edge.
+        let col = if self.pending_wrap { self.size.width } else { self.cursor.col };
+        term::ControlCodes::cursor_position((self.cursor.row + 1) as u16, (col + 1) as u16)
+            .term_input_into(buf);
+        if origin_mode {
+            controls.enable_scroll_region_origin_mode.term_input_into(buf);
+        }
+    }
+
+    /// Copy the cells in the given region out into a grid with one entry
+    /// per row, padding each row out to the screen width with blanks.
+    /// Rows the screen has not written to yet come out blank too.
+    pub fn snapshot(&self, dump_region: crate::ContentRegion) -> Vec<Vec<Cell>> {
+        self.region_lines(dump_region)
+            .into_iter()
+            .map(|line| {
+                let mut row: Vec<Cell> = line
+                    .map(|l| l.cells.iter().take(self.size.width).cloned().collect())
+                    .unwrap_or_default();
+                row.resize(self.size.width, Cell::empty());
+                row
+            })
+            .collect()
+    }
+
+    /// Hash the glyphs and attributes in the given region. Short lines
+    /// and unwritten rows count as padded out with blanks just like in
+    /// `snapshot`, but no cells get copied.
+    pub fn content_hash(&self, dump_region: crate::ContentRegion) -> u64 {
+        use std::hash::{Hash, Hasher};
+
+        // DefaultHasher::new always uses the same keys, so hashes are
+        // comparable across terminals within a process.
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        let blank = Cell::empty();
+        for line in self.region_lines(dump_region) {
+            let cells = line.map(|l| &l.cells[..]).unwrap_or_default();
+            for col in 0..self.size.width {
+                cells.get(col).unwrap_or(&blank).hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
+
+    /// The lines making up the given region from top to bottom, with
+    /// None for rows that have never been written to.
+    fn region_lines(&self, dump_region: crate::ContentRegion) -> Vec<Option<&Line>> {
+        match (&self.grid, dump_region) {
+            (_, crate::ContentRegion::CursorContext { above, below }) => {
+                self.cursor_context_rows(above, below).map(|row| self.line_at(row)).collect()
+            }
+            (_, crate::ContentRegion::Screen) => {
+                (0..self.size.height).map(|row| self.line_at(row)).collect()
+            }
+            (Grid::Scrollback(scrollback), dump_region) => {
+                scrollback.lines_in(self.size, &dump_region).map(Some).collect()
+            }
+            (Grid::AltScreen(altscreen), _) => altscreen.buf.iter().map(Some).collect(),
+        }
+    }
+
+    /// Write out the given region, with the given overlay cells drawn over
+    /// the visible window. Only the rows the overlay lands on get copied,
+    /// so an overlay costs next to nothing on top of the dump itself.
+    pub fn dump_contents_into(
+        &self,
+        buf: &mut Vec<u8>,
+        dump_region: crate::ContentRegion,
+        overlay: &[(Pos, Cell)],
+    ) {
+        let overlaid = self.overlay_rows(overlay);
+        let swaps: Vec<(&Line, &Line)> = overlaid
+            .iter()
+            .filter_map(|(row, copy)| self.line_at(*row).map(|line| (line, copy)))
+            .collect();
+        // Overlay cells can land past the last line stored so far, in which
+        // case blank lines go in ahead of them just like writing there
+        // would add.
+        let stored_rows =
+            (0..self.size.height).take_while(|row| self.line_at(*row).is_some()).count();
+        let padded_rows = overlaid.iter().map(|(row, _)| row + 1).fold(stored_rows, std::cmp::max);
+        let blank = Line::new();
+        let visible: Vec<Option<&Line>> = (0..self.size.height)
+            .map(|row| match overlaid.iter().find(|(r, _)| *r == row) {
+                Some((_, copy)) => Some(copy),
+                None => self.line_at(row).or((row < padded_rows).then_some(&blank)),
+            })
+            .collect();
+        let padding = (stored_rows..padded_rows).filter_map(|row| visible[row]);
+
+        // The row the dumped lines start at, which the cursor position
+        // needs to be relative to.
+        let mut first_row = 0;
+        match (&self.grid, dump_region) {
+            (_, crate::ContentRegion::CursorContext { above, below }) => {
+                let rows = self.cursor_context_rows(above, below);
+                first_row = rows.start;
+                line::term_input_lines_into(rows.filter_map(|row| visible[row]), buf);
             }
-            Grid::AltScreen(altscreen) => altscreen.term_input_into(buf),
+            (Grid::Scrollback(scrollback), dump_region) => {
+                let lines = scrollback.lines_in(self.size, &dump_region);
+                let lines = lines.map(|line| swapped(&swaps, line)).chain(padding);
+                line::term_input_lines_into(lines, buf);
+                scrollback.dump_scroll_state_into(buf, self.size, dump_region);
+            }
+            (Grid::AltScreen(altscreen), _) => {
+                let lines = altscreen.buf.iter().map(|line| swapped(&swaps, line));
+                line::term_input_lines_into(lines, buf);
+                altscreen.scroll_region.term_input_into(buf);
+            }
+        }
+
+        // Real terminals confine printing to the margins, so they can only
+        // go in once the contents are down. Setting them homes the cursor.
+        if let Some((left, right)) = self.margins {
+            term::ControlCodes::set_margins((left + 1) as u16, right as u16).term_input_into(buf);
         }
 
-        term::ControlCodes::cursor_position(
-            (self.cursor.row + 1) as u16,
-            (self.cursor.col + 1) as u16,
-        )
-        .term_input_into(buf);
+        // There is no direct way to restore a pending wrap, so we park
+        // the cursor past the right edge, which is where it logically is.
+        let col = if self.pending_wrap { self.size.width } else { self.cursor.col };
+        let row = self.cursor.row - first_row;
+        term::ControlCodes::cursor_position((row + 1) as u16, (col + 1) as u16)
+            .term_input_into(buf);
 
         if matches!(self.grid.origin_mode(), OriginMode::ScrollRegion) {
             term::control_codes().enable_scroll_region_origin_mode.term_input_into(buf);
         }
     }
 
-    pub fn resize(&mut self, new_size: crate::Size) {
-        match &mut self.grid {
-            Grid::Scrollback(scrollback) => scrollback.reflow(new_size.width),
-            Grid::AltScreen(altscreen) => altscreen.resize(new_size),
+    /// Copies of the visible rows that the given overlay cells land on,
+    /// with the cells drawn over them. Cells outside of the visible window
+    /// are skipped.
+    fn overlay_rows(&self, overlay: &[(Pos, Cell)]) -> Vec<(usize, Line)> {
+        let width = self.size.width;
+        let mut rows: Vec<(usize, Line)> = vec![];
+        for (pos, cell) in overlay.iter() {
+            if pos.row >= self.size.height || pos.col >= width {
+                debug!("ignoring overlay cell outside the screen at {pos:?}");
+                continue;
+            }
+            let i = match rows.iter().position(|(row, _)| *row == pos.row) {
+                Some(i) => i,
+                None => {
+                    rows.push((pos.row, self.line_at(pos.row).cloned().unwrap_or_else(Line::new)));
+                    rows.len() - 1
+                }
+            };
+
+            let line = &mut rows[i].1;
+            let res = line.set_cell(width, pos.col, cell.clone()).and_then(|()| {
+                for col in (pos.col + 1)..std::cmp::min(pos.col + cell.width() as usize, width) {
+                    line.set_cell(width, col, Cell::wide_pad())?;
+                }
+                Ok(())
+            });
+            if let Err(e) = res {
+                warn!("setting overlay cell: {e:?}");
+            }
+        }
+        rows
+    }
+
+    /// Resize the screen. The alt_anchor is only consulted for alt
+    /// screens, since scrollback screens reflow their contents instead.
+    pub fn resize(&mut self, new_size: crate::Size, alt_anchor: crate::AltScreenAnchor) {
+        if let Grid::Scrollback(scrollback) = &mut self.grid {
+            scrollback.reflow(new_size.width);
+        }
+        self.resize_window(new_size, alt_anchor);
+        self.pending_wrap = false;
+    }
+
+    /// Change the size of the visible window without reflowing the
+    /// scrollback. This is only correct when the width stays the same,
+    /// since the stored line wrapping is left as is.
+    pub fn resize_window(&mut self, new_size: crate::Size, alt_anchor: crate::AltScreenAnchor) {
+        if let Grid::AltScreen(altscreen) = &mut self.grid {
+            let shift = altscreen.resize(new_size, alt_anchor, self.cursor.row);
+            self.cursor.row = self.cursor.row.saturating_add_signed(shift);
+            self.saved_cursor.pos.row = self.saved_cursor.pos.row.saturating_add_signed(shift);
         }
         self.size = new_size;
+        if matches!(self.margins, Some((_, right)) if right > self.size.width) {
+            self.margins = None;
+        }
+        if matches!(self.grid.scroll_region(), ScrollRegion::Window { bottom, .. } if *bottom > self.size.height)
+        {
+            self.set_scroll_region(ScrollRegion::TrackSize);
+        }
 
+        let cursor = self.cursor;
         self.cursor.clamp_to(self.size);
+        if self.cursor != cursor {
+            self.pending_wrap = false;
+        }
         self.saved_cursor.pos.clamp_to(self.size);
     }
 
+    /// The currently active kitty keyboard enhancement flags.
+    pub fn keyboard_flags(&self) -> u16 {
+        self.keyboard_flags.last().copied().unwrap_or(0)
+    }
+
+    /// The full kitty keyboard flag stack, bottom first.
+    pub fn keyboard_flags_stack(&self) -> &[u16] {
+        &self.keyboard_flags
+    }
+
+    /// Push a new set of kitty keyboard flags (`CSI > flags u`).
+    pub fn push_keyboard_flags(&mut self, flags: u16) {
+        if self.keyboard_flags.len() >= KEYBOARD_FLAGS_STACK_DEPTH {
+            self.keyboard_flags.remove(0);
+        }
+        self.keyboard_flags.push(flags);
+    }
+
+    /// Pop `n` entries off the kitty keyboard flag stack (`CSI < n u`).
+    pub fn pop_keyboard_flags(&mut self, n: usize) {
+        let new_len = self.keyboard_flags.len().saturating_sub(n);
+        self.keyboard_flags.truncate(new_len);
+    }
+
+    /// Modify the active kitty keyboard flags (`CSI = flags ; mode u`).
+    /// Mode 1 replaces the flags, mode 2 sets the given bits and mode 3
+    /// clears them.
+    pub fn set_keyboard_flags(&mut self, flags: u16, mode: u16) {
+        let current = self.keyboard_flags();
+        let new_flags = match mode {
+            1 => flags,
+            2 => current | flags,
+            3 => current & !flags,
+            _ => {
+                warn!("unknown keyboard flags mode {mode}");
+                return;
+            }
+        };
+        match self.keyboard_flags.last_mut() {
+            Some(top) => *top = new_flags,
+            None => self.keyboard_flags.push(new_flags),
+        }
+    }
+
+    /// Clamp the cursor to the screen (or the scroll region in origin
+    /// mode). This gets called after every explicit cursor movement, so
+    /// it also cancels any pending wrap.
     pub fn clamp(&mut self) {
+        self.pending_wrap = false;
         match &self.grid {
             Grid::Scrollback(scrollback) => {
                 scrollback.clamp_to_scroll_region(&mut self.cursor, &self.size)
@@ -174,7 +526,14 @@ impl Screen {
     // Control Code Handlers
     //
 
-    pub fn write_at_cursor(&mut self, cell: Cell) -> anyhow::Result<()> {
+    pub fn write_at_cursor(&mut self, cell: Cell) -> Result<(), GridError> {
+        if self.pending_wrap {
+            // Putting the cursor past the right edge makes the grid wrap
+            // before writing the cell.
+            self.cursor.col = self.size.width;
+            self.pending_wrap = false;
+        }
+
         self.cursor = match &mut self.grid {
             Grid::Scrollback(scrollback) => {
                 scrollback.write_at_cursor(self.size, self.cursor, cell)?
@@ -183,6 +542,52 @@ impl Screen {
                 altscreen.write_at_cursor(self.size, self.cursor, cell)?
             }
         };
+        if self.cursor.col >= self.size.width {
+            self.cursor.col = self.size.width.saturating_sub(1);
+            self.pending_wrap = true;
+        }
+
+        Ok(())
+    }
+
+    /// Write a run of printable ASCII at the cursor. This does the same
+    /// thing as writing each char with `write_at_cursor`, but once the
+    /// first char of a row is in place the rest of the row gets filled
+    /// in all at once.
+    pub fn write_ascii_run(
+        &mut self,
+        mut run: &[u8],
+        attrs: Arc<term::Attrs>,
+        protected: bool,
+    ) -> Result<(), GridError> {
+        let cell = |b: u8| {
+            let mut cell = Cell::with_shared_attrs(char::from(b), attrs.clone());
+            cell.set_protected(protected);
+            cell
+        };
+
+        while let Some((first, rest)) = run.split_first() {
+            // The first char goes the slow way, which takes care of
+            // wrapping and making sure there is a line to write to.
+            self.write_at_cursor(cell(*first))?;
+            let n = if self.pending_wrap {
+                0
+            } else {
+                std::cmp::min(self.size.width - self.cursor.col, rest.len())
+            };
+            if n > 0 {
+                let (width, cursor) = (self.size.width, self.cursor);
+                let line =
+                    self.line_at_mut(cursor.row).ok_or(GridError::MissingLine(cursor.row))?;
+                line.set_cells(width, cursor.col, rest[..n].iter().map(|b| cell(*b)))?;
+                self.cursor.col += n;
+                if self.cursor.col >= width {
+                    self.cursor.col = width - 1;
+                    self.pending_wrap = true;
+                }
+            }
+            run = &rest[n..];
+        }
 
         Ok(())
     }
@@ -207,27 +612,88 @@ impl Screen {
 
     /// Erase whichever screen is currently active, not including scrollback.
     /// Used to implement 'CSI 2 J' and 'CSI 3 J' (which includes the
-    /// scrollback).
+    /// scrollback). The alt screen has no scrollback, so 'CSI 3 J' leaves
+    /// it alone.
     pub fn erase(&mut self, include_scrollback: bool) {
         match &mut self.grid {
             Grid::Scrollback(s) => s.erase(self.size, include_scrollback),
+            Grid::AltScreen(_) if include_scrollback => {}
             Grid::AltScreen(alt) => alt.erase(),
         }
     }
 
+    /// Drop any scrollback above the visible window. The alt screen has
+    /// none, so this does nothing there.
+    pub fn clear_scrollback(&mut self) {
+        if let Grid::Scrollback(s) = &mut self.grid {
+            s.clear_history(self.size);
+        }
+    }
+
+    /// Get the cell at the given position within the visible window.
+    pub fn get_cell(&self, pos: Pos) -> Option<&Cell> {
+        if pos.row >= self.size.height || pos.col >= self.size.width {
+            return None;
+        }
+
+        let line = match &self.grid {
+            Grid::Scrollback(s) => s.get_line(self.size, pos.row),
+            Grid::AltScreen(alt) => alt.get_line(pos.row),
+        };
+        match line {
+            Some(line) => line.get_cell(self.size.width, pos.col),
+            // Rows that have never been written to are blank.
+            None => Some(cell::empty()),
+        }
+    }
+
+    /// The id of the line at the given row of the visible window, making
+    /// sure the row is backed by a line first. Only the main screen tracks
+    /// line ids, so this is always None for the alt screen.
+    pub fn line_id(&mut self, row: usize) -> Option<crate::LineId> {
+        match &mut self.grid {
+            Grid::Scrollback(s) => {
+                s.ensure_row(self.size, row);
+                s.line_id(self.size, row)
+            }
+            Grid::AltScreen(_) => None,
+        }
+    }
+
+    /// The id of the line at the given row of the visible window, or None
+    /// if there is no line there yet or this is the alt screen.
+    pub fn visible_line_id(&self, row: usize) -> Option<crate::LineId> {
+        match &self.grid {
+            Grid::Scrollback(s) => s.line_id(self.size, row),
+            Grid::AltScreen(_) => None,
+        }
+    }
+
+    /// The id of the oldest line still stored, or None for the alt screen.
+    pub fn oldest_line_id(&self) -> Option<crate::LineId> {
+        match &self.grid {
+            Grid::Scrollback(s) => Some(s.oldest_line_id()),
+            Grid::AltScreen(_) => None,
+        }
+    }
+
+    /// Selectively erase the given section of the given row, leaving
+    /// protected cells alone.
+    pub fn selective_erase(&mut self, row: usize, section: line::Section) {
+        if let Some(line) = self.line_at_mut(row) {
+            line.selective_erase(section);
+        }
+    }
+
     /// Gets the current line. If the cursor is not currently over an actual
     /// line, this returns nothing.
     pub fn get_line_mut(&mut self) -> Option<&mut Line> {
-        match &mut self.grid {
-            Grid::Scrollback(s) => s.get_line_mut(self.size, self.cursor.row),
-            Grid::AltScreen(alt) => Some(alt.get_line_mut(self.cursor.row)),
-        }
+        self.line_at_mut(self.cursor.row)
     }
 
     pub fn scroll_up(&mut self, n: usize) {
-        match &mut self.grid {
-            Grid::Scrollback(s) => s.scroll_up(n),
-            _ => {}
+        if let Grid::Scrollback(s) = &mut self.grid {
+            s.scroll_up(n);
         }
     }
 
@@ -251,6 +717,168 @@ impl Screen {
         }
     }
 
+    /// Handler for DECIC (Insert Column, CSI n ' }).
+    ///
+    /// n blank columns are inserted at the cursor column in every line of
+    /// the scroll region, pushing cells to the right and dropping any that
+    /// fall off the right edge. The new cells get the given attributes so
+    /// the current background carries over.
+    pub fn insert_columns(&mut self, attrs: &term::Attrs, n: usize) {
+        let col = self.cursor.col;
+        let (left, right) = self.margin_cols();
+        if col < left || col >= right {
+            return;
+        }
+        for row in self.scroll_region_rows() {
+            if let Some(line) = self.line_at_mut(row) {
+                line.within_right_margin(right, |l| l.insert_blanks(right, col, attrs, n));
+            }
+        }
+    }
+
+    /// Handler for DECDC (Delete Column, CSI n ' ~).
+    ///
+    /// n columns starting at the cursor column are deleted from every line
+    /// of the scroll region, sucking cells to the right leftwards and
+    /// backfilling the right edge with blanks that have the given
+    /// attributes.
+    pub fn delete_columns(&mut self, attrs: &term::Attrs, n: usize) {
+        let col = self.cursor.col;
+        let (left, right) = self.margin_cols();
+        if col < left || col >= right {
+            return;
+        }
+        for row in self.scroll_region_rows() {
+            if let Some(line) = self.line_at_mut(row) {
+                line.within_right_margin(right, |l| l.delete_character(right, col, attrs, n));
+            }
+        }
+    }
+
+    /// Handler for SL (Scroll Left, CSI n SP @).
+    ///
+    /// Every line of the scroll region is shifted n columns to the left,
+    /// dropping cells that fall off the left edge and filling in blanks
+    /// with the given attributes on the right.
+    pub fn scroll_left(&mut self, attrs: &term::Attrs, n: usize) {
+        let (left, right) = self.margin_cols();
+        for row in self.scroll_region_rows() {
+            if let Some(line) = self.line_at_mut(row) {
+                line.within_right_margin(right, |l| l.delete_character(right, left, attrs, n));
+            }
+        }
+    }
+
+    /// Handler for SR (Scroll Right, CSI n SP A).
+    ///
+    /// Every line of the scroll region is shifted n columns to the right,
+    /// dropping cells that fall off the right edge and filling in blanks
+    /// with the given attributes on the left.
+    pub fn scroll_right(&mut self, attrs: &term::Attrs, n: usize) {
+        let (left, right) = self.margin_cols();
+        for row in self.scroll_region_rows() {
+            if let Some(line) = self.line_at_mut(row) {
+                line.within_right_margin(right, |l| l.insert_blanks(right, left, attrs, n));
+            }
+        }
+    }
+
+    /// Handler for ICH (Insert Character, CSI n @).
+    ///
+    /// n blanks with the given attributes are inserted at the cursor,
+    /// pushing cells to the right and dropping any that go past the right
+    /// margin. Nothing happens if the cursor is outside the left and right
+    /// margins.
+    pub fn insert_chars(&mut self, attrs: &term::Attrs, n: usize) {
+        let col = self.cursor.col;
+        let (left, right) = self.margin_cols();
+        if col < left || col >= right {
+            return;
+        }
+        // Same as with DCH, blanks with a background show up even on a
+        // row that has never been written to.
+        if let Grid::Scrollback(s) = &mut self.grid {
+            s.ensure_row(self.size, self.cursor.row);
+        }
+        if let Some(line) = self.get_line_mut() {
+            line.within_right_margin(right, |l| l.insert_character(right, col, attrs, n));
+        }
+    }
+
+    /// Handler for DCH (Delete Character, CSI n P).
+    ///
+    /// n cells are deleted at the cursor, sucking cells left from as far
+    /// as the right margin and backfilling with blanks that have the given
+    /// attributes. Nothing happens if the cursor is outside the left and
+    /// right margins.
+    pub fn delete_chars(&mut self, attrs: &term::Attrs, n: usize) {
+        let col = self.cursor.col;
+        let (left, right) = self.margin_cols();
+        if col < left || col >= right {
+            return;
+        }
+        // The backfill is visible even on a row that has never been
+        // written to, just like on the alt screen.
+        if let Grid::Scrollback(s) = &mut self.grid {
+            s.ensure_row(self.size, self.cursor.row);
+        }
+        if let Some(line) = self.get_line_mut() {
+            line.within_right_margin(right, |l| l.delete_character(right, col, attrs, n));
+        }
+    }
+
+    /// The [left, right) columns that horizontal edits are confined to.
+    /// This is the full width unless DECSLRM margins are set.
+    pub fn margin_cols(&self) -> (usize, usize) {
+        self.margins.unwrap_or((0, self.size.width))
+    }
+
+    // Column operations are bounded vertically by the scroll region and
+    // horizontally by `margin_cols`.
+    fn scroll_region_rows(&self) -> std::ops::Range<usize> {
+        let (top, bottom) = self.scroll_region(false).as_region(&self.size).row_bounds();
+        top..bottom
+    }
+
+    /// The cell just before the cursor, which is the last one written
+    /// if the cursor has not moved since. Wide chars are found by their
+    /// first column.
+    pub fn prev_cell_mut(&mut self) -> Option<&mut Cell> {
+        let mut col =
+            if self.pending_wrap { self.cursor.col } else { self.cursor.col.checked_sub(1)? };
+        let row = self.cursor.row;
+        let line = self.line_at_mut(row)?;
+        while col > 0 && line.cells.get(col).is_some_and(|c| c.is_wide_pad()) {
+            col -= 1;
+        }
+        line.cells.get_mut(col).filter(|c| !c.is_blank())
+    }
+
+    /// The rows of the visible window from `above` rows above the cursor
+    /// to `below` rows below it, clamped to the window.
+    fn cursor_context_rows(&self, above: usize, below: usize) -> std::ops::Range<usize> {
+        let start = self.cursor.row.saturating_sub(above);
+        let end = std::cmp::min(
+            self.cursor.row.saturating_add(below).saturating_add(1),
+            self.size.height,
+        );
+        start..end
+    }
+
+    fn line_at(&self, row: usize) -> Option<&Line> {
+        match &self.grid {
+            Grid::Scrollback(s) => s.get_line(self.size, row),
+            Grid::AltScreen(alt) => alt.get_line(row),
+        }
+    }
+
+    fn line_at_mut(&mut self, row: usize) -> Option<&mut Line> {
+        match &mut self.grid {
+            Grid::Scrollback(s) => s.get_line_mut(self.size, row),
+            Grid::AltScreen(alt) => alt.get_line_mut(row),
+        }
+    }
+
     /// Handler for the Insert Line command (CSI n L).
     ///
     /// n lines are inserted above the current line, dropping any lines that
@@ -262,6 +890,81 @@ impl Screen {
         }
     }
 
+    /// Handler for DECDHL, DECSWL and DECDWL (`ESC # 3` through `ESC # 6`),
+    /// which set the size of the cursor line.
+    pub fn set_line_size(&mut self, line_size: term::LineSize) {
+        if let Grid::Scrollback(s) = &mut self.grid {
+            s.ensure_row(self.size, self.cursor.row);
+        }
+        if let Some(line) = self.get_line_mut() {
+            line.line_size = line_size;
+        }
+    }
+
+    /// Move the cursor down a row for a line feed. At the bottom margin of
+    /// the scroll region the region scrolls up instead, which on the main
+    /// screen with no scroll region set pushes the top line of the window
+    /// into the scrollback. Outside of the region the cursor just stops
+    /// at the last row.
+    pub fn index(&mut self) {
+        let (top, bottom) = self.scroll_region(false).as_region(&self.size).row_bounds();
+        let within_scroll = top <= self.cursor.row && self.cursor.row < bottom;
+        self.pending_wrap = false;
+        if within_scroll && self.cursor.row + 1 >= bottom {
+            self.scroll_down(1);
+        } else {
+            self.cursor.row += 1;
+            self.clamp();
+        }
+    }
+
+    /// Handler for RI (Reverse Index, ESC M).
+    ///
+    /// Moves the cursor up a row. At the top margin of the scroll region
+    /// the region scrolls down instead, with a blank line coming in at the
+    /// top and the bottom line of the region falling off. Rows outside
+    /// the region are never touched.
+    pub fn reverse_index(&mut self) {
+        self.pending_wrap = false;
+        let (top, _) = self.scroll_region(false).as_region(&self.size).row_bounds();
+        if self.cursor.row == top {
+            // Shuffling in a line at the top margin is exactly IL 1.
+            self.insert_lines(1);
+        } else if self.cursor.row > 0 {
+            self.cursor.row -= 1;
+        }
+    }
+
+    /// Handler for DECBI (Back Index, ESC 6).
+    ///
+    /// Moves the cursor left a column. At the left margin the lines of
+    /// the scroll region scroll right instead, with a blank column coming
+    /// in at the margin.
+    pub fn back_index(&mut self, attrs: &term::Attrs) {
+        self.pending_wrap = false;
+        let (left, _) = self.margin_cols();
+        if self.cursor.col == left {
+            self.scroll_right(attrs, 1);
+        } else if self.cursor.col > 0 {
+            self.cursor.col -= 1;
+        }
+    }
+
+    /// Handler for DECFI (Forward Index, ESC 9).
+    ///
+    /// Moves the cursor right a column. At the right margin the lines of
+    /// the scroll region scroll left instead, with a blank column coming
+    /// in at the margin.
+    pub fn forward_index(&mut self, attrs: &term::Attrs) {
+        self.pending_wrap = false;
+        let (_, right) = self.margin_cols();
+        if self.cursor.col + 1 == right {
+            self.scroll_left(attrs, 1);
+        } else if self.cursor.col + 1 < self.size.width {
+            self.cursor.col += 1;
+        }
+    }
+
     /// Handler for the Delete Line command (CSI n M).
     ///
     /// n lines below the current line are deleted (including the current line),
@@ -280,7 +983,7 @@ impl std::fmt::Display for Screen {
         for _ in 0..self.size.width {
             write!(f, "-")?;
         }
-        writeln!(f, "")?;
+        writeln!(f)?;
 
         match &self.grid {
             Grid::Scrollback(s) => write!(f, "{}", s)?,
@@ -301,15 +1004,25 @@ impl std::fmt::Display for Screen {
 pub struct SavedCursor {
     pub pos: Pos,
     pub attrs: term::Attrs,
+    pub origin_mode: OriginMode,
+    /// The G0 and G1 designations along with which one was in GL.
+    pub charsets: [term::Charset; 2],
+    pub gl_charset: usize,
 }
 
 impl SavedCursor {
     pub fn new(pos: Pos) -> Self {
-        SavedCursor { pos, attrs: term::Attrs::default() }
+        SavedCursor {
+            pos,
+            attrs: term::Attrs::default(),
+            origin_mode: OriginMode::default(),
+            charsets: [term::Charset::default(); 2],
+            gl_charset: 0,
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Grid {
     Scrollback(Scrollback),
     AltScreen(AltScreen),
@@ -331,16 +1044,49 @@ impl Grid {
     }
 }
 
+/// The overlaid copy of `line` if there is one, or else `line` itself.
+/// Lines are matched up by address, since the same line can show up at
+/// different positions depending on the region being dumped.
+fn swapped<'a>(swaps: &[(&'a Line, &'a Line)], line: &'a Line) -> &'a Line {
+    swaps.iter().find(|(orig, _)| std::ptr::eq(*orig, line)).map_or(line, |(_, copy)| copy)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::term::Attrs;
-    use crate::Size;
+    use crate::{AltScreenAnchor, Size};
+
+    #[test]
+    fn write_zero_width() {
+        for mut screen in [
+            Screen::scrollback(10, Size { width: 0, height: 5 }),
+            Screen::alt(Size { width: 0, height: 5 }),
+        ] {
+            match screen.write_at_cursor(Cell::new('a', Attrs::default())) {
+                Err(e @ GridError::ZeroWidth) => assert!(e.to_string().contains("zero width")),
+                r => panic!("expected zero width error, got {r:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn write_million_cells() {
+        let mut screen = Screen::scrollback(1000, Size { width: 80, height: 24 });
+        let start = std::time::Instant::now();
+        for i in 0..1_000_000 {
+            let c = char::from(b'a' + (i % 26) as u8);
+            screen.write_at_cursor(Cell::new(c, Attrs::default())).unwrap();
+        }
+        // Very loose so that slow debug builds on busy machines still pass,
+        // but far below what a quadratic write path would take.
+        assert!(start.elapsed() < std::time::Duration::from_secs(30));
+    }
 
     #[test]
     fn altscreen_resize_grow_height() {
         let mut screen = Screen::alt(Size { width: 10, height: 5 });
-        screen.resize(Size { width: 10, height: 10 });
+        screen.resize(Size { width: 10, height: 10 }, AltScreenAnchor::Top);
 
         match &screen.grid {
             Grid::AltScreen(alt) => {
@@ -354,7 +1100,7 @@ mod tests {
     #[test]
     fn altscreen_resize_shrink_height() {
         let mut screen = Screen::alt(Size { width: 10, height: 10 });
-        screen.resize(Size { width: 10, height: 5 });
+        screen.resize(Size { width: 10, height: 5 }, AltScreenAnchor::Top);
         match &screen.grid {
             Grid::AltScreen(alt) => {
                 assert_eq!(alt.buf.len(), 5);
@@ -376,7 +1122,7 @@ mod tests {
             _ => panic!("wrong grid type"),
         }
 
-        screen.resize(Size { width: 5, height: 5 });
+        screen.resize(Size { width: 5, height: 5 }, AltScreenAnchor::Top);
         assert_eq!(screen.size.width, 5);
 
         // Line should be truncated
@@ -388,20 +1134,113 @@ mod tests {
         }
     }
 
+    #[test]
+    fn altscreen_erase_with_cursor_past_bottom() {
+        let size = Size { width: 5, height: 3 };
+        let mut screen = Screen::alt(size);
+        for c in "abcdefghijklmn".chars() {
+            screen.write_at_cursor(Cell::new(c, Attrs::default())).unwrap();
+        }
+
+        // Park the cursor just past the bottom row, where a write that
+        // wrapped could have left it.
+        screen.cursor = Pos { row: size.height, col: 2 };
+        assert!(screen.get_line_mut().is_none());
+        screen.erase_to_end();
+        screen.erase_from_start();
+        match &screen.grid {
+            Grid::AltScreen(alt) => assert!(alt.buf.iter().all(|l| l.cells.is_empty())),
+            _ => panic!("wrong grid type"),
+        }
+    }
+
     #[test]
     fn altscreen_cursor_clamping() {
         let mut screen = Screen::alt(Size { width: 10, height: 10 });
         screen.cursor = Pos { row: 9, col: 9 };
         screen.saved_cursor.pos = Pos { row: 8, col: 8 };
 
-        screen.resize(Size { width: 5, height: 5 });
+        screen.resize(Size { width: 5, height: 5 }, AltScreenAnchor::Top);
 
+        // The cursor row is never dropped, so the top of the screen gets
+        // trimmed and the saved cursor moves up along with its content.
         assert_eq!(screen.cursor.row, 4);
         assert_eq!(screen.cursor.col, 4);
-        assert_eq!(screen.saved_cursor.pos.row, 4);
+        assert_eq!(screen.saved_cursor.pos.row, 3);
         assert_eq!(screen.saved_cursor.pos.col, 4);
     }
 
+    fn alt_rows(screen: &Screen) -> Vec<String> {
+        match &screen.grid {
+            Grid::AltScreen(alt) => {
+                alt.buf.iter().map(|l| format!("{}", l).trim_end().into()).collect()
+            }
+            _ => panic!("wrong grid type"),
+        }
+    }
+
+    fn numbered_alt_screen(size: Size) -> Screen {
+        let mut screen = Screen::alt(size);
+        match &mut screen.grid {
+            Grid::AltScreen(alt) => {
+                for (i, line) in alt.buf.iter_mut().enumerate() {
+                    let c = char::from_digit(i as u32, 10).unwrap();
+                    line.set_cell(size.width, 0, Cell::new(c, Attrs::default())).unwrap();
+                }
+            }
+            _ => panic!("wrong grid type"),
+        }
+        screen
+    }
+
+    #[test]
+    fn altscreen_resize_top_anchor_with_content() {
+        let mut screen = numbered_alt_screen(Size { width: 5, height: 5 });
+        screen.cursor = Pos { row: 1, col: 0 };
+
+        screen.resize(Size { width: 5, height: 3 }, AltScreenAnchor::Top);
+        assert_eq!(alt_rows(&screen), vec!["0", "1", "2"]);
+        assert_eq!(screen.cursor, Pos { row: 1, col: 0 });
+
+        screen.resize(Size { width: 5, height: 5 }, AltScreenAnchor::Top);
+        assert_eq!(alt_rows(&screen), vec!["0", "1", "2", "", ""]);
+        assert_eq!(screen.cursor, Pos { row: 1, col: 0 });
+    }
+
+    #[test]
+    fn altscreen_resize_top_anchor_keeps_cursor_row() {
+        let mut screen = numbered_alt_screen(Size { width: 5, height: 5 });
+        screen.cursor = Pos { row: 3, col: 0 };
+
+        screen.resize(Size { width: 5, height: 2 }, AltScreenAnchor::Top);
+        assert_eq!(alt_rows(&screen), vec!["2", "3"]);
+        assert_eq!(screen.cursor, Pos { row: 1, col: 0 });
+    }
+
+    #[test]
+    fn altscreen_resize_bottom_anchor_with_content() {
+        let mut screen = numbered_alt_screen(Size { width: 5, height: 5 });
+        screen.cursor = Pos { row: 4, col: 0 };
+
+        screen.resize(Size { width: 5, height: 3 }, AltScreenAnchor::Bottom);
+        assert_eq!(alt_rows(&screen), vec!["2", "3", "4"]);
+        assert_eq!(screen.cursor, Pos { row: 2, col: 0 });
+
+        screen.resize(Size { width: 5, height: 5 }, AltScreenAnchor::Bottom);
+        assert_eq!(alt_rows(&screen), vec!["", "", "2", "3", "4"]);
+        assert_eq!(screen.cursor, Pos { row: 4, col: 0 });
+    }
+
+    #[test]
+    fn altscreen_resize_bottom_anchor_keeps_cursor_row() {
+        let mut screen = numbered_alt_screen(Size { width: 5, height: 5 });
+        screen.cursor = Pos { row: 1, col: 0 };
+
+        screen.resize(Size { width: 5, height: 3 }, AltScreenAnchor::Bottom);
+        assert_eq!(alt_rows(&screen), vec!["1", "2", "3"]);
+        assert_eq!(screen.cursor, Pos { row: 0, col: 0 });
+    }
+
     fn get_screen_cell(screen: &Screen, row: usize, col: usize) -> Option<Cell> {
         match &screen.grid {
             Grid::Scrollback(sb) => sb
@@ -530,7 +1369,7 @@ mod tests {
 
         // Resize to width 5. Should split into "01234" and "56789"
         let new_size = Size { width: 5, height: 5 };
-        screen.resize(new_size);
+        screen.resize(new_size, AltScreenAnchor::Top);
 
         // "56789" should be at row 1 (since it wrapped)
         // "01234" should be at row 0
@@ -576,7 +1415,7 @@ mod tests {
 
         // Resize to width 10. Should merge back to "0123456789"
         let new_size = Size { width: 10, height: 5 };
-        screen.resize(new_size);
+        screen.resize(new_size, AltScreenAnchor::Top);
 
         // Should all be on top line (Row 0)
         assert_eq!(
@@ -618,10 +1457,10 @@ mod tests {
             }
 
             // Resize
-            screen.resize(Size { width: end_w, height: 10 });
+            screen.resize(Size { width: end_w, height: 10 }, AltScreenAnchor::Top);
 
             // Resize back
-            screen.resize(start_size);
+            screen.resize(start_size, AltScreenAnchor::Top);
 
             // Verify content is identical to if we just pushed it
             let mut expected_screen = Screen::scrollback(100, start_size);
@@ -18,13 +18,13 @@
 
 use crate::{
     cell::Cell,
+    error::GridError,
     line::{self, Line},
     term::{self, AsTermInput, OriginMode, Pos, ScrollRegion},
     ContentRegion,
 };
 use std::collections::VecDeque;
 
-use anyhow::{anyhow, Context};
 use tracing::{error, warn};
 
 // A scrollback stores the termianal state for the main screen.
@@ -46,6 +46,12 @@ pub struct Scrollback {
     /// This is set by DECSTBM (CSI n ; n r).
     pub scroll_region: ScrollRegion,
     pub origin_mode: OriginMode,
+    /// The number of lines that have ever been added to the buffer. This
+    /// is what gives each line its `LineId`.
+    lines_added: u64,
+    /// The most cells a single logical line may span before we force
+    /// a hard break instead of soft wrapping onto the next row.
+    max_line_len: Option<usize>,
 }
 
 impl std::fmt::Display for Scrollback {
@@ -67,6 +73,8 @@ impl Scrollback {
             lines: scrollback_lines,
             scroll_region: ScrollRegion::default(),
             origin_mode: OriginMode::default(),
+            lines_added: 0,
+            max_line_len: None,
         }
     }
 
@@ -90,8 +98,32 @@ impl Scrollback {
         self.lines = scrollback_lines;
     }
 
+    pub fn max_line_len(&self) -> Option<usize> {
+        self.max_line_len
+    }
+
+    /// Set the most cells a logical line may span. Lines that are
+    /// already longer are left alone, the cap only applies to future
+    /// wrapping.
+    pub fn set_max_line_len(&mut self, max_line_len: Option<usize>) {
+        self.max_line_len = max_line_len;
+    }
+
+    /// Returns true if a logical line ending at the given row has room
+    /// to soft wrap onto one more row without going over `max_line_len`.
+    fn can_wrap(&self, size: crate::Size, row: usize) -> bool {
+        let Some(max) = self.max_line_len else {
+            return true;
+        };
+        let Some(idx) = self.idx_from_bottom(size, row) else {
+            return true;
+        };
+        let rows = 1 + self.buf.iter().skip(idx + 1).take_while(|line| line.is_wrapped).count();
+        (rows + 1) * size.width <= max
+    }
+
     /// Set the cell at the given grid coordinates.
-    pub fn set(&mut self, size: crate::Size, pos: Pos, cell: Cell) -> anyhow::Result<()> {
+    pub fn set(&mut self, size: crate::Size, pos: Pos, cell: Cell) -> Result<(), GridError> {
         if let Some(line) = self.get_line_mut(size, pos.row) {
             return line.set_cell(size.width, pos.col, cell);
         }
@@ -99,8 +131,20 @@ impl Scrollback {
         Ok(())
     }
 
+    /// Make sure there is a line backing the given row of the visible
+    /// window, adding blank lines at the bottom as needed.
+    pub fn ensure_row(&mut self, size: crate::Size, row: usize) {
+        if row >= size.height {
+            return;
+        }
+        while self.get_line(size, row).is_none() && self.buf.len() < self.lines {
+            self.add_line(Line::new());
+        }
+    }
+
     fn add_line(&mut self, line: Line) {
         self.buf.push_front(line);
+        self.lines_added += 1;
         while self.buf.len() > self.lines {
             self.buf.pop_back();
         }
@@ -117,36 +161,22 @@ impl Scrollback {
         }
     }
 
-    pub fn dump_contents_into(
+    /// Write out the scroll region, and the scroll offset if the region
+    /// includes the lines below the visible window. These go after the
+    /// lines of `lines_in`, which the screen takes care of.
+    pub fn dump_scroll_state_into(
         &self,
         buf: &mut Vec<u8>,
         size: crate::Size,
         dump_region: ContentRegion,
     ) {
-        let lines_iter: Box<dyn Iterator<Item = (usize, &Line)>> = match dump_region {
-            ContentRegion::All => Box::new(self.buf.iter().enumerate().rev()),
-            ContentRegion::Screen => Box::new(
-                self.buf.iter().skip(self.scroll_offset).take(size.height).enumerate().rev(),
-            ),
-            ContentRegion::BottomLines(nlines) => {
-                Box::new(self.buf.iter().take(nlines).enumerate().rev())
-            }
-        };
-
-        for (i, line) in lines_iter {
-            line.term_input_into(buf);
-            if i != 0 {
-                term::Crlf::default().term_input_into(buf);
-            }
-        }
-
         self.scroll_region.term_input_into(buf);
 
         let generate_scroll = self.scroll_offset > 0
             && matches!(self.scroll_region, ScrollRegion::TrackSize)
             && match dump_region {
                 ContentRegion::All => true,
-                ContentRegion::Screen => false,
+                ContentRegion::Screen | ContentRegion::CursorContext { .. } => false,
                 ContentRegion::BottomLines(n) => n >= size.height + self.scroll_offset,
             };
         if generate_scroll {
@@ -154,6 +184,24 @@ impl Scrollback {
         }
     }
 
+    /// The lines in the given region, from top to bottom.
+    pub fn lines_in(
+        &self,
+        size: crate::Size,
+        region: &ContentRegion,
+    ) -> Box<dyn Iterator<Item = &Line> + '_> {
+        match region {
+            ContentRegion::All => Box::new(self.buf.iter().rev()),
+            ContentRegion::Screen => {
+                Box::new(self.buf.iter().skip(self.scroll_offset).take(size.height).rev())
+            }
+            ContentRegion::BottomLines(nlines) => Box::new(self.buf.iter().take(*nlines).rev()),
+            // The screen picks out the rows around the cursor itself, since
+            // we don't know where the cursor is.
+            ContentRegion::CursorContext { .. } => self.lines_in(size, &ContentRegion::Screen),
+        }
+    }
+
     pub fn reflow(&mut self, new_width: usize) {
         let mut new_scrollback = VecDeque::with_capacity(self.buf.len());
         let mut logical_line = VecDeque::new();
@@ -164,48 +212,56 @@ impl Scrollback {
             if !is_wrapped {
                 // We've gotten to the end of the logical line. We now
                 // need to chop it up into grid lines by the new width.
-                let mut line = Line::new();
-                while let Some(chunk) = logical_line.pop_front() {
-                    let remainder = new_width - line.cells.len();
-                    if chunk.cells.len() < remainder {
-                        line.cells.extend_from_slice(chunk.cells.as_slice());
-
-                        if line.cells.len() == new_width {
-                            new_scrollback.push_front(line);
-                            line = Line::new();
-                        }
-                    } else {
-                        // Complete the partial line.
-                        line.cells.extend_from_slice(&chunk.cells[..remainder]);
-                        line.is_wrapped = chunk.cells.len() > remainder || !logical_line.is_empty();
-                        new_scrollback.push_front(line);
-                        line = Line::new();
-
-                        let remaining_chunks: Vec<_> =
-                            chunk.cells[remainder..].chunks(new_width).collect();
-                        for (i, c) in remaining_chunks.iter().enumerate() {
-                            line.cells.extend_from_slice(c);
-                            if i < remaining_chunks.len() - 1 {
-                                line.is_wrapped = true;
-                            } else {
-                                line.is_wrapped = !logical_line.is_empty();
-                            }
-
-                            if line.cells.len() == new_width {
-                                new_scrollback.push_front(line);
-                                line = Line::new();
-                            }
-                        }
-                    }
+                let logical_width: usize = logical_line.iter().map(Line::display_width).sum();
+                let cells = logical_line.drain(..).flat_map(|l| l.cells);
+                if logical_width <= new_width {
+                    // It all fits on one row, which also covers blank
+                    // logical lines, since those are still a row on the
+                    // screen even though they have no cells.
+                    new_scrollback.push_front(Line {
+                        cells: cells.collect(),
+                        is_wrapped: false,
+                        line_size: term::LineSize::Single,
+                    });
+                    continue;
                 }
 
-                if line.cells.len() != 0 {
-                    new_scrollback.push_front(line);
+                let mut line = Line::new();
+                let mut line_width = 0;
+                for cell in cells {
+                    // A wide cell that would straddle the edge moves down
+                    // to the next row along with its pads, which have no
+                    // width of their own.
+                    let cell_width = cell.columns();
+                    if line_width + cell_width > new_width && !line.cells.is_empty() {
+                        line.is_wrapped = true;
+                        new_scrollback.push_front(std::mem::replace(&mut line, Line::new()));
+                        line_width = 0;
+                    }
+                    line.cells.push(cell);
+                    line_width += cell_width;
                 }
+                new_scrollback.push_front(line);
             }
         }
 
         self.buf = new_scrollback;
+        // Reflow splits and joins lines, so ids above the bottom line
+        // only stay roughly in place. Just make sure every line still
+        // has one.
+        self.lines_added = std::cmp::max(self.lines_added, self.buf.len() as u64);
+    }
+
+    /// The id of the line at the given row of the visible window, if
+    /// there is a line there.
+    pub fn line_id(&self, size: crate::Size, row: usize) -> Option<crate::LineId> {
+        self.idx_from_bottom(size, row).map(|i| self.lines_added - 1 - i as u64)
+    }
+
+    /// The id of the oldest line still stored. Every line with a smaller
+    /// id has been dropped off the top of the scrollback.
+    pub fn oldest_line_id(&self) -> crate::LineId {
+        self.lines_added - self.buf.len() as u64
     }
 
     // Resolve a logical offset in the visible grid to an actual Line.
@@ -217,7 +273,6 @@ impl Scrollback {
         }
     }
 
-    #[allow(dead_code)]
     pub fn get_line(&self, size: crate::Size, row: usize) -> Option<&Line> {
         if let Some(i) = self.idx_from_bottom(size, row) {
             Some(&self.buf[i])
@@ -257,20 +312,27 @@ impl Scrollback {
         size: crate::Size,
         mut cursor: Pos,
         cell: Cell,
-    ) -> anyhow::Result<Pos> {
+    ) -> Result<Pos, GridError> {
         if size.width < 1 {
-            return Err(anyhow!("cannot write to zero width terminal grid"));
+            return Err(GridError::ZeroWidth);
         }
 
         // We do the wrapping before writing a cell rather than after
         // doing so to allow the user to avoid setting the wrap bit
         // by entering \r\n right after writing the very rightmost
-        // cell.
-        if cursor.col >= size.width {
+        // cell. A wide character that does not have room for all of
+        // its columns on the current line wraps the same way a narrow
+        // character written past the right margin does, so the cursor
+        // column always advances by the full cell width.
+        if cursor.col + cell.width() as usize > size.width {
+            // A program that never sends a newline could otherwise build
+            // one enormous logical line, which makes reflow and dumps slow,
+            // so past the cap we break the line for real.
+            let wrap = self.can_wrap(size, cursor.row);
             if let Some(line) = self.get_line_mut(size, cursor.row) {
-                line.is_wrapped = true;
+                line.is_wrapped = wrap;
             } else {
-                return Err(anyhow!("unexpectedly missing line when setting wrap marker"));
+                return Err(GridError::MissingLine(cursor.row));
             }
 
             cursor.col = 0;
@@ -284,37 +346,20 @@ impl Scrollback {
         }
 
         assert!(self.lines >= size.height);
+        // Rows between the last line and the cursor are blank rows on the
+        // screen, so they become real (unwrapped) blank lines. Reflow keeps
+        // them as is, so they never merge with their neighbors.
         while self.buf.len() < cursor.row + 1 {
-            // TODO: these lines will all count as having
-            // not been wrapped and will be retained on reflow.
-            // Is that actually what we want?
             self.add_line(Line::new());
         }
 
-        if cursor.col + cell.width() as usize >= size.width + 1 {
-            if let Some(line) = self.get_line_mut(size, cursor.row) {
-                line.is_wrapped = true;
-            } else {
-                return Err(anyhow!(
-                    "unexpectedly missing line when setting wide char wrap marker"
-                ));
-            }
-
-            cursor.col = 0;
-            cursor.row += 1;
-
-            if self.buf.len() < cursor.row + 1 {
-                self.add_line(Line::new())
-            }
-        }
-
         let mut npad = cell.width().saturating_sub(1);
-        self.set(size, cursor, cell).context("setting main cell")?;
+        self.set(size, cursor, cell)?;
         cursor.col += 1;
         while npad > 0 {
             assert!(cursor.col < size.width);
 
-            self.set(size, cursor, Cell::wide_pad()).context("padding after wide char")?;
+            self.set(size, cursor, Cell::wide_pad())?;
             cursor.col += 1;
             npad -= 1;
         }
@@ -337,7 +382,7 @@ impl Scrollback {
         };
         for i in cursor.row + 1..end {
             if let Some(snip_line) = self.get_line_mut(size, i) {
-                snip_line.erase(line::Section::Whole);
+                snip_line.clear();
             }
         }
     }
@@ -350,7 +395,7 @@ impl Scrollback {
 
         for i in start..cursor.row {
             if let Some(snip_line) = self.get_line_mut(size, i) {
-                snip_line.erase(line::Section::Whole);
+                snip_line.clear();
             }
         }
         if let Some(snip_line) = self.get_line_mut(size, cursor.row) {
@@ -371,11 +416,18 @@ impl Scrollback {
 
         for i in start..end {
             if let Some(snip_line) = self.get_line_mut(size, i) {
-                snip_line.erase(line::Section::Whole);
+                snip_line.clear();
             }
         }
     }
 
+    /// Drop every line above the visible window, leaving the window
+    /// itself alone. Line ids of the remaining lines don't change.
+    pub fn clear_history(&mut self, size: crate::Size) {
+        let keep = self.lines_below_grid_start(size);
+        self.buf.truncate(keep);
+    }
+
     pub fn scroll_up(&mut self, n: usize) {
         self.scroll_offset += n;
         if self.scroll_offset > self.lines {
@@ -386,7 +438,10 @@ impl Scrollback {
     pub fn scroll_down(&mut self, size: &crate::Size, n: usize) {
         match self.scroll_region {
             ScrollRegion::TrackSize => {
-                for _ in 0..n.saturating_sub(self.scroll_offset) {
+                // Adding more lines than the scrollback can hold just pushes
+                // every old line out, so there is no need to go further.
+                let new_lines = std::cmp::min(n.saturating_sub(self.scroll_offset), self.lines);
+                for _ in 0..new_lines {
                     self.add_line(Line::new());
                 }
                 self.scroll_offset = self.scroll_offset.saturating_sub(n);
@@ -426,112 +481,44 @@ impl Scrollback {
     }
 
     pub fn insert_lines(&mut self, cursor: &Pos, size: &crate::Size, n: usize) {
-        let bottom = match self.scroll_region {
-            ScrollRegion::TrackSize => size.height,
-            ScrollRegion::Window { top, bottom } => {
-                if cursor.row < top || bottom <= cursor.row {
-                    // Insert Line does nothing when the cursor is outside
-                    // the scroll region.
-                    return;
-                }
-                bottom
-            }
-        };
-
-        let row_idx = match self.idx_from_bottom(*size, cursor.row) {
-            Some(r) => r,
-            // If the cursor is pointing past the point where we have
-            // data, inserting blanks below the current line is a no-op,
-            // no matter how many we are inserting.
-            None => return,
-        };
-
-        // The lines below the cursor. N.B. this is stored in
-        // reverse order from how you normally visualize it.
-        let mut lines_below_cursor = Vec::with_capacity(row_idx);
-        for _ in 0..=row_idx {
-            if let Some(l) = self.buf.pop_front() {
-                lines_below_cursor.push(l);
-            } else {
-                error!("internal error: row idx computed incorrectly");
-            }
-        }
-
-        let lines_to_insert = std::cmp::min(n, bottom - cursor.row);
-        for _ in 0..lines_to_insert {
-            self.buf.push_front(Line::new());
-        }
-
-        // Up until the bottom of the scroll region, backfill
-        // from the end of the lines_below_cursor vec.
-        let backfill_to_bottom = (bottom - cursor.row) - lines_to_insert;
-        for i in 0..backfill_to_bottom {
-            let take_idx = lines_below_cursor.len() - 1 - i;
-            self.buf.push_front(std::mem::replace(&mut lines_below_cursor[take_idx], Line::new()));
-        }
-
-        // Past the scroll region, backfill from the start of the
-        // lines_below_cursor vec.
-        let backfill_past_scroll_region = size.height - bottom;
-        for i in 0..backfill_past_scroll_region {
-            let take_idx = backfill_past_scroll_region - 1 - i;
-            self.buf.push_front(std::mem::replace(&mut lines_below_cursor[take_idx], Line::new()));
-        }
+        self.shift_lines(cursor, *size, |region, at| line::insert_lines(region, at, n));
     }
 
     pub fn delete_lines(&mut self, cursor: &Pos, size: &crate::Size, n: usize) {
-        let bottom = match self.scroll_region {
-            ScrollRegion::TrackSize => size.height,
-            ScrollRegion::Window { top, bottom } => {
-                if cursor.row < top || bottom <= cursor.row {
-                    // Insert Line does nothing when the cursor is outside
-                    // the scroll region.
-                    return;
-                }
-                bottom
-            }
-        };
+        self.shift_lines(cursor, *size, |region, at| line::delete_lines(region, at, n));
+    }
 
-        let row_idx = match self.idx_from_bottom(*size, cursor.row) {
-            Some(r) => r,
-            // If the cursor is pointing past the point where we have
-            // data, inserting blanks below the current line is a no-op,
-            // no matter how many we are inserting.
-            None => return,
+    /// Run one of the shared IL / DL shuffles over the in-view scroll
+    /// region. Does nothing if the cursor is outside the scroll region or
+    /// below the point where we have data, since the lines being shuffled
+    /// would all be blank anyway.
+    fn shift_lines<F>(&mut self, cursor: &Pos, size: crate::Size, shift: F)
+    where
+        F: FnOnce(&mut [Line], usize),
+    {
+        let (top, bottom) = match self.scroll_region {
+            ScrollRegion::TrackSize => (0, size.height),
+            ScrollRegion::Window { top, bottom } => (top, std::cmp::min(bottom, size.height)),
         };
-
-        // The lines below the cursor. N.B. this is stored in
-        // reverse order from how you normally visualize it.
-        let mut lines_below_cursor = Vec::with_capacity(row_idx);
-        for _ in 0..=row_idx {
-            if let Some(l) = self.buf.pop_front() {
-                lines_below_cursor.push(l);
-            } else {
-                error!("internal error: row idx computed incorrectly");
-            }
-        }
-
-        let lines_to_delete = std::cmp::min(n, bottom - cursor.row);
-
-        // Replace the undeleted lines from the scrollback region.
-        let undeleted_lines_in_scrollback_buf = (bottom - cursor.row) - lines_to_delete;
-        for i in 0..undeleted_lines_in_scrollback_buf {
-            let take_idx = lines_below_cursor.len() - lines_to_delete - 1 - i;
-            self.buf.push_front(std::mem::replace(&mut lines_below_cursor[take_idx], Line::new()));
+        if cursor.row < top || bottom <= cursor.row || self.get_line(size, cursor.row).is_none() {
+            return;
         }
 
-        // Inject the blank lines we need to put in at the bottom of the scrollback
-        // region.
-        for _ in 0..lines_to_delete {
-            self.buf.push_front(Line::new());
-        }
+        // Lines may get shuffled down into rows we don't have storage
+        // for yet.
+        self.ensure_row(size, bottom - 1);
+        let (Some(bottom_idx), Some(top_idx)) =
+            (self.idx_from_bottom(size, bottom - 1), self.idx_from_bottom(size, top))
+        else {
+            error!("internal error: scroll region rows missing after ensure_row");
+            return;
+        };
 
-        // Past the scroll region, backfill from the start of the
-        // lines_below_cursor vec.
-        let backfill_past_scroll_region = size.height - bottom;
-        for i in 0..backfill_past_scroll_region {
-            let take_idx = backfill_past_scroll_region - 1 - i;
-            self.buf.push_front(std::mem::replace(&mut lines_below_cursor[take_idx], Line::new()));
-        }
+        // The buffer is stored bottom first, so flip the region around
+        // to hand the shuffle a top to bottom view.
+        let region = &mut self.buf.make_contiguous()[bottom_idx..=top_idx];
+        region.reverse();
+        shift(region, cursor.row - top);
+        region.reverse();
     }
 }
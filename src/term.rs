@@ -40,25 +40,56 @@ pub struct Pos {
 impl Pos {
     /// Ensure that the cursor is within the given region
     /// by moving to the closest edge if it is out of bounds.
+    ///
+    /// An empty region pins the position to its low edge.
     pub fn clamp_to<R>(&mut self, region: R)
     where
         R: Region,
     {
         let (low_row, high_row) = region.row_bounds();
+        if self.row >= high_row {
+            self.row = high_row.saturating_sub(1);
+        }
         if self.row < low_row {
             self.row = low_row;
         }
-        if self.row >= high_row {
-            self.row = high_row - 1;
-        }
 
         let (low_col, high_col) = region.col_bounds();
+        if self.col >= high_col {
+            self.col = high_col.saturating_sub(1);
+        }
         if self.col < low_col {
             self.col = low_col;
         }
-        if self.col >= high_col {
-            self.col = high_col - 1;
-        }
+    }
+
+    /// A copy of this position clamped to the given region.
+    pub fn clamped<R>(mut self, region: R) -> Pos
+    where
+        R: Region,
+    {
+        self.clamp_to(region);
+        self
+    }
+
+    /// Step up n rows, stopping at row 0.
+    pub fn up(self, n: usize) -> Pos {
+        Pos { row: self.row.saturating_sub(n), col: self.col }
+    }
+
+    /// Step down n rows. The result is not clamped to any region.
+    pub fn down(self, n: usize) -> Pos {
+        Pos { row: self.row.saturating_add(n), col: self.col }
+    }
+
+    /// Step left n cols, stopping at col 0.
+    pub fn left(self, n: usize) -> Pos {
+        Pos { row: self.row, col: self.col.saturating_sub(n) }
+    }
+
+    /// Step right n cols. The result is not clamped to any region.
+    pub fn right(self, n: usize) -> Pos {
+        Pos { row: self.row, col: self.col.saturating_add(n) }
     }
 }
 
@@ -152,10 +183,211 @@ pub enum OriginMode {
     ScrollRegion,
 }
 
+/// A character set that can be designated into one of the G0/G1 slots
+/// via `ESC ( <c>` or `ESC ) <c>`. Characters printed while a slot is
+/// shifted into GL get translated through the charset in that slot.
+#[derive(Debug, Eq, PartialEq, Clone, Default, Copy)]
+pub enum Charset {
+    /// Plain ASCII (`ESC ( B`). Characters pass through unchanged.
+    #[default]
+    Ascii,
+    /// The VT100 line drawing set (`ESC ( 0`).
+    DecSpecialGraphics,
+}
+
+impl Charset {
+    /// Get the charset named by the final byte of a designation escape.
+    /// National replacement sets and the like are treated as ASCII.
+    pub fn from_designator(byte: u8) -> Self {
+        match byte {
+            b'0' => Charset::DecSpecialGraphics,
+            _ => Charset::Ascii,
+        }
+    }
+
+    /// The final byte used to designate this charset.
+    pub fn designator(&self) -> u8 {
+        match self {
+            Charset::Ascii => b'B',
+            Charset::DecSpecialGraphics => b'0',
+        }
+    }
+
+    /// Map a printed character through this charset.
+    pub fn translate(&self, c: char) -> char {
+        match self {
+            Charset::Ascii => c,
+            Charset::DecSpecialGraphics => match c {
+                '_' => ' ',
+                '`' => '◆',
+                'a' => '▒',
+                'b' => '␉',
+                'c' => '␌',
+                'd' => '␍',
+                'e' => '␊',
+                'f' => '°',
+                'g' => '±',
+                'h' => '␤',
+                'i' => '␋',
+                'j' => '┘',
+                'k' => '┐',
+                'l' => '┌',
+                'm' => '└',
+                'n' => '┼',
+                'o' => '⎺',
+                'p' => '⎻',
+                'q' => '─',
+                'r' => '⎼',
+                's' => '⎽',
+                't' => '├',
+                'u' => '┤',
+                'v' => '┴',
+                'w' => '┬',
+                'x' => '│',
+                'y' => '≤',
+                'z' => '≥',
+                '{' => 'π',
+                '|' => '≠',
+                '}' => '£',
+                '~' => '·',
+                _ => c,
+            },
+        }
+    }
+}
+
+/// The size a line is displayed at, as set by DECDHL (`ESC # 3` and
+/// `ESC # 4`), DECSWL (`ESC # 5`) and DECDWL (`ESC # 6`). We only track
+/// this so it survives a dump; cells are laid out the same either way.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum LineSize {
+    #[default]
+    Single,
+    DoubleHeightTop,
+    DoubleHeightBottom,
+    DoubleWidth,
+}
+
+impl LineSize {
+    /// Get the size selected by the final byte of an `ESC #` sequence.
+    pub fn from_final_byte(byte: u8) -> Option<Self> {
+        match byte {
+            b'3' => Some(LineSize::DoubleHeightTop),
+            b'4' => Some(LineSize::DoubleHeightBottom),
+            b'5' => Some(LineSize::Single),
+            b'6' => Some(LineSize::DoubleWidth),
+            _ => None,
+        }
+    }
+
+    /// The code that gives the cursor line this size.
+    pub fn code(&self) -> ControlCode {
+        let byte = match self {
+            LineSize::DoubleHeightTop => b'3',
+            LineSize::DoubleHeightBottom => b'4',
+            LineSize::Single => b'5',
+            LineSize::DoubleWidth => b'6',
+        };
+        ControlCode::ESC { intermediates: smallvec![b'#'], byte }
+    }
+}
+
+/// The cursor shape as set by DECSCUSR (`CSI n SP q`).
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum CursorStyle {
+    /// The default shape, selected by both 0 and 1.
+    #[default]
+    BlinkingBlock,
+    SteadyBlock,
+    BlinkingUnderline,
+    SteadyUnderline,
+    BlinkingBar,
+    SteadyBar,
+}
+
+impl CursorStyle {
+    /// Get the style selected by a DECSCUSR param, or None if the param
+    /// is out of range.
+    pub fn from_param(n: u16) -> Option<Self> {
+        match n {
+            0 | 1 => Some(CursorStyle::BlinkingBlock),
+            2 => Some(CursorStyle::SteadyBlock),
+            3 => Some(CursorStyle::BlinkingUnderline),
+            4 => Some(CursorStyle::SteadyUnderline),
+            5 => Some(CursorStyle::BlinkingBar),
+            6 => Some(CursorStyle::SteadyBar),
+            _ => None,
+        }
+    }
+
+    /// The shape of the cursor, regardless of blinking.
+    pub fn shape(&self) -> CursorShape {
+        match self {
+            CursorStyle::BlinkingBlock | CursorStyle::SteadyBlock => CursorShape::Block,
+            CursorStyle::BlinkingUnderline | CursorStyle::SteadyUnderline => CursorShape::Underline,
+            CursorStyle::BlinkingBar | CursorStyle::SteadyBar => CursorShape::Bar,
+        }
+    }
+
+    /// Indicates if DECSCUSR asked for a blinking cursor. The odd params
+    /// blink and the even ones are steady. This is separate from the
+    /// `CSI ? 12` blink mode.
+    pub fn is_blinking(&self) -> bool {
+        matches!(
+            self,
+            CursorStyle::BlinkingBlock | CursorStyle::BlinkingUnderline | CursorStyle::BlinkingBar
+        )
+    }
+
+    /// The DECSCUSR param that selects this style.
+    pub fn param(&self) -> u16 {
+        match self {
+            CursorStyle::BlinkingBlock => 1,
+            CursorStyle::SteadyBlock => 2,
+            CursorStyle::BlinkingUnderline => 3,
+            CursorStyle::SteadyUnderline => 4,
+            CursorStyle::BlinkingBar => 5,
+            CursorStyle::SteadyBar => 6,
+        }
+    }
+}
+
+/// The shape part of a `CursorStyle`.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum CursorShape {
+    Block,
+    Underline,
+    Bar,
+}
+
 pub trait AsTermInput {
     fn term_input_into(&self, buf: &mut Vec<u8>);
 }
 
+impl<T: AsTermInput + ?Sized> AsTermInput for &T {
+    fn term_input_into(&self, buf: &mut Vec<u8>) {
+        (**self).term_input_into(buf);
+    }
+}
+
+/// A sequence of inputs serializes as each of them in order.
+impl<T: AsTermInput> AsTermInput for [T] {
+    fn term_input_into(&self, buf: &mut Vec<u8>) {
+        for item in self {
+            item.term_input_into(buf);
+        }
+    }
+}
+
+/// Serialize a batch of inputs that need not share a type, such as a
+/// mix of control codes and raw text, into a single buffer.
+#[allow(dead_code)]
+pub fn term_input_all(items: &[&dyn AsTermInput]) -> Vec<u8> {
+    let mut buf = vec![];
+    items.term_input_into(&mut buf);
+    buf
+}
+
 #[derive(Debug)]
 #[must_use = "this struct does nothing unless you call term_input_into"]
 pub struct Raw {
@@ -181,7 +413,7 @@ impl AsTermInput for Raw {
     }
 }
 
-#[derive(Default, Debug, Eq, PartialEq, Clone)]
+#[derive(Default, Debug, Eq, PartialEq, Clone, Hash)]
 #[must_use = "this struct does nothing unless you call term_input_into"]
 pub struct Attrs {
     pub fgcolor: Color,
@@ -195,35 +427,82 @@ pub struct Attrs {
     pub strikethrough: bool,
     pub framed: Option<FrameStyle>,
     pub overline: bool,
+    /// The alternate font selected by SGR 11 through 19, or 0 for the
+    /// primary font. Hardly anything renders these, but we hang on to
+    /// them so they survive a dump.
+    pub font: u8,
     // The link this cell points to, if any. Set by OSC 8.
     pub link_target: Option<LinkTarget>,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Hash)]
 pub struct LinkTarget {
     pub params: SmallVec<[u8; 8]>,
     pub url: SmallVec<[u8; 8]>,
+    /// The `id=` entry from params, if there was one. Runs of cells with
+    /// the same url and id are one logical link even if they are not
+    /// adjacent (say because the link wrapped or got redrawn piecemeal),
+    /// so terminals highlight them together on hover.
+    pub id: Option<SmallVec<[u8; 8]>>,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+impl LinkTarget {
+    /// Make a link target, pulling the id out of the `:` separated
+    /// `key=value` params.
+    pub fn new(params: &[u8], url: &[u8]) -> Self {
+        let id = params
+            .split(|b| *b == b':')
+            .find_map(|kv| kv.strip_prefix(b"id="))
+            .map(SmallVec::from_slice);
+        LinkTarget { params: SmallVec::from_slice(params), url: SmallVec::from_slice(url), id }
+    }
+
+    /// Indicates if the two targets belong to the same logical link.
+    /// Anonymous links never group with anything else.
+    pub fn same_link(&self, other: &LinkTarget) -> bool {
+        self.id.is_some() && self.id == other.id && self.url == other.url
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Hash)]
 pub enum UnderlineStyle {
     Single,
     Double,
+    Curly,
+    Dotted,
+    Dashed,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+impl UnderlineStyle {
+    /// The code that selects this style. The colon form styles replace
+    /// whatever underline was there before, but the legacy double
+    /// underline (`CSI 21 m`) is tracked apart from the others, so
+    /// switching into or out of it needs an `undo_underline` first.
+    fn code(&self) -> ControlCode {
+        let controls = control_codes();
+        match self {
+            UnderlineStyle::Single => controls.underline.clone(),
+            UnderlineStyle::Double => controls.double_underline.clone(),
+            UnderlineStyle::Curly => ControlCodes::underline_style(3),
+            UnderlineStyle::Dotted => ControlCodes::underline_style(4),
+            UnderlineStyle::Dashed => ControlCodes::underline_style(5),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Hash)]
 pub enum FontWeight {
     Bold,
     Faint,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Hash)]
 pub enum BlinkStyle {
     Slow,
     Rapid,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Hash)]
 pub enum FrameStyle {
     Frame,
     Circle,
@@ -250,7 +529,10 @@ impl std::fmt::Display for Attrs {
         match self.underline {
             Some(UnderlineStyle::Single) => write!(f, "_")?,
             Some(UnderlineStyle::Double) => write!(f, "‗")?,
-            _ => {}
+            Some(UnderlineStyle::Curly) => write!(f, "﹏")?,
+            Some(UnderlineStyle::Dotted) => write!(f, "┈")?,
+            Some(UnderlineStyle::Dashed) => write!(f, "╌")?,
+            None => {}
         }
         if self.inverse {
             write!(f, "<")?;
@@ -274,6 +556,9 @@ impl std::fmt::Display for Attrs {
         if self.overline {
             write!(f, "‾")?;
         }
+        if self.font != 0 {
+            write!(f, "<F{}>", self.font)?;
+        }
 
         if let Some(link_target) = &self.link_target {
             write!(
@@ -289,6 +574,13 @@ impl std::fmt::Display for Attrs {
 }
 
 impl Attrs {
+    /// The attributes that blank cells get when erased while these are
+    /// the current attributes. Real terminals carry the background color
+    /// over to erased cells (background color erase) but nothing else.
+    pub fn erase_attrs(&self) -> Attrs {
+        Attrs { bgcolor: self.bgcolor, ..Attrs::default() }
+    }
+
     pub fn has_attrs(&self) -> bool {
         !matches!(self.fgcolor, Color::Default)
             || !matches!(self.bgcolor, Color::Default)
@@ -301,9 +593,32 @@ impl Attrs {
             || self.strikethrough
             || self.framed.is_some()
             || self.overline
+            || self.font != 0
             || self.link_target.is_some()
     }
 
+    /// The SGR params that select these attributes after a reset, as in
+    /// `0;1;31`. This is what a DECRQSS query for SGR gets back. Links
+    /// are set with OSC 8 rather than SGR, so they are left out.
+    pub fn sgr_params(&self) -> Vec<u8> {
+        let mut buf = vec![b'0'];
+        let next = Attrs { link_target: None, ..self.clone() };
+        for code in Attrs::default().transition_to(&next) {
+            if let ControlCode::CSI { params, action: 'm', .. } = code {
+                for param in params.iter() {
+                    buf.push(b';');
+                    for (i, subparam) in param.iter().enumerate() {
+                        if i != 0 {
+                            buf.push(b':');
+                        }
+                        extend_itoa(&mut buf, *subparam);
+                    }
+                }
+            }
+        }
+        buf
+    }
+
     /// Given another set of attributes, generate the minimal control codes
     /// which will transition the terminal to the other set of attributes
     /// from this one.
@@ -329,18 +644,14 @@ impl Attrs {
         match (&self.underline, &next.underline) {
             (None, None) => {}
             (Some(_), None) => codes.push(controls.undo_underline.clone()),
-            (None, Some(style)) => match style {
-                UnderlineStyle::Single => codes.push(controls.underline.clone()),
-                UnderlineStyle::Double => codes.push(controls.double_underline.clone()),
-            },
             (Some(old), Some(new)) if old == new => {}
-            (Some(_), Some(style)) => {
+            (Some(old), Some(new))
+                if *old == UnderlineStyle::Double || *new == UnderlineStyle::Double =>
+            {
                 codes.push(controls.undo_underline.clone());
-                match style {
-                    UnderlineStyle::Single => codes.push(controls.underline.clone()),
-                    UnderlineStyle::Double => codes.push(controls.double_underline.clone()),
-                }
+                codes.push(new.code());
             }
+            (_, Some(style)) => codes.push(style.code()),
         }
 
         if self.inverse && !next.inverse {
@@ -418,6 +729,10 @@ impl Attrs {
             codes.push(controls.overline.clone());
         }
 
+        if self.font != next.font {
+            codes.push(ControlCodes::select_font(next.font));
+        }
+
         match (&self.link_target, &next.link_target) {
             (None, None) => {}
             (Some(_), None) => codes.push(controls.end_link.clone()),
@@ -488,13 +803,46 @@ pub struct ControlCodes {
     pub end_link: ControlCode,
     pub show_cursor: ControlCode,
     pub hide_cursor: ControlCode,
-    pub enable_application_keypad_mode: ControlCode,
-    pub disable_application_keypad_mode: ControlCode,
+    pub enable_application_cursor_keys: ControlCode,
+    pub disable_application_cursor_keys: ControlCode,
+    pub keypad_application_mode: ControlCode,
+    pub select_7bit_controls: ControlCode,
+    pub select_8bit_controls: ControlCode,
+    pub keypad_numeric_mode: ControlCode,
+    pub reverse_index: ControlCode,
+    pub back_index: ControlCode,
+    pub forward_index: ControlCode,
     pub enable_paste_mode: ControlCode,
     pub disable_paste_mode: ControlCode,
+    pub enable_insert_mode: ControlCode,
+    pub disable_insert_mode: ControlCode,
+    pub enable_newline_mode: ControlCode,
+    pub disable_newline_mode: ControlCode,
+    pub enable_lr_margin_mode: ControlCode,
+    pub disable_lr_margin_mode: ControlCode,
+    pub enable_132_column_mode: ControlCode,
+    pub disable_132_column_mode: ControlCode,
+    pub enable_reverse_wraparound: ControlCode,
+    pub disable_reverse_wraparound: ControlCode,
+    pub enable_cursor_blink: ControlCode,
+    pub disable_cursor_blink: ControlCode,
+    pub enable_reverse_video: ControlCode,
+    pub disable_reverse_video: ControlCode,
     pub horizontal_tab_set: ControlCode,
     pub soft_reset: ControlCode,
     pub hard_reset: ControlCode,
+    pub primary_device_attributes: ControlCode,
+    pub primary_device_attributes_reply: ControlCode,
+    pub tertiary_device_attributes: ControlCode,
+    pub tertiary_device_attributes_reply: ControlCode,
+    pub identify_terminal: ControlCode,
+    pub request_cursor_position: ControlCode,
+    pub reset_key_modifier_options: ControlCode,
+    pub query_keyboard_flags: ControlCode,
+    pub select_latin1: ControlCode,
+    pub select_utf8: ControlCode,
+    pub shift_out: ControlCode,
+    pub shift_in: ControlCode,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -513,6 +861,15 @@ pub enum ControlCode {
         intermediates: SmallVec<[u8; 8]>,
         byte: u8,
     },
+    DCS {
+        params: SmallVec<[u16; 4]>,
+        intermediates: SmallVec<[u8; 8]>,
+        action: char,
+        data: SmallVec<[u8; 8]>,
+    },
+    C0 {
+        byte: u8,
+    },
     __NonExhaustive,
 }
 
@@ -552,7 +909,8 @@ impl AsTermInput for ControlCode {
             }
             ControlCode::CSI { params, intermediates, action } => {
                 buf.extend_from_slice(b"\x1b["); // CSI
-                buf.extend_from_slice(intermediates);
+                let (private_markers, intermediates) = split_csi_intermediates(intermediates);
+                buf.extend_from_slice(private_markers);
 
                 for (i, param) in params.iter().enumerate() {
                     if i != 0 {
@@ -567,6 +925,7 @@ impl AsTermInput for ControlCode {
                     }
                 }
 
+                buf.extend_from_slice(intermediates);
                 let mut action_buf = [0; 4];
                 buf.extend_from_slice(action.encode_utf8(&mut action_buf).as_bytes());
             }
@@ -575,6 +934,21 @@ impl AsTermInput for ControlCode {
                 buf.extend_from_slice(intermediates);
                 buf.push(*byte);
             }
+            ControlCode::DCS { params, intermediates, action, data } => {
+                buf.extend_from_slice(b"\x1bP"); // DCS
+                for (i, param) in params.iter().enumerate() {
+                    if i != 0 {
+                        buf.push(b';');
+                    }
+                    extend_itoa(buf, *param);
+                }
+                buf.extend_from_slice(intermediates);
+                let mut action_buf = [0; 4];
+                buf.extend_from_slice(action.encode_utf8(&mut action_buf).as_bytes());
+                buf.extend_from_slice(data);
+                buf.extend_from_slice(b"\x1b\\"); // ST
+            }
+            ControlCode::C0 { byte } => buf.push(*byte),
             _ => {}
         }
     }
@@ -594,8 +968,9 @@ impl std::fmt::Display for ControlCode {
         match self {
             ControlCode::CSI { params, intermediates, action } => {
                 write!(f, "CSI ")?;
-                for intermediate in intermediates {
-                    write!(f, "{} ", *intermediate as char)?;
+                let (private_markers, intermediates) = split_csi_intermediates(intermediates);
+                for marker in private_markers {
+                    write!(f, "{} ", *marker as char)?;
                 }
                 for (i, param) in params.iter().enumerate() {
                     if i != 0 {
@@ -608,6 +983,9 @@ impl std::fmt::Display for ControlCode {
                         write!(f, "{} ", subparam)?;
                     }
                 }
+                for intermediate in intermediates {
+                    write!(f, "{} ", *intermediate as char)?;
+                }
                 write!(f, "{}", action)?;
             }
             ControlCode::ESC { intermediates, byte } => {
@@ -617,6 +995,7 @@ impl std::fmt::Display for ControlCode {
                 }
                 write!(f, "{}", byte)?;
             }
+            ControlCode::C0 { byte } => write!(f, "C0 {:#04x}", byte)?,
             _ => write!(f, "<display unimpl>")?,
         }
 
@@ -625,6 +1004,26 @@ impl std::fmt::Display for ControlCode {
 }
 
 impl ControlCode {
+    /// Like `term_input_into`, but introducing the code with a single 8-bit
+    /// C1 byte (0x9B for CSI, 0x90 for DCS, 0x9D for OSC) and ending
+    /// strings with the C1 ST (0x9C). Plain escapes have no C1 form and
+    /// come out just as they would normally.
+    pub fn term_input_8bit_into(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        self.term_input_into(buf);
+        let (introducer, has_st) = match self {
+            ControlCode::CSI { .. } => (0x9b, false),
+            ControlCode::DCS { .. } => (0x90, true),
+            ControlCode::OSC { term, .. } => (0x9d, *term == OSCTerm::St),
+            _ => return,
+        };
+        buf.splice(start..start + 2, [introducer]);
+        if has_st {
+            buf.truncate(buf.len() - 2);
+            buf.push(0x9c);
+        }
+    }
+
     fn fuse_csi<I>(control_codes: I) -> Vec<Self>
     where
         I: IntoIterator<Item = Self>,
@@ -910,16 +1309,23 @@ pub fn control_codes() -> &'static ControlCodes {
             intermediates: smallvec![b'?'],
             action: 'l',
         },
-        enable_application_keypad_mode: ControlCode::CSI {
+        enable_application_cursor_keys: ControlCode::CSI {
             params: smallvec![smallvec![1]],
             intermediates: smallvec![b'?'],
             action: 'h',
         },
-        disable_application_keypad_mode: ControlCode::CSI {
+        disable_application_cursor_keys: ControlCode::CSI {
             params: smallvec![smallvec![1]],
             intermediates: smallvec![b'?'],
             action: 'l',
         },
+        keypad_application_mode: ControlCode::ESC { intermediates: smallvec![], byte: b'=' },
+        keypad_numeric_mode: ControlCode::ESC { intermediates: smallvec![], byte: b'>' },
+        reverse_index: ControlCode::ESC { intermediates: smallvec![], byte: b'M' },
+        back_index: ControlCode::ESC { intermediates: smallvec![], byte: b'6' },
+        forward_index: ControlCode::ESC { intermediates: smallvec![], byte: b'9' },
+        select_7bit_controls: ControlCode::ESC { intermediates: smallvec![b' '], byte: b'F' },
+        select_8bit_controls: ControlCode::ESC { intermediates: smallvec![b' '], byte: b'G' },
         enable_paste_mode: ControlCode::CSI {
             params: smallvec![smallvec![2004]],
             intermediates: smallvec![b'?'],
@@ -930,6 +1336,76 @@ pub fn control_codes() -> &'static ControlCodes {
             intermediates: smallvec![b'?'],
             action: 'l',
         },
+        enable_insert_mode: ControlCode::CSI {
+            params: smallvec![smallvec![4]],
+            intermediates: smallvec![],
+            action: 'h',
+        },
+        disable_insert_mode: ControlCode::CSI {
+            params: smallvec![smallvec![4]],
+            intermediates: smallvec![],
+            action: 'l',
+        },
+        enable_newline_mode: ControlCode::CSI {
+            params: smallvec![smallvec![20]],
+            intermediates: smallvec![],
+            action: 'h',
+        },
+        disable_newline_mode: ControlCode::CSI {
+            params: smallvec![smallvec![20]],
+            intermediates: smallvec![],
+            action: 'l',
+        },
+        enable_lr_margin_mode: ControlCode::CSI {
+            params: smallvec![smallvec![69]],
+            intermediates: smallvec![b'?'],
+            action: 'h',
+        },
+        disable_lr_margin_mode: ControlCode::CSI {
+            params: smallvec![smallvec![69]],
+            intermediates: smallvec![b'?'],
+            action: 'l',
+        },
+        enable_132_column_mode: ControlCode::CSI {
+            params: smallvec![smallvec![3]],
+            intermediates: smallvec![b'?'],
+            action: 'h',
+        },
+        disable_132_column_mode: ControlCode::CSI {
+            params: smallvec![smallvec![3]],
+            intermediates: smallvec![b'?'],
+            action: 'l',
+        },
+        enable_reverse_wraparound: ControlCode::CSI {
+            params: smallvec![smallvec![45]],
+            intermediates: smallvec![b'?'],
+            action: 'h',
+        },
+        disable_reverse_wraparound: ControlCode::CSI {
+            params: smallvec![smallvec![45]],
+            intermediates: smallvec![b'?'],
+            action: 'l',
+        },
+        enable_cursor_blink: ControlCode::CSI {
+            params: smallvec![smallvec![12]],
+            intermediates: smallvec![b'?'],
+            action: 'h',
+        },
+        disable_cursor_blink: ControlCode::CSI {
+            params: smallvec![smallvec![12]],
+            intermediates: smallvec![b'?'],
+            action: 'l',
+        },
+        enable_reverse_video: ControlCode::CSI {
+            params: smallvec![smallvec![5]],
+            intermediates: smallvec![b'?'],
+            action: 'h',
+        },
+        disable_reverse_video: ControlCode::CSI {
+            params: smallvec![smallvec![5]],
+            intermediates: smallvec![b'?'],
+            action: 'l',
+        },
         horizontal_tab_set: ControlCode::ESC { intermediates: smallvec![], byte: b'H' },
         soft_reset: ControlCode::CSI {
             params: smallvec![],
@@ -937,11 +1413,135 @@ pub fn control_codes() -> &'static ControlCodes {
             action: 'p',
         },
         hard_reset: ControlCode::ESC { intermediates: smallvec![], byte: b'c' },
+        primary_device_attributes: ControlCode::CSI {
+            params: smallvec![],
+            intermediates: smallvec![],
+            action: 'c',
+        },
+        // VT220 with ANSI color.
+        primary_device_attributes_reply: ControlCode::CSI {
+            params: smallvec![smallvec![62], smallvec![22]],
+            intermediates: smallvec![b'?'],
+            action: 'c',
+        },
+        tertiary_device_attributes: ControlCode::CSI {
+            params: smallvec![],
+            intermediates: smallvec![b'='],
+            action: 'c',
+        },
+        // We have no real unit ID, so report all zeros like xterm does.
+        tertiary_device_attributes_reply: ControlCode::DCS {
+            params: smallvec![],
+            intermediates: smallvec![b'!'],
+            action: '|',
+            data: smallvec![b'0', b'0', b'0', b'0', b'0', b'0', b'0', b'0'],
+        },
+        identify_terminal: ControlCode::ESC { intermediates: smallvec![], byte: b'Z' },
+        request_cursor_position: ControlCode::CSI {
+            params: smallvec![smallvec![6]],
+            intermediates: smallvec![],
+            action: 'n',
+        },
+        reset_key_modifier_options: ControlCode::CSI {
+            params: smallvec![],
+            intermediates: smallvec![b'>'],
+            action: 'm',
+        },
+        query_keyboard_flags: ControlCode::CSI {
+            params: smallvec![],
+            intermediates: smallvec![b'?'],
+            action: 'u',
+        },
+        select_latin1: ControlCode::ESC { intermediates: smallvec![b'%'], byte: b'@' },
+        select_utf8: ControlCode::ESC { intermediates: smallvec![b'%'], byte: b'G' },
+        shift_out: ControlCode::C0 { byte: 0x0e },
+        shift_in: ControlCode::C0 { byte: 0x0f },
     })
 }
 
 #[allow(dead_code)]
 impl ControlCodes {
+    /// Push kitty keyboard protocol flags (`CSI > flags u`).
+    pub fn push_keyboard_flags(flags: u16) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![flags]],
+            intermediates: smallvec![b'>'],
+            action: 'u',
+        }
+    }
+
+    /// Pop `n` entries off the kitty keyboard flag stack (`CSI < n u`).
+    pub fn pop_keyboard_flags(n: u16) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![n]],
+            intermediates: smallvec![b'<'],
+            action: 'u',
+        }
+    }
+
+    /// Modify the active kitty keyboard flags (`CSI = flags ; mode u`).
+    pub fn set_keyboard_flags(flags: u16, mode: u16) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![flags], smallvec![mode]],
+            intermediates: smallvec![b'='],
+            action: 'u',
+        }
+    }
+
+    /// The reply to a DSR cursor position request (`CSI row ; col R`).
+    pub fn cursor_position_report(row: u16, col: u16) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![row], smallvec![col]],
+            intermediates: smallvec![],
+            action: 'R',
+        }
+    }
+
+    /// The reply to a kitty keyboard flags query (`CSI ? flags u`).
+    pub fn keyboard_flags_reply(flags: u16) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![flags]],
+            intermediates: smallvec![b'?'],
+            action: 'u',
+        }
+    }
+
+    /// Set the xterm modifyOtherKeys level (`CSI > 4 ; level m`).
+    pub fn set_modify_other_keys(level: u16) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![4], smallvec![level]],
+            intermediates: smallvec![b'>'],
+            action: 'm',
+        }
+    }
+
+    /// Designate `charset` into G0 (`slot == 0`) or G1 (`slot == 1`).
+    pub fn designate_charset(slot: usize, charset: Charset) -> ControlCode {
+        let intermediate = if slot == 0 { b'(' } else { b')' };
+        ControlCode::ESC { intermediates: smallvec![intermediate], byte: charset.designator() }
+    }
+
+    /// Select an underline style with the colon form of SGR 4 (`CSI 4 : n
+    /// m`), where 0 means no underline, 1 single, 2 double, 3 curly, 4
+    /// dotted and 5 dashed.
+    pub fn underline_style(n: u16) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![4, n]],
+            intermediates: smallvec![],
+            action: 'm',
+        }
+    }
+
+    /// Select alternate font `n` (1 through 9), or the primary font if
+    /// `n` is 0.
+    pub fn select_font(n: u8) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![10 + n as u16]],
+            intermediates: smallvec![],
+            action: 'm',
+        }
+    }
+
     pub fn fgcolor_idx(i: u8) -> ControlCode {
         if i < 8 {
             ControlCode::CSI {
@@ -1126,6 +1726,15 @@ impl ControlCodes {
         }
     }
 
+    /// REP, print the last printed char n more times.
+    pub fn repeat_char(n: u16) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![n]],
+            intermediates: smallvec![],
+            action: 'b',
+        }
+    }
+
     pub fn delete_character(n: u16) -> ControlCode {
         if n == 1 {
             ControlCode::CSI { params: smallvec![], intermediates: smallvec![], action: 'P' }
@@ -1138,6 +1747,106 @@ impl ControlCodes {
         }
     }
 
+    /// DECSCL, select the conformance level (61 through 65) and whether
+    /// 8-bit controls are used (`CSI level ; c1 " p`).
+    pub fn set_conformance_level(level: u16, eight_bit_controls: bool) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![level], smallvec![if eight_bit_controls { 2 } else { 1 }]],
+            intermediates: smallvec![b'"'],
+            action: 'p',
+        }
+    }
+
+    /// DECSCA, turn character protection for newly written cells on or off.
+    pub fn select_character_protection(protected: bool) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![if protected { 1 } else { 0 }]],
+            intermediates: smallvec![b'"'],
+            action: 'q',
+        }
+    }
+
+    /// DECSED, selective erase in display (`CSI ? n J`).
+    pub fn selective_erase_display(n: u16) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![n]],
+            intermediates: smallvec![b'?'],
+            action: 'J',
+        }
+    }
+
+    /// DECSEL, selective erase in line (`CSI ? n K`).
+    pub fn selective_erase_line(n: u16) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![n]],
+            intermediates: smallvec![b'?'],
+            action: 'K',
+        }
+    }
+
+    pub fn insert_columns(n: u16) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![n]],
+            intermediates: smallvec![b'\''],
+            action: '}',
+        }
+    }
+
+    pub fn delete_columns(n: u16) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![n]],
+            intermediates: smallvec![b'\''],
+            action: '~',
+        }
+    }
+
+    /// DECRQSS, asking the terminal to report the setting named by `data`
+    /// (e.g. `r` for the scroll region).
+    pub fn request_status_string(data: &[u8]) -> ControlCode {
+        ControlCode::DCS {
+            params: smallvec![],
+            intermediates: smallvec![b'$'],
+            action: 'q',
+            data: SmallVec::from_slice(data),
+        }
+    }
+
+    /// The reply to DECRQSS. `data` holds the control sequence which
+    /// would restore the setting (without the CSI), and is empty if the
+    /// request was not valid.
+    pub fn status_string_reply(valid: bool, data: &[u8]) -> ControlCode {
+        ControlCode::DCS {
+            params: smallvec![valid as u16],
+            intermediates: smallvec![b'$'],
+            action: 'r',
+            data: SmallVec::from_slice(data),
+        }
+    }
+
+    pub fn set_cursor_style(style: u16) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![style]],
+            intermediates: smallvec![b' '],
+            action: 'q',
+        }
+    }
+
+    pub fn scroll_left(n: u16) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![n]],
+            intermediates: smallvec![b' '],
+            action: '@',
+        }
+    }
+
+    pub fn scroll_right(n: u16) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![n]],
+            intermediates: smallvec![b' '],
+            action: 'A',
+        }
+    }
+
     /// 1-indexed, inclusive on both ends (closed, closed).
     pub fn set_scroll_region(top: u16, bottom: u16) -> ControlCode {
         ControlCode::CSI {
@@ -1147,6 +1856,15 @@ impl ControlCodes {
         }
     }
 
+    /// DECSLRM. Only takes effect while DECLRMM is on.
+    pub fn set_margins(left: u16, right: u16) -> ControlCode {
+        ControlCode::CSI {
+            params: smallvec![smallvec![left], smallvec![right]],
+            intermediates: smallvec![],
+            action: 's',
+        }
+    }
+
     pub fn set_title_and_icon_name(title: SmallVec<[u8; 8]>) -> ControlCode {
         ControlCode::OSC { params: smallvec![smallvec![b'0'], title], term: OSCTerm::default() }
     }
@@ -1163,6 +1881,17 @@ impl ControlCodes {
         ControlCode::OSC { params: smallvec![smallvec![b'7'], host, dir], term: OSCTerm::default() }
     }
 
+    pub fn set_user_var(name: &[u8], value: &[u8]) -> ControlCode {
+        let mut payload: SmallVec<[u8; 8]> = SmallVec::from_slice(b"SetUserVar=");
+        payload.extend_from_slice(name);
+        payload.push(b'=');
+        payload.extend_from_slice(value);
+        ControlCode::OSC {
+            params: smallvec![smallvec![b'1', b'3', b'3', b'7'], payload],
+            term: OSCTerm::default(),
+        }
+    }
+
     pub fn start_link(params: SmallVec<[u8; 8]>, url: SmallVec<[u8; 8]>) -> ControlCode {
         ControlCode::OSC {
             params: smallvec![smallvec![b'8'], params, url,],
@@ -1204,6 +1933,13 @@ impl ControlCodes {
         ControlCode::OSC { params, term: OSCTerm::default() }
     }
 
+    /// OSC 110 through OSC 119, which reset the functional color that
+    /// OSC 10 through OSC 19 set.
+    pub fn reset_functional_color(offset: usize) -> ControlCode {
+        let params = smallvec![smallvec![b'1', b'1', b'0' + offset as u8]];
+        ControlCode::OSC { params, term: OSCTerm::default() }
+    }
+
     pub fn tab_clear(code: Option<u16>) -> ControlCode {
         let params = match code {
             Some(c) => {
@@ -1228,7 +1964,7 @@ impl ControlCodes {
 }
 
 /// Represents a foreground or background color for cells.
-#[derive(Eq, PartialEq, Debug, Copy, Clone, Default)]
+#[derive(Eq, PartialEq, Debug, Copy, Clone, Default, Hash)]
 #[allow(dead_code)]
 pub enum Color {
     /// The default terminal color.
@@ -1260,6 +1996,103 @@ impl Color {
     }
 }
 
+/// A fully resolved color, as you would hand to a renderer.
+#[derive(Eq, PartialEq, Debug, Copy, Clone, Default)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    /// The foreground used for `Color::Default` when OSC 10 has not set one.
+    pub const DEFAULT_FG: Rgb = Rgb { r: 229, g: 229, b: 229 };
+    /// The background used for `Color::Default` when OSC 11 has not set one.
+    pub const DEFAULT_BG: Rgb = Rgb { r: 0, g: 0, b: 0 };
+
+    /// The color xterm uses for the given palette index when it has not
+    /// been changed with OSC 4.
+    pub fn xterm_palette(idx: u8) -> Rgb {
+        const ANSI: [(u8, u8, u8); 16] = [
+            (0, 0, 0),
+            (205, 0, 0),
+            (0, 205, 0),
+            (205, 205, 0),
+            (0, 0, 238),
+            (205, 0, 205),
+            (0, 205, 205),
+            (229, 229, 229),
+            (127, 127, 127),
+            (255, 0, 0),
+            (0, 255, 0),
+            (255, 255, 0),
+            (92, 92, 255),
+            (255, 0, 255),
+            (0, 255, 255),
+            (255, 255, 255),
+        ];
+        const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+        match idx {
+            0..=15 => {
+                let (r, g, b) = ANSI[idx as usize];
+                Rgb { r, g, b }
+            }
+            16..=231 => {
+                let i = (idx - 16) as usize;
+                Rgb { r: CUBE[i / 36], g: CUBE[(i / 6) % 6], b: CUBE[i % 6] }
+            }
+            _ => {
+                let level = 8 + 10 * (idx - 232);
+                Rgb { r: level, g: level, b: level }
+            }
+        }
+    }
+
+    /// Parse an X11 style color spec as used by OSC 4 and OSC 10 through
+    /// 19, either `rgb:r/g/b` with 1 to 4 hex digits per channel or
+    /// `#rgb` with 1 to 4 digits per channel. Color names are not
+    /// supported and give None.
+    pub fn parse_spec(spec: &[u8]) -> Option<Rgb> {
+        // Scale a channel of 1 to 4 hex digits down to 8 bits.
+        fn channel(digits: &[u8]) -> Option<u8> {
+            if digits.is_empty() || digits.len() > 4 {
+                return None;
+            }
+            let value = u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+            let max = (1u32 << (4 * digits.len())) - 1;
+            Some((value * 255 / max) as u8)
+        }
+
+        if let Some(rest) = spec.strip_prefix(b"rgb:") {
+            let mut parts = rest.split(|b| *b == b'/');
+            let r = channel(parts.next()?)?;
+            let g = channel(parts.next()?)?;
+            let b = channel(parts.next()?)?;
+            if parts.next().is_some() {
+                return None;
+            }
+            Some(Rgb { r, g, b })
+        } else if let Some(rest) = spec.strip_prefix(b"#") {
+            if rest.is_empty() || rest.len() % 3 != 0 {
+                return None;
+            }
+            // The # forms keep the high bits rather than scaling.
+            let n = rest.len() / 3;
+            let high = |digits: &[u8]| -> Option<u8> {
+                let value = u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+                Some(match n {
+                    1 => (value << 4) as u8,
+                    _ => (value >> (4 * (n - 2))) as u8,
+                })
+            };
+            Some(Rgb { r: high(&rest[..n])?, g: high(&rest[n..2 * n])?, b: high(&rest[2 * n..])? })
+        } else {
+            None
+        }
+    }
+}
+
 #[derive(Default, Debug)]
 #[must_use = "this struct does nothing unless you call term_input_into"]
 pub struct Crlf;
@@ -1270,7 +2103,98 @@ impl AsTermInput for Crlf {
     }
 }
 
+/// vte hands us both the private markers that come before the params
+/// (`?`, `>`, `=` and `<`) and the true intermediates that come after
+/// them (`!`, `'`, `$` and friends) in a single slice. Split them back
+/// apart so they can be written out in the right order.
+fn split_csi_intermediates(intermediates: &[u8]) -> (&[u8], &[u8]) {
+    let n_markers = intermediates.iter().take_while(|b| (0x3c..=0x3f).contains(*b)).count();
+    intermediates.split_at(n_markers)
+}
+
 fn extend_itoa<I: itoa::Integer>(buf: &mut Vec<u8>, i: I) {
     let mut itoa_buf = itoa::Buffer::new();
     buf.extend_from_slice(itoa_buf.format(i).as_bytes());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Size;
+
+    #[test]
+    fn term_input_all_concatenates() {
+        let mut want = vec![];
+        ControlCodes::cursor_position(2, 3).term_input_into(&mut want);
+        Raw::from("hi").term_input_into(&mut want);
+        Crlf.term_input_into(&mut want);
+        control_codes().clear_attrs.term_input_into(&mut want);
+
+        let got = term_input_all(&[
+            &ControlCodes::cursor_position(2, 3),
+            &Raw::from("hi"),
+            &Crlf,
+            &control_codes().clear_attrs,
+        ]);
+        assert_eq!(got, want);
+
+        // Slices of a single type work directly too.
+        let mut want = vec![];
+        ControlCodes::cursor_position(2, 3).term_input_into(&mut want);
+        control_codes().clear_attrs.term_input_into(&mut want);
+        let mut got = vec![];
+        [ControlCodes::cursor_position(2, 3), control_codes().clear_attrs.clone()]
+            .term_input_into(&mut got);
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn parse_color_specs() {
+        let red = Some(Rgb { r: 255, g: 0, b: 0 });
+        assert_eq!(Rgb::parse_spec(b"rgb:ff/00/00"), red);
+        assert_eq!(Rgb::parse_spec(b"rgb:f/0/0"), red);
+        assert_eq!(Rgb::parse_spec(b"rgb:ffff/0000/0000"), red);
+        assert_eq!(Rgb::parse_spec(b"#ff0000"), red);
+        assert_eq!(Rgb::parse_spec(b"#fff000000"), red);
+        assert_eq!(Rgb::parse_spec(b"#123"), Some(Rgb { r: 0x10, g: 0x20, b: 0x30 }));
+        assert_eq!(Rgb::parse_spec(b"rgb:ff/00"), None);
+        assert_eq!(Rgb::parse_spec(b"red"), None);
+    }
+
+    #[test]
+    fn clamp_at_edges() {
+        let size = Size { width: 5, height: 3 };
+        assert_eq!(Pos { row: 2, col: 4 }.clamped(size), Pos { row: 2, col: 4 });
+        assert_eq!(Pos { row: 3, col: 5 }.clamped(size), Pos { row: 2, col: 4 });
+        assert_eq!(Pos { row: usize::MAX, col: usize::MAX }.clamped(size), Pos { row: 2, col: 4 });
+    }
+
+    #[test]
+    fn clamp_zero_size() {
+        assert_eq!(
+            Pos { row: 3, col: 3 }.clamped(Size { width: 0, height: 0 }),
+            Pos { row: 0, col: 0 }
+        );
+        assert_eq!(
+            Pos { row: 3, col: 3 }.clamped(Size { width: 5, height: 0 }),
+            Pos { row: 0, col: 3 }
+        );
+    }
+
+    #[test]
+    fn steps_saturate() {
+        let pos = Pos { row: 1, col: 1 };
+        assert_eq!(pos.up(5), Pos { row: 0, col: 1 });
+        assert_eq!(pos.left(5), Pos { row: 1, col: 0 });
+        assert_eq!(pos.down(usize::MAX), Pos { row: usize::MAX, col: 1 });
+        assert_eq!(pos.right(usize::MAX), Pos { row: 1, col: usize::MAX });
+    }
+
+    #[test]
+    fn size_helpers() {
+        assert_eq!(Size { width: 5, height: 3 }.area(), 15);
+        assert!(!Size { width: 5, height: 3 }.is_empty());
+        assert!(Size { width: 0, height: 3 }.is_empty());
+        assert!(Size { width: 5, height: 0 }.is_empty());
+    }
+}
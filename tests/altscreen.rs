@@ -15,7 +15,7 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("B"),
-            term::Crlf::default(),
+            term::Crlf,
             term::ControlCodes::cursor_position(1, 2),
             term::control_codes().clear_attrs
 }
@@ -45,7 +45,7 @@ frag! {
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
-            term::Crlf::default(),
+            term::Crlf,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_attrs
 }
@@ -64,7 +64,7 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("B"),
-            term::Crlf::default(),
+            term::Crlf,
             term::ControlCodes::cursor_position(1, 2),
             term::control_codes().clear_attrs
 }
@@ -77,10 +77,10 @@ frag! {
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::set_scroll_region(2, 5),
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_attrs
@@ -96,12 +96,13 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("A"),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::set_scroll_region(2, 5),
-            term::ControlCodes::cursor_position(1, 2),
+            // DECSTBM homes the cursor.
+            term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_attrs
 }
 
@@ -114,10 +115,10 @@ frag! {
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_attrs
 }
@@ -140,8 +141,8 @@ frag! {
 frag! {
     alt_screen_erase_display_to_end { scrollback_lines: 100, width: 10, height: 10 }
     <= term::control_codes().enable_alt_screen,
-       term::Raw::from("11111"), term::Crlf::default(),
-       term::Raw::from("22222"), term::Crlf::default(),
+       term::Raw::from("11111"), term::Crlf,
+       term::Raw::from("22222"), term::Crlf,
        term::Raw::from("33333"),
        term::ControlCodes::cursor_position(2, 3),
        term::control_codes().erase_to_end
@@ -150,16 +151,16 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11111"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("22"),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::cursor_position(2, 3),
             term::control_codes().clear_attrs
 }
@@ -167,8 +168,8 @@ frag! {
 frag! {
     alt_screen_erase_display_from_start { scrollback_lines: 100, width: 10, height: 10 }
     <= term::control_codes().enable_alt_screen,
-       term::Raw::from("11111"), term::Crlf::default(),
-       term::Raw::from("22222"), term::Crlf::default(),
+       term::Raw::from("11111"), term::Crlf,
+       term::Raw::from("22222"), term::Crlf,
        term::Raw::from("33333"),
        term::ControlCodes::cursor_position(2, 3),
        term::control_codes().erase_from_start
@@ -176,17 +177,17 @@ frag! {
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("   22"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("33333"),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::cursor_position(2, 3),
             term::control_codes().clear_attrs
 }
@@ -194,8 +195,8 @@ frag! {
 frag! {
     alt_screen_erase_screen { scrollback_lines: 100, width: 10, height: 10 }
     <= term::control_codes().enable_alt_screen,
-       term::Raw::from("11111"), term::Crlf::default(),
-       term::Raw::from("22222"), term::Crlf::default(),
+       term::Raw::from("11111"), term::Crlf,
+       term::Raw::from("22222"), term::Crlf,
        term::Raw::from("33333"),
        term::ControlCodes::cursor_position(2, 3),
        term::control_codes().erase_screen
@@ -203,15 +204,15 @@ frag! {
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::cursor_position(2, 3),
             term::control_codes().clear_attrs
 }
@@ -219,10 +220,10 @@ frag! {
 frag! {
     alt_screen_erase_display_to_end_with_decom { scrollback_lines: 100, width: 10, height: 10 }
     <= term::control_codes().enable_alt_screen,
-       term::Raw::from("11111"), term::Crlf::default(),
-       term::Raw::from("22222"), term::Crlf::default(),
-       term::Raw::from("33333"), term::Crlf::default(),
-       term::Raw::from("44444"), term::Crlf::default(),
+       term::Raw::from("11111"), term::Crlf,
+       term::Raw::from("22222"), term::Crlf,
+       term::Raw::from("33333"), term::Crlf,
+       term::Raw::from("44444"), term::Crlf,
        term::Raw::from("55555"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::control_codes().enable_scroll_region_origin_mode,
@@ -233,18 +234,18 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11111"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("22222"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("33"),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("55555"),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(3, 3),
             term::control_codes().enable_scroll_region_origin_mode,
@@ -254,10 +255,10 @@ frag! {
 frag! {
     alt_screen_erase_display_from_start_with_decom { scrollback_lines: 100, width: 10, height: 10 }
     <= term::control_codes().enable_alt_screen,
-       term::Raw::from("11111"), term::Crlf::default(),
-       term::Raw::from("22222"), term::Crlf::default(),
-       term::Raw::from("33333"), term::Crlf::default(),
-       term::Raw::from("44444"), term::Crlf::default(),
+       term::Raw::from("11111"), term::Crlf,
+       term::Raw::from("22222"), term::Crlf,
+       term::Raw::from("33333"), term::Crlf,
+       term::Raw::from("44444"), term::Crlf,
        term::Raw::from("55555"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::control_codes().enable_scroll_region_origin_mode,
@@ -268,18 +269,18 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11111"),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("   33"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("44444"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("55555"),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(3, 3),
             term::control_codes().enable_scroll_region_origin_mode,
@@ -289,10 +290,10 @@ frag! {
 frag! {
     alt_screen_erase_screen_with_decom { scrollback_lines: 100, width: 10, height: 10 }
     <= term::control_codes().enable_alt_screen,
-       term::Raw::from("11111"), term::Crlf::default(),
-       term::Raw::from("22222"), term::Crlf::default(),
-       term::Raw::from("33333"), term::Crlf::default(),
-       term::Raw::from("44444"), term::Crlf::default(),
+       term::Raw::from("11111"), term::Crlf,
+       term::Raw::from("22222"), term::Crlf,
+       term::Raw::from("33333"), term::Crlf,
+       term::Raw::from("44444"), term::Crlf,
        term::Raw::from("55555"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::control_codes().enable_scroll_region_origin_mode,
@@ -303,16 +304,16 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11111"),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("55555"),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(3, 3),
             term::control_codes().enable_scroll_region_origin_mode,
@@ -322,9 +323,9 @@ frag! {
 frag! {
     alt_screen_insert_line_basic { scrollback_lines: 100, width: 5, height: 4 }
     <= term::control_codes().enable_alt_screen,
-       term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
+       term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
        term::Raw::from("44"),
        term::ControlCodes::cursor_position(2, 1),
        term::ControlCodes::insert_lines(1)
@@ -333,10 +334,10 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11"),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("22"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("33"),
             term::ControlCodes::cursor_position(2, 1),
             term::control_codes().clear_attrs
@@ -346,10 +347,10 @@ frag! {
     alt_screen_insert_line_scroll_region { scrollback_lines: 100, width: 5, height: 5 }
     <= term::control_codes().enable_alt_screen,
        term::ControlCodes::cursor_position(1, 1),
-       term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
-       term::Raw::from("44"), term::Crlf::default(),
+       term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
+       term::Raw::from("44"), term::Crlf,
        term::Raw::from("55"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::ControlCodes::cursor_position(3, 1),
@@ -359,12 +360,12 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("22"),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("33"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("55"),
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(3, 1),
@@ -374,9 +375,9 @@ frag! {
 frag! {
     alt_screen_insert_line_many { scrollback_lines: 100, width: 5, height: 4 }
     <= term::control_codes().enable_alt_screen,
-       term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
+       term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
        term::Raw::from("44"),
        term::ControlCodes::cursor_position(2, 1),
        term::ControlCodes::insert_lines(10)
@@ -385,9 +386,9 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11"),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::cursor_position(2, 1),
             term::control_codes().clear_attrs
 }
@@ -395,10 +396,10 @@ frag! {
 frag! {
     alt_screen_insert_line_medium { scrollback_lines: 100, width: 5, height: 5 }
     <= term::control_codes().enable_alt_screen,
-       term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
-       term::Raw::from("44"), term::Crlf::default(),
+       term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
+       term::Raw::from("44"), term::Crlf,
        term::Raw::from("55"),
        term::ControlCodes::cursor_position(2, 1),
        term::ControlCodes::insert_lines(2)
@@ -407,11 +408,11 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11"),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("22"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("33"),
             term::ControlCodes::cursor_position(2, 1),
             term::control_codes().clear_attrs
@@ -420,10 +421,10 @@ frag! {
 frag! {
     alt_screen_insert_outside_scroll_region { scrollback_lines: 100, width: 5, height: 5 }
     <= term::control_codes().enable_alt_screen,
-       term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
-       term::Raw::from("44"), term::Crlf::default(),
+       term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
+       term::Raw::from("44"), term::Crlf,
        term::Raw::from("55"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::ControlCodes::cursor_position(1, 1),
@@ -432,10 +433,10 @@ frag! {
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
-            term::Raw::from("11"), term::Crlf::default(),
-            term::Raw::from("22"), term::Crlf::default(),
-            term::Raw::from("33"), term::Crlf::default(),
-            term::Raw::from("44"), term::Crlf::default(),
+            term::Raw::from("11"), term::Crlf,
+            term::Raw::from("22"), term::Crlf,
+            term::Raw::from("33"), term::Crlf,
+            term::Raw::from("44"), term::Crlf,
             term::Raw::from("55"),
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(1, 1),
@@ -445,9 +446,9 @@ frag! {
 frag! {
     alt_screen_delete_line_basic { scrollback_lines: 100, width: 5, height: 4 }
     <= term::control_codes().enable_alt_screen,
-       term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
+       term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
        term::Raw::from("44"),
        term::ControlCodes::cursor_position(2, 1),
        term::ControlCodes::delete_lines(1)
@@ -455,9 +456,9 @@ frag! {
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
-            term::Raw::from("11"), term::Crlf::default(),
-            term::Raw::from("33"), term::Crlf::default(),
-            term::Raw::from("44"), term::Crlf::default(),
+            term::Raw::from("11"), term::Crlf,
+            term::Raw::from("33"), term::Crlf,
+            term::Raw::from("44"), term::Crlf,
             term::ControlCodes::cursor_position(2, 1),
             term::control_codes().clear_attrs
 }
@@ -466,10 +467,10 @@ frag! {
     alt_screen_delete_lines_scroll_region { scrollback_lines: 100, width: 5, height: 5 }
     <= term::control_codes().enable_alt_screen,
        term::ControlCodes::cursor_position(1, 1),
-       term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
-       term::Raw::from("44"), term::Crlf::default(),
+       term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
+       term::Raw::from("44"), term::Crlf,
        term::Raw::from("55"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::ControlCodes::cursor_position(3, 1),
@@ -478,10 +479,10 @@ frag! {
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
-            term::Raw::from("11"), term::Crlf::default(),
-            term::Raw::from("22"), term::Crlf::default(),
-            term::Raw::from("44"), term::Crlf::default(),
-            term::Crlf::default(),
+            term::Raw::from("11"), term::Crlf,
+            term::Raw::from("22"), term::Crlf,
+            term::Raw::from("44"), term::Crlf,
+            term::Crlf,
             term::Raw::from("55"),
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(3, 1),
@@ -491,9 +492,9 @@ frag! {
 frag! {
     alt_screen_delete_many_lines { scrollback_lines: 100, width: 5, height: 4 }
     <= term::control_codes().enable_alt_screen,
-       term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
+       term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
        term::Raw::from("44"),
        term::ControlCodes::cursor_position(2, 1),
        term::ControlCodes::delete_lines(10)
@@ -502,9 +503,9 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11"),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::cursor_position(2, 1),
             term::control_codes().clear_attrs
 }
@@ -512,10 +513,10 @@ frag! {
 frag! {
     alt_screen_delete_outside_scroll_region { scrollback_lines: 100, width: 5, height: 5 }
     <= term::control_codes().enable_alt_screen,
-       term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
-       term::Raw::from("44"), term::Crlf::default(),
+       term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
+       term::Raw::from("44"), term::Crlf,
        term::Raw::from("55"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::ControlCodes::cursor_position(1, 1),
@@ -525,13 +526,13 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("22"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("33"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("44"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("55"),
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(1, 1),
@@ -549,9 +550,9 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("1 23"),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::cursor_position(1, 2),
             term::control_codes().clear_attrs
 }
@@ -567,9 +568,9 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from(" 12"),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_attrs
 }
@@ -585,9 +586,9 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("  123"),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_attrs
 }
@@ -602,10 +603,10 @@ frag! {
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
-            term::Raw::from("13   "),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Raw::from("13"),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::cursor_position(1, 2),
             term::control_codes().clear_attrs
 }
@@ -620,10 +621,10 @@ frag! {
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
-            term::Raw::from("3    "),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Raw::from("3"),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_attrs
 }
@@ -638,10 +639,10 @@ frag! {
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
-            term::Raw::from("12   "),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Raw::from("12"),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::cursor_position(1, 3),
             term::control_codes().clear_attrs
 }
@@ -651,39 +652,39 @@ frag! {
     <= term::control_codes().enable_alt_screen,
        term::Raw::from("123"),
        term::ControlCodes::cursor_position(1, 2),
-       term::ControlCodes::fgcolor_idx(1),
+       term::ControlCodes::bgcolor_idx(1),
        term::ControlCodes::delete_character(1)
     => ContentRegion::All =>
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("13  "),
-            term::ControlCodes::fgcolor_idx(1),
+            term::ControlCodes::bgcolor_idx(1),
             term::Raw::from(" "),
-            term::control_codes().fgcolor_default,
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::control_codes().bgcolor_default,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::cursor_position(1, 2),
             term::control_codes().clear_attrs,
-            term::ControlCodes::fgcolor_idx(1)
+            term::ControlCodes::bgcolor_idx(1)
 }
 
 frag! {
     alt_screen_scroll_on_newline { scrollback_lines: 100, width: 5, height: 3 }
     <= term::control_codes().enable_alt_screen,
-       term::Raw::from("1"), term::Crlf::default(),
-       term::Raw::from("2"), term::Crlf::default(),
-       term::Raw::from("3"), term::Crlf::default(),
+       term::Raw::from("1"), term::Crlf,
+       term::Raw::from("2"), term::Crlf,
+       term::Raw::from("3"), term::Crlf,
        term::Raw::from("4")
     => ContentRegion::All =>
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("2"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("3"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("4"),
             term::ControlCodes::cursor_position(3, 2),
             term::control_codes().clear_attrs
@@ -692,10 +693,10 @@ frag! {
 frag! {
     alt_screen_scroll_region_on_newline { scrollback_lines: 100, width: 5, height: 5 }
     <= term::control_codes().enable_alt_screen,
-       term::Raw::from("0"), term::Crlf::default(),
-       term::Raw::from("1"), term::Crlf::default(),
-       term::Raw::from("2"), term::Crlf::default(),
-       term::Raw::from("3"), term::Crlf::default(),
+       term::Raw::from("0"), term::Crlf,
+       term::Raw::from("1"), term::Crlf,
+       term::Raw::from("2"), term::Crlf,
+       term::Raw::from("3"), term::Crlf,
        term::Raw::from("4"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::ControlCodes::cursor_position(4, 1),
@@ -706,13 +707,13 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("0"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("2"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("3"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("X"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("4"),
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(4, 2),
@@ -722,10 +723,10 @@ frag! {
 frag! {
     alt_screen_newline_below_scroll_region { scrollback_lines: 100, width: 5, height: 5 }
     <= term::control_codes().enable_alt_screen,
-       term::Raw::from("0"), term::Crlf::default(),
-       term::Raw::from("1"), term::Crlf::default(),
-       term::Raw::from("2"), term::Crlf::default(),
-       term::Raw::from("3"), term::Crlf::default(),
+       term::Raw::from("0"), term::Crlf,
+       term::Raw::from("1"), term::Crlf,
+       term::Raw::from("2"), term::Crlf,
+       term::Raw::from("3"), term::Crlf,
        term::Raw::from("4"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::ControlCodes::cursor_position(5, 1),
@@ -736,13 +737,13 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("0"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("1"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("2"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("3"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("X"),
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(5, 2),
@@ -752,10 +753,10 @@ frag! {
 frag! {
     alt_screen_newline_above_scroll_region { scrollback_lines: 100, width: 5, height: 5 }
     <= term::control_codes().enable_alt_screen,
-       term::Raw::from("0"), term::Crlf::default(),
-       term::Raw::from("1"), term::Crlf::default(),
-       term::Raw::from("2"), term::Crlf::default(),
-       term::Raw::from("3"), term::Crlf::default(),
+       term::Raw::from("0"), term::Crlf,
+       term::Raw::from("1"), term::Crlf,
+       term::Raw::from("2"), term::Crlf,
+       term::Raw::from("3"), term::Crlf,
        term::Raw::from("4"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::ControlCodes::cursor_position(1, 1),
@@ -766,15 +767,298 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("0"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("X"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("2"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("3"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("4"),
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(2, 2),
             term::control_codes().clear_attrs
 }
+
+#[test]
+fn alt_screen_resize_then_redraw() {
+    use shpool_vterm::term::AsTermInput;
+
+    let mut term = shpool_vterm::Term::new(100, shpool_vterm::Size { width: 5, height: 4 });
+    let mut input = vec![];
+    term::control_codes().enable_alt_screen.term_input_into(&mut input);
+    term::Raw::from("1\r\n2\r\n3\r\n4").term_input_into(&mut input);
+    term.process(input.as_slice());
+
+    // The cursor is on the last row, so shrinking must not drop it.
+    term.resize(shpool_vterm::Size { width: 5, height: 2 });
+
+    let mut expected = vec![];
+    term::control_codes().clear_attrs.term_input_into(&mut expected);
+    term::ControlCodes::cursor_position(1, 1).term_input_into(&mut expected);
+    term::control_codes().clear_screen.term_input_into(&mut expected);
+    term::Raw::from("3").term_input_into(&mut expected);
+    term::Crlf.term_input_into(&mut expected);
+    term::Raw::from("4").term_input_into(&mut expected);
+    term::ControlCodes::cursor_position(2, 2).term_input_into(&mut expected);
+    term::control_codes().clear_attrs.term_input_into(&mut expected);
+    assert_eq!(term.contents(ContentRegion::All), expected);
+
+    // The app gets a SIGWINCH and redraws the whole screen.
+    let mut input = vec![];
+    term::ControlCodes::cursor_position(1, 1).term_input_into(&mut input);
+    term::control_codes().erase_screen.term_input_into(&mut input);
+    term::Raw::from("ab\r\ncd").term_input_into(&mut input);
+    term.process(input.as_slice());
+
+    let mut expected = vec![];
+    term::control_codes().clear_attrs.term_input_into(&mut expected);
+    term::ControlCodes::cursor_position(1, 1).term_input_into(&mut expected);
+    term::control_codes().clear_screen.term_input_into(&mut expected);
+    term::Raw::from("ab").term_input_into(&mut expected);
+    term::Crlf.term_input_into(&mut expected);
+    term::Raw::from("cd").term_input_into(&mut expected);
+    term::ControlCodes::cursor_position(2, 3).term_input_into(&mut expected);
+    term::control_codes().clear_attrs.term_input_into(&mut expected);
+    assert_eq!(term.contents(ContentRegion::All), expected);
+}
+
+#[test]
+fn alt_screen_resize_grow_bottom_anchor() {
+    use shpool_vterm::term::AsTermInput;
+
+    let mut term = shpool_vterm::Term::new(100, shpool_vterm::Size { width: 5, height: 2 });
+    term.set_alt_screen_anchor(shpool_vterm::AltScreenAnchor::Bottom);
+    let mut input = vec![];
+    term::control_codes().enable_alt_screen.term_input_into(&mut input);
+    term::Raw::from("1\r\n2").term_input_into(&mut input);
+    term.process(input.as_slice());
+
+    term.resize(shpool_vterm::Size { width: 5, height: 3 });
+
+    let mut expected = vec![];
+    term::control_codes().clear_attrs.term_input_into(&mut expected);
+    term::ControlCodes::cursor_position(1, 1).term_input_into(&mut expected);
+    term::control_codes().clear_screen.term_input_into(&mut expected);
+    term::Crlf.term_input_into(&mut expected);
+    term::Raw::from("1").term_input_into(&mut expected);
+    term::Crlf.term_input_into(&mut expected);
+    term::Raw::from("2").term_input_into(&mut expected);
+    term::ControlCodes::cursor_position(3, 2).term_input_into(&mut expected);
+    term::control_codes().clear_attrs.term_input_into(&mut expected);
+    assert_eq!(term.contents(ContentRegion::All), expected);
+}
+
+frag! {
+    alt_screen_wide_char { scrollback_lines: 100, width: 10, height: 2 }
+    <= term::control_codes().enable_alt_screen,
+       term::Raw::from("A😊B")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("A😊B"),
+            term::Crlf,
+            term::ControlCodes::cursor_position(1, 5),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    alt_screen_wide_char_overwrite_pad { scrollback_lines: 100, width: 10, height: 2 }
+    <= term::control_codes().enable_alt_screen,
+       term::Raw::from("A😊B"),
+       term::ControlCodes::cursor_backwards(2),
+       term::Raw::from("x")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("A xB"),
+            term::Crlf,
+            term::ControlCodes::cursor_position(1, 4),
+            term::control_codes().clear_attrs
+}
+
+#[test]
+fn alt_screen_wrapped_dump_matches_scrollback() {
+    use shpool_vterm::term::AsTermInput;
+
+    let size = shpool_vterm::Size { width: 5, height: 3 };
+    let content = b"abcdefg\r\nhi";
+
+    let mut scrollback = shpool_vterm::Term::new(100, size);
+    scrollback.process(content);
+
+    let mut alt = shpool_vterm::Term::new(100, size);
+    let mut input = vec![];
+    term::control_codes().enable_alt_screen.term_input_into(&mut input);
+    input.extend_from_slice(content);
+    alt.process(&input);
+
+    assert_eq!(scrollback.contents(ContentRegion::All), alt.contents(ContentRegion::All));
+}
+
+frag! {
+    alt_screen_erase_scrollback_is_noop { scrollback_lines: 10, width: 5, height: 2 }
+    <= term::Raw::from("1\r\n2\r\n3"),
+       term::control_codes().enable_alt_screen,
+       term::Raw::from("ab\r\ncd"),
+       term::control_codes().erase_scrollback
+    => ContentRegion::Screen =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("ab"),
+            term::Crlf,
+            term::Raw::from("cd"),
+            term::ControlCodes::cursor_position(2, 3),
+            term::control_codes().clear_attrs
+}
+
+#[test]
+fn carriage_return_cancels_pending_wrap() {
+    for enter_alt in [&b""[..], b"\x1b[?1049h"] {
+        let mut term = shpool_vterm::Term::new(100, shpool_vterm::Size { width: 3, height: 2 });
+        term.process(enter_alt);
+        // On the last row too, where a wrap would scroll.
+        term.process(b"abc\rx\r\ndef\ry");
+
+        for (row, want) in [(0, ['x', 'b', 'c']), (1, ['y', 'e', 'f'])] {
+            for (col, c) in want.into_iter().enumerate() {
+                assert_eq!(
+                    term.cell_at(row, col).unwrap().chars(),
+                    &[c],
+                    "{enter_alt:?} ({row}, {col})"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn erase_line_after_filling_last_column() {
+    use shpool_vterm::{ContentRegion, TextOptions};
+
+    for enter_alt in [&b""[..], b"\x1b[?1049h"] {
+        let mut term = shpool_vterm::Term::new(100, shpool_vterm::Size { width: 5, height: 3 });
+        term.process(enter_alt);
+        // The cursor is still on the last column of the first row, so the
+        // erase takes out the 'e' and leaves the row below alone.
+        term.process(b"\x1b[2;1Hvwxyz\x1b[1;1Habcde\x1b[K");
+
+        let text = term.text(ContentRegion::Screen, TextOptions::default());
+        assert_eq!(text.lines().take(2).collect::<Vec<_>>(), ["abcd", "vwxyz"], "{enter_alt:?}");
+    }
+}
+
+frag! {
+    alt_screen_erase_line_last_row { scrollback_lines: 100, width: 5, height: 2 }
+    <= term::control_codes().enable_alt_screen,
+       term::Raw::from("ab\r\ncdef"),
+       term::ControlCodes::cursor_position(2, 3),
+       term::control_codes().erase_to_end_of_line
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("ab"),
+            term::Crlf,
+            term::Raw::from("cd"),
+            term::ControlCodes::cursor_position(2, 3),
+            term::control_codes().clear_attrs
+}
+
+#[test]
+fn alt_screen_ris() {
+    use shpool_vterm::term::AsTermInput;
+
+    let size = shpool_vterm::Size { width: 10, height: 3 };
+    let mut term = shpool_vterm::Term::new(100, size);
+    let mut input = vec![];
+    term::Raw::from("main").term_input_into(&mut input);
+    term::control_codes().enable_alt_screen.term_input_into(&mut input);
+    term::Raw::from("alt").term_input_into(&mut input);
+    term.process(&input);
+    assert!(term.is_alt_screen());
+
+    input.clear();
+    term::control_codes().hard_reset.term_input_into(&mut input);
+    term.process(&input);
+    assert!(!term.is_alt_screen());
+
+    let fresh = shpool_vterm::Term::new(100, size);
+    assert_eq!(term.contents(ContentRegion::All), fresh.contents(ContentRegion::All));
+}
+
+#[test]
+fn alt_screen_reset_api() {
+    let size = shpool_vterm::Size { width: 10, height: 3 };
+    let mut term = shpool_vterm::Term::new(100, size);
+    term.process(b"main\x1b[?1049halt");
+    term.reset();
+    assert!(!term.is_alt_screen());
+
+    let fresh = shpool_vterm::Term::new(100, size);
+    assert_eq!(term.contents(ContentRegion::All), fresh.contents(ContentRegion::All));
+}
+
+#[test]
+fn alt_screen_cursor_save_restore() {
+    use shpool_vterm::{term::AsTermInput, Attrs, Color, Size, Term};
+
+    fn cpr(term: &mut Term) -> Vec<u8> {
+        term.process(b"\x1b[6n");
+        term.take_responses()
+    }
+    fn report(row: u16, col: u16) -> Vec<u8> {
+        let mut buf = vec![];
+        term::ControlCodes::cursor_position_report(row, col).term_input_into(&mut buf);
+        buf
+    }
+
+    let mut term = Term::new(100, Size { width: 10, height: 5 });
+    term.process(b"\x1b[3;4H\x1b[31m");
+
+    // Entering homes the cursor on the cleared alt screen.
+    term.process(b"\x1b[?1049h");
+    assert_eq!(cpr(&mut term), report(1, 1));
+    term.process(b"\x1b[2;6H\x1b[32m");
+    assert_eq!(cpr(&mut term), report(2, 6));
+
+    // Leaving puts back the main cursor along with its attrs.
+    term.process(b"\x1b[?1049lx");
+    assert_eq!(cpr(&mut term), report(3, 5));
+    let red = Attrs { fgcolor: Color::Idx(1), ..Attrs::default() };
+    assert_eq!(term.cell_at(2, 3).unwrap().attrs(), &red);
+}
+
+#[test]
+fn alt_screen_shrink_below_scroll_region() {
+    use shpool_vterm::{Size, Term};
+
+    let small = Size { width: 10, height: 5 };
+    for shrink in [Term::set_viewport_height as fn(&mut Term, usize), |term, height| {
+        term.resize(Size { width: 10, height })
+    }] {
+        for scroll in [&b"\x1b[3S"[..], b"\x1b[3T", b"\x1b[5;1H\x1bD\x1bD"] {
+            let mut term = Term::new(100, Size { width: 10, height: 10 });
+            term.process(b"\x1b[?1049h\x1b[2;10r");
+            shrink(&mut term, 5);
+
+            // The old region no longer fits, so it acts like there is
+            // no region set at all.
+            let mut fresh = Term::new(100, small);
+            fresh.process(b"\x1b[?1049h");
+            for term in [&mut term, &mut fresh] {
+                term.process(b"\x1b[1;1Ha\x1b[5;1Hb");
+                term.process(scroll);
+            }
+            assert_eq!(
+                term.contents(ContentRegion::All),
+                fresh.contents(ContentRegion::All),
+                "{}",
+                String::from_utf8_lossy(scroll)
+            );
+        }
+    }
+}
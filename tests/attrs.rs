@@ -2,7 +2,10 @@
 #[path = "support/mod.rs"]
 mod support;
 
-use shpool_vterm::{term, ContentRegion};
+use shpool_vterm::{
+    term::{self, AsTermInput},
+    ContentRegion,
+};
 use smallvec::smallvec;
 
 frag! {
@@ -31,9 +34,7 @@ frag! {
             term::control_codes().clear_screen,
             term::ControlCodes::start_link(smallvec![], smallvec![b'h', b't', b't', b'p']),
             term::Raw::from("abcde"),
-            term::control_codes().end_link,
-            term::Crlf::default(),
-            term::ControlCodes::start_link(smallvec![], smallvec![b'h', b't', b't', b'p']),
+            term::Crlf,
             term::Raw::from("f"),
             term::control_codes().end_link,
             term::ControlCodes::cursor_position(2, 2),
@@ -53,8 +54,8 @@ frag! {
             term::control_codes().clear_screen,
             term::ControlCodes::start_link(smallvec![], smallvec![b'h', b't', b't', b'p']),
             term::Raw::from("a"),
+            term::Crlf,
             term::control_codes().end_link,
-            term::Crlf::default(),
             term::Raw::from(" "),
             term::ControlCodes::start_link(smallvec![], smallvec![b'h', b't', b't', b'p']),
             term::Raw::from("b"),
@@ -100,6 +101,49 @@ frag! {
             term::ControlCodes::start_link(smallvec![], smallvec![b'h', b't', b't', b'p'])
 }
 
+frag! {
+    link_id_across_wrap { scrollback_lines: 10, width: 5, height: 10 }
+    <= term::ControlCodes::start_link(smallvec![b'i', b'd', b'=', b'x'], smallvec![b'h', b't', b't', b'p']),
+       term::Raw::from("ab"),
+       term::control_codes().end_link,
+       term::Raw::from(" "),
+       term::ControlCodes::start_link(smallvec![b'i', b'd', b'=', b'x'], smallvec![b'h', b't', b't', b'p']),
+       term::Raw::from("cdef"),
+       term::control_codes().end_link
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::start_link(smallvec![b'i', b'd', b'=', b'x'], smallvec![b'h', b't', b't', b'p']),
+            term::Raw::from("ab"),
+            term::control_codes().end_link,
+            term::Raw::from(" "),
+            term::ControlCodes::start_link(smallvec![b'i', b'd', b'=', b'x'], smallvec![b'h', b't', b't', b'p']),
+            term::Raw::from("cd"),
+            term::Crlf,
+            term::Raw::from("ef"),
+            term::control_codes().end_link,
+            term::ControlCodes::cursor_position(2, 3),
+            term::control_codes().clear_attrs
+}
+
+#[test]
+fn link_id_groups_runs() {
+    use shpool_vterm::{Size, Term};
+
+    let mut term = Term::new(10, Size { width: 5, height: 10 });
+    term.process(b"\x1b]8;id=x;http\x1b\\ab\x1b]8;;\x1b\\ \x1b]8;foo=1:id=x;http\x1b\\cdef");
+    term.process(b"\x1b]8;;http\x1b\\g");
+
+    let link = |row, col| term.cell_at(row, col).unwrap().attrs().link_target.clone().unwrap();
+    assert_eq!(link(0, 0).id.as_deref(), Some(&b"x"[..]));
+    assert!(link(0, 0).same_link(&link(0, 3)));
+    assert!(link(0, 0).same_link(&link(1, 1)));
+    // Links without an id only ever match by being the same run.
+    assert_eq!(link(1, 2).id, None);
+    assert!(!link(1, 2).same_link(&link(1, 2)));
+}
+
 frag! {
     underline { scrollback_lines: 100, width: 100, height: 100 }
     <= term::Raw::from("a"),
@@ -332,6 +376,52 @@ frag! {
             term::control_codes().clear_attrs
 }
 
+frag! {
+    underline_styles { scrollback_lines: 100, width: 100, height: 100 }
+    <= term::ControlCodes::underline_style(3),
+       term::Raw::from("a"),
+       term::ControlCodes::underline_style(0),
+       term::Raw::from("b"),
+       term::control_codes().underline,
+       term::Raw::from("c"),
+       term::ControlCodes::underline_style(3),
+       term::Raw::from("d"),
+       term::ControlCodes::underline_style(2),
+       term::Raw::from("e"),
+       term::control_codes().underline,
+       term::Raw::from("f")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::underline_style(3),
+            term::Raw::from("a"),
+            term::control_codes().undo_underline,
+            term::Raw::from("b"),
+            term::control_codes().underline,
+            term::Raw::from("c"),
+            term::ControlCodes::underline_style(3),
+            term::Raw::from("d"),
+            // A single underline doesn't clear a double one, or the
+            // other way around, so these go through an undo.
+            term::ControlCode::CSI {
+                params: smallvec![smallvec![24], smallvec![21]],
+                intermediates: smallvec![],
+                action: 'm',
+            },
+            term::Raw::from("e"),
+            term::ControlCode::CSI {
+                params: smallvec![smallvec![24], smallvec![4]],
+                intermediates: smallvec![],
+                action: 'm',
+            },
+            term::Raw::from("f"),
+            term::control_codes().undo_underline,
+            term::ControlCodes::cursor_position(1, 7),
+            term::control_codes().clear_attrs,
+            term::control_codes().underline
+}
+
 frag! {
     save_restore_cursor_attrs { scrollback_lines: 100, width: 10, height: 10 }
     <= term::control_codes().bold,
@@ -443,6 +533,30 @@ frag! {
             term::control_codes().clear_attrs
 }
 
+frag! {
+    show_cursor_after_soft_reset { scrollback_lines: 10, width: 10, height: 10 }
+    <= term::control_codes().hide_cursor,
+       term::control_codes().soft_reset
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    show_cursor_after_ris { scrollback_lines: 10, width: 10, height: 10 }
+    <= term::control_codes().hide_cursor,
+       term::control_codes().hard_reset
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs
+}
+
 frag! {
     hide_cursor_with_text { scrollback_lines: 10, width: 10, height: 10 }
     <= term::control_codes().hide_cursor,
@@ -458,21 +572,21 @@ frag! {
 }
 
 frag! {
-    application_keypad_mode { scrollback_lines: 10, width: 10, height: 10 }
-    <= term::control_codes().enable_application_keypad_mode
+    application_cursor_keys { scrollback_lines: 10, width: 10, height: 10 }
+    <= term::control_codes().enable_application_cursor_keys
     => ContentRegion::All =>
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_attrs,
-            term::control_codes().enable_application_keypad_mode
+            term::control_codes().enable_application_cursor_keys
 }
 
 frag! {
-    disable_application_keypad_mode { scrollback_lines: 10, width: 10, height: 10 }
-    <= term::control_codes().enable_application_keypad_mode,
-       term::control_codes().disable_application_keypad_mode
+    disable_application_cursor_keys { scrollback_lines: 10, width: 10, height: 10 }
+    <= term::control_codes().enable_application_cursor_keys,
+       term::control_codes().disable_application_cursor_keys
     => ContentRegion::All =>
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
@@ -504,3 +618,297 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_attrs
 }
+
+frag! {
+    reenable_paste_mode { scrollback_lines: 10, width: 10, height: 10 }
+    <= term::control_codes().enable_paste_mode,
+       term::control_codes().disable_paste_mode,
+       term::control_codes().enable_paste_mode
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs,
+            term::control_codes().enable_paste_mode
+}
+
+frag! {
+    paste_mode_after_unhandled_mode { scrollback_lines: 10, width: 10, height: 10 }
+    <= term::Raw::from("\x1b[?1000;2004h")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs,
+            term::control_codes().enable_paste_mode
+}
+
+frag! {
+    steady_underline_cursor { scrollback_lines: 10, width: 10, height: 10 }
+    <= term::Raw::from("ab"),
+       term::ControlCodes::set_cursor_style(4)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("ab"),
+            term::ControlCodes::cursor_position(1, 3),
+            term::control_codes().clear_attrs,
+            term::ControlCodes::set_cursor_style(4)
+}
+
+frag! {
+    default_cursor_style_omitted { scrollback_lines: 10, width: 10, height: 10 }
+    <= term::ControlCodes::set_cursor_style(4),
+       term::ControlCodes::set_cursor_style(1)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    screen_dump_color_continues_from_scrollback { scrollback_lines: 10, width: 5, height: 2 }
+    <= term::ControlCodes::fgcolor_idx(1),
+       term::Raw::from("aa"), term::Crlf,
+       term::Raw::from("bb"),
+       term::control_codes().clear_attrs,
+       term::Crlf,
+       term::Raw::from("cc")
+    => ContentRegion::Screen =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::fgcolor_idx(1),
+            term::Raw::from("bb"),
+            term::Crlf,
+            term::control_codes().fgcolor_default,
+            term::Raw::from("cc"),
+            term::ControlCodes::cursor_position(2, 3),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    screen_dump_no_color_bleed_from_scrollback { scrollback_lines: 10, width: 5, height: 2 }
+    <= term::ControlCodes::fgcolor_idx(1),
+       term::Raw::from("aa"),
+       term::control_codes().clear_attrs,
+       term::Crlf,
+       term::Raw::from("bb"), term::Crlf,
+       term::Raw::from("cc")
+    => ContentRegion::Screen =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("bb"),
+            term::Crlf,
+            term::Raw::from("cc"),
+            term::ControlCodes::cursor_position(2, 3),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    alternate_font { scrollback_lines: 10, width: 10, height: 10 }
+    <= term::ControlCodes::select_font(2),
+       term::Raw::from("ab"),
+       term::control_codes().clear_attrs,
+       term::Raw::from("c")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::select_font(2),
+            term::Raw::from("ab"),
+            term::ControlCodes::select_font(0),
+            term::Raw::from("c"),
+            term::ControlCodes::cursor_position(1, 4),
+            term::control_codes().clear_attrs
+}
+
+#[test]
+fn cell_attr_readback() {
+    let mut term = shpool_vterm::Term::new(100, shpool_vterm::Size { width: 10, height: 5 });
+    term.process(b"\x1b[1;31mx\x1b[0my");
+
+    let styled = term.cell_at(0, 0).expect("cell in bounds");
+    assert_eq!(styled.chars(), &['x']);
+    assert!(styled.is_bold());
+    assert!(!styled.is_italic());
+    assert_eq!(styled.fg(), shpool_vterm::Color::Idx(1));
+    assert_eq!(styled.bg(), shpool_vterm::Color::Default);
+
+    let plain = term.cell_at(0, 1).expect("cell in bounds");
+    assert_eq!(plain.chars(), &['y']);
+    assert!(!plain.is_bold());
+    assert_eq!(plain.fg(), shpool_vterm::Color::Default);
+
+    assert!(term.cell_at(3, 3).expect("cell in bounds").is_empty());
+    assert!(term.cell_at(5, 0).is_none());
+    assert!(term.cell_at(0, 10).is_none());
+}
+
+#[test]
+fn attrs_at_readback() {
+    use shpool_vterm::{Attrs, Color, FontWeight, Pos, UnderlineStyle};
+
+    let mut term = shpool_vterm::Term::new(100, shpool_vterm::Size { width: 10, height: 5 });
+    term.process("\x1b[1;3;4:3;9;38;2;1;2;3;44m中\x1b[0mx".as_bytes());
+
+    let expected = Attrs {
+        fgcolor: Color::Rgb(1, 2, 3),
+        bgcolor: Color::Idx(4),
+        font_weight: Some(FontWeight::Bold),
+        italic: true,
+        underline: Some(UnderlineStyle::Curly),
+        strikethrough: true,
+        ..Attrs::default()
+    };
+    assert_eq!(term.attrs_at(Pos { row: 0, col: 0 }), Some(expected.clone()));
+    // The pad after a wide char reports the wide char's attrs.
+    assert_eq!(term.attrs_at(Pos { row: 0, col: 1 }), Some(expected));
+    assert_eq!(term.attrs_at(Pos { row: 0, col: 2 }), Some(Attrs::default()));
+    assert_eq!(term.attrs_at(Pos { row: 3, col: 3 }), Some(Attrs::default()));
+    assert_eq!(term.attrs_at(Pos { row: 5, col: 0 }), None);
+}
+
+#[test]
+fn write_styled_runs() {
+    let mut term = shpool_vterm::Term::new(100, shpool_vterm::Size { width: 10, height: 5 });
+    let bold = shpool_vterm::Attrs {
+        font_weight: Some(shpool_vterm::FontWeight::Bold),
+        ..shpool_vterm::Attrs::default()
+    };
+    let italic = shpool_vterm::Attrs { italic: true, ..shpool_vterm::Attrs::default() };
+    term.write_styled("ab", bold.clone());
+    term.write_styled("cd", italic.clone());
+
+    let mut expected = vec![];
+    term::control_codes().clear_attrs.term_input_into(&mut expected);
+    term::ControlCodes::cursor_position(1, 1).term_input_into(&mut expected);
+    term::control_codes().clear_screen.term_input_into(&mut expected);
+    term::control_codes().bold.term_input_into(&mut expected);
+    term::Raw::from("ab").term_input_into(&mut expected);
+    for code in bold.transition_to(&italic) {
+        code.term_input_into(&mut expected);
+    }
+    term::Raw::from("cd").term_input_into(&mut expected);
+    term::control_codes().undo_italic.term_input_into(&mut expected);
+    term::ControlCodes::cursor_position(1, 5).term_input_into(&mut expected);
+    term::control_codes().clear_attrs.term_input_into(&mut expected);
+    assert_eq!(term.contents(ContentRegion::All), expected);
+
+    // The cursor attrs are untouched, so regular output stays plain.
+    term.process(b"e");
+    assert!(!term.cell_at(0, 4).expect("cell in bounds").is_italic());
+}
+
+#[test]
+fn text_masks_concealed() {
+    let mut term = shpool_vterm::Term::new(100, shpool_vterm::Size { width: 10, height: 5 });
+    let mut input = vec![];
+    term::Raw::from("pw: ").term_input_into(&mut input);
+    term::control_codes().conceal.term_input_into(&mut input);
+    term::Raw::from("hunter2").term_input_into(&mut input);
+    term::control_codes().undo_conceal.term_input_into(&mut input);
+    term::Raw::from("\r\nok").term_input_into(&mut input);
+    term.process(&input);
+
+    assert_eq!(
+        term.text(ContentRegion::All, shpool_vterm::TextOptions::default()),
+        "pw:       \nok\n"
+    );
+}
+
+#[test]
+fn text_reveals_concealed() {
+    let mut term = shpool_vterm::Term::new(100, shpool_vterm::Size { width: 10, height: 5 });
+    let mut input = vec![];
+    term::Raw::from("pw: ").term_input_into(&mut input);
+    term::control_codes().conceal.term_input_into(&mut input);
+    term::Raw::from("hunter2").term_input_into(&mut input);
+    term::control_codes().undo_conceal.term_input_into(&mut input);
+    term::Raw::from("\r\nok").term_input_into(&mut input);
+    term.process(&input);
+
+    assert_eq!(
+        term.text(ContentRegion::All, shpool_vterm::TextOptions { reveal_concealed: true }),
+        "pw: hunter2\nok\n"
+    );
+}
+
+#[test]
+fn overstrike_mode() {
+    use shpool_vterm::{Attrs, FontWeight, Pos, Size, Term, UnderlineStyle};
+
+    let bold = Attrs { font_weight: Some(FontWeight::Bold), ..Attrs::default() };
+    let underline = Attrs { underline: Some(UnderlineStyle::Single), ..Attrs::default() };
+
+    let mut term = Term::new(10, Size { width: 10, height: 3 });
+    assert!(!term.overstrike_mode());
+    term.process(b"a\x08a_\x08b");
+    assert_eq!(term.attrs_at(Pos { row: 0, col: 0 }), Some(Attrs::default()));
+    assert_eq!(term.grapheme_at(Pos { row: 0, col: 1 }).as_deref(), Some("b"));
+
+    term.set_overstrike_mode(true);
+    term.process(b"\r\na\x08a_\x08bc\x08d_\x08c\x08c");
+    assert_eq!(term.grapheme_at(Pos { row: 1, col: 0 }).as_deref(), Some("a"));
+    assert_eq!(term.attrs_at(Pos { row: 1, col: 0 }), Some(bold.clone()));
+    assert_eq!(term.grapheme_at(Pos { row: 1, col: 1 }).as_deref(), Some("b"));
+    assert_eq!(term.attrs_at(Pos { row: 1, col: 1 }), Some(underline));
+    // A different char just replaces the old one.
+    assert_eq!(term.grapheme_at(Pos { row: 1, col: 2 }).as_deref(), Some("d"));
+    assert_eq!(term.attrs_at(Pos { row: 1, col: 2 }), Some(Attrs::default()));
+    // Overstrikes stack.
+    assert_eq!(
+        term.attrs_at(Pos { row: 1, col: 3 }),
+        Some(Attrs { underline: Some(UnderlineStyle::Single), ..bold })
+    );
+
+    term.reset();
+    assert!(term.overstrike_mode());
+}
+
+#[test]
+fn dump_fuses_attrs_across_lines() {
+    use shpool_vterm::{Size, Term};
+
+    let size = Size { width: 5, height: 3 };
+    let mut term = Term::new(10, size);
+    term.process(b"\x1b[1;32mgood\r\ngood\r\ngood");
+
+    let dump = term.contents(ContentRegion::All);
+    // One SGR code up front and one reset at the end, rather than a pair
+    // for every line.
+    let body = b"\x1b[32;1mgood\r\ngood\r\ngood\x1b[39;22m";
+    assert!(dump.windows(body.len()).any(|w| w == body), "{:?}", String::from_utf8_lossy(&dump));
+
+    let restored = Term::from_contents(10, size, &dump);
+    assert_eq!(restored.snapshot(ContentRegion::All), term.snapshot(ContentRegion::All));
+    assert_eq!(restored.contents(ContentRegion::All), dump);
+}
+
+frag! {
+    dump_drops_bgcolor_at_line_breaks { scrollback_lines: 10, width: 3, height: 2 }
+    <= term::ControlCodes::bgcolor_idx(4),
+       term::Raw::from("abc"),
+       term::Crlf,
+       term::Raw::from("def")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::bgcolor_idx(4),
+            term::Raw::from("abc"),
+            term::control_codes().bgcolor_default,
+            term::Crlf,
+            term::ControlCodes::bgcolor_idx(4),
+            term::Raw::from("def"),
+            term::control_codes().bgcolor_default,
+            term::ControlCodes::cursor_position(2, 4),
+            term::control_codes().clear_attrs,
+            term::ControlCodes::bgcolor_idx(4)
+}
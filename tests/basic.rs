@@ -2,7 +2,11 @@
 #[path = "support/mod.rs"]
 mod support;
 
-use shpool_vterm::{term, ContentRegion};
+use shpool_vterm::{
+    term::{self, AsTermInput},
+    ContentRegion,
+};
+use support::screens::assert_screens_agree;
 
 frag! {
     simple_str { scrollback_lines: 100, width: 100, height: 100 }
@@ -38,7 +42,7 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("foo"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("bar"),
             term::ControlCodes::cursor_position(1, 4),
             term::control_codes().clear_attrs
@@ -81,7 +85,7 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("A"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("😊"),
             term::ControlCodes::cursor_position(2, 3),
             term::control_codes().clear_attrs
@@ -95,12 +99,86 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("a😊"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("b"),
             term::ControlCodes::cursor_position(2, 2),
             term::control_codes().clear_attrs
 }
 
+frag! {
+    wide_char_cha { scrollback_lines: 100, width: 10, height: 10 }
+    <= term::Raw::from("A😊B"),
+       term::ControlCodes::cursor_horizontal_absolute(4),
+       term::Raw::from("x")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("A😊x"),
+            term::ControlCodes::cursor_position(1, 5),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    wide_char_wrap_bottom { scrollback_lines: 100, width: 2, height: 2 }
+    <= term::Raw::from("A\r\nB"),
+       term::Raw::from("😊")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("A"),
+            term::Crlf,
+            term::Raw::from("B"),
+            term::Crlf,
+            term::Raw::from("😊"),
+            term::ControlCodes::cursor_position(2, 3),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    pending_wrap_cub { scrollback_lines: 100, width: 5, height: 3 }
+    <= term::Raw::from("abcde"),
+       term::ControlCodes::cursor_backwards(1),
+       term::Raw::from("X")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("abcXe"),
+            term::ControlCodes::cursor_position(1, 5),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    pending_wrap_cuf { scrollback_lines: 100, width: 5, height: 3 }
+    <= term::Raw::from("abcde"),
+       term::ControlCodes::cursor_forward(1),
+       term::Raw::from("X")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("abcdX"),
+            term::ControlCodes::cursor_position(1, 6),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    pending_wrap_print { scrollback_lines: 100, width: 5, height: 3 }
+    <= term::Raw::from("abcde"),
+       term::Raw::from("f")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("abcde"),
+            term::Crlf,
+            term::Raw::from("f"),
+            term::ControlCodes::cursor_position(2, 2),
+            term::control_codes().clear_attrs
+}
+
 frag! {
     erase_display_to_end { scrollback_lines: 100, width: 10, height: 10 }
     <= term::Raw::from("ABCDEF"),
@@ -151,15 +229,15 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("1"),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::cursor_position(2, 2),
             term::control_codes().clear_attrs
     => ContentRegion::Screen =>
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
-            term::Crlf::default(),
+            term::Crlf,
             term::ControlCodes::cursor_position(2, 2),
             term::control_codes().clear_attrs
 }
@@ -212,13 +290,13 @@ frag! {
             term::control_codes().clear_screen,
 
             term::Raw::from("AX"),
-            term::Crlf::default(),
+            term::Crlf,
 
             term::Raw::from("   YE"),
-            term::Crlf::default(),
+            term::Crlf,
 
             term::Raw::from("   Z"),
-            term::Crlf::default(),
+            term::Crlf,
 
             term::Raw::from("AW"),
 
@@ -242,10 +320,10 @@ frag! {
 
 frag! {
     erase_display_to_end_with_decom { scrollback_lines: 100, width: 5, height: 5 }
-    <= term::Raw::from("11111"), term::Crlf::default(),
-       term::Raw::from("22222"), term::Crlf::default(),
-       term::Raw::from("33333"), term::Crlf::default(),
-       term::Raw::from("44444"), term::Crlf::default(),
+    <= term::Raw::from("11111"), term::Crlf,
+       term::Raw::from("22222"), term::Crlf,
+       term::Raw::from("33333"), term::Crlf,
+       term::Raw::from("44444"), term::Crlf,
        term::Raw::from("55555"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::control_codes().enable_scroll_region_origin_mode,
@@ -256,12 +334,12 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11111"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("22222"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("33"),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("55555"),
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(3, 3),
@@ -271,10 +349,10 @@ frag! {
 
 frag! {
     erase_display_from_start_with_decom { scrollback_lines: 100, width: 5, height: 5 }
-    <= term::Raw::from("11111"), term::Crlf::default(),
-       term::Raw::from("22222"), term::Crlf::default(),
-       term::Raw::from("33333"), term::Crlf::default(),
-       term::Raw::from("44444"), term::Crlf::default(),
+    <= term::Raw::from("11111"), term::Crlf,
+       term::Raw::from("22222"), term::Crlf,
+       term::Raw::from("33333"), term::Crlf,
+       term::Raw::from("44444"), term::Crlf,
        term::Raw::from("55555"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::control_codes().enable_scroll_region_origin_mode,
@@ -285,12 +363,12 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11111"),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("   33"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("44444"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("55555"),
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(3, 3),
@@ -298,12 +376,55 @@ frag! {
             term::control_codes().clear_attrs
 }
 
+frag! {
+    erase_display_from_start_with_decom_tall { scrollback_lines: 100, width: 10, height: 10 }
+    <= term::Raw::from("11111"), term::Crlf,
+       term::Raw::from("22222"), term::Crlf,
+       term::Raw::from("33333"), term::Crlf,
+       term::Raw::from("44444"), term::Crlf,
+       term::Raw::from("55555"),
+       term::ControlCodes::set_scroll_region(2, 4),
+       term::control_codes().enable_scroll_region_origin_mode,
+       term::ControlCodes::cursor_position(2, 3),
+       term::control_codes().erase_from_start
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("11111"),
+            term::Crlf,
+            term::Crlf,
+            term::Raw::from("   33"),
+            term::Crlf,
+            term::Raw::from("44444"),
+            term::Crlf,
+            term::Raw::from("55555"),
+            term::ControlCodes::set_scroll_region(2, 4),
+            term::ControlCodes::cursor_position(3, 3),
+            term::control_codes().enable_scroll_region_origin_mode,
+            term::control_codes().clear_attrs
+}
+
+#[test]
+fn erase_display_from_start_with_decom_matches_alt_screen() {
+    assert_screens_agree(
+        shpool_vterm::Size { width: 10, height: 10 },
+        &[
+            &term::Raw::from("11111\r\n22222\r\n33333\r\n44444\r\n55555"),
+            &term::ControlCodes::set_scroll_region(2, 4),
+            &term::control_codes().enable_scroll_region_origin_mode,
+            &term::ControlCodes::cursor_position(2, 3),
+            &term::control_codes().erase_from_start,
+        ],
+    );
+}
+
 frag! {
     erase_screen_with_decom { scrollback_lines: 100, width: 5, height: 5 }
-    <= term::Raw::from("11111"), term::Crlf::default(),
-       term::Raw::from("22222"), term::Crlf::default(),
-       term::Raw::from("33333"), term::Crlf::default(),
-       term::Raw::from("44444"), term::Crlf::default(),
+    <= term::Raw::from("11111"), term::Crlf,
+       term::Raw::from("22222"), term::Crlf,
+       term::Raw::from("33333"), term::Crlf,
+       term::Raw::from("44444"), term::Crlf,
        term::Raw::from("55555"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::control_codes().enable_scroll_region_origin_mode,
@@ -314,10 +435,10 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11111"),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("55555"),
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(3, 3),
@@ -339,3 +460,823 @@ frag! {
             term::ControlCodes::cursor_position(1, 5),
             term::control_codes().clear_attrs
 }
+
+frag! {
+    wide_char_overwrite_pad { scrollback_lines: 100, width: 10, height: 10 }
+    <= term::Raw::from("A😊B"),
+       term::ControlCodes::cursor_backwards(2), // onto the trailing column of 😊
+       term::Raw::from("x")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("A xB"),
+            term::ControlCodes::cursor_position(1, 4),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    wide_char_overwrite_start { scrollback_lines: 100, width: 10, height: 10 }
+    <= term::Raw::from("A😊B"),
+       term::ControlCodes::cursor_backwards(3), // onto 😊 itself
+       term::Raw::from("x")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("Ax B"),
+            term::ControlCodes::cursor_position(1, 3),
+            term::control_codes().clear_attrs
+}
+
+#[test]
+fn from_contents_round_trip() {
+    let size = shpool_vterm::Size { width: 10, height: 5 };
+    let mut term = shpool_vterm::Term::new(100, size);
+    term.process(b"\x1b[1mbold\x1b[0m\r\nplain\x1b[31m red\r\n\x1b[2;3H");
+    let contents = term.contents(ContentRegion::All);
+
+    let restored = shpool_vterm::Term::from_contents(100, size, &contents);
+    assert_eq!(restored.contents(ContentRegion::All), contents);
+}
+
+#[test]
+fn from_contents_without_prefix() {
+    let size = shpool_vterm::Size { width: 10, height: 5 };
+    let restored = shpool_vterm::Term::from_contents(100, size, b"foo");
+
+    let mut term = shpool_vterm::Term::new(100, size);
+    term.process(b"foo");
+    assert_eq!(restored.contents(ContentRegion::All), term.contents(ContentRegion::All));
+}
+
+frag! {
+    erase_line_to_end_bce { scrollback_lines: 100, width: 5, height: 2 }
+    <= term::Raw::from("abcde"),
+       term::ControlCodes::cursor_position(1, 3),
+       term::ControlCodes::bgcolor_idx(1),
+       term::control_codes().erase_to_end_of_line
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("ab"),
+            term::ControlCodes::bgcolor_idx(1),
+            term::Raw::from("   "),
+            term::control_codes().bgcolor_default,
+            term::ControlCodes::cursor_position(1, 3),
+            term::control_codes().clear_attrs,
+            term::ControlCodes::bgcolor_idx(1)
+}
+
+frag! {
+    erase_line_to_start_bce { scrollback_lines: 100, width: 5, height: 2 }
+    <= term::Raw::from("abcde"),
+       term::ControlCodes::cursor_position(1, 3),
+       term::ControlCodes::bgcolor_idx(1),
+       term::control_codes().erase_to_start_of_line
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::bgcolor_idx(1),
+            term::Raw::from("   "),
+            term::control_codes().bgcolor_default,
+            term::Raw::from("de"),
+            term::ControlCodes::cursor_position(1, 3),
+            term::control_codes().clear_attrs,
+            term::ControlCodes::bgcolor_idx(1)
+}
+
+frag! {
+    erase_whole_line_bce { scrollback_lines: 100, width: 5, height: 2 }
+    <= term::Raw::from("abc"),
+       term::ControlCodes::bgcolor_idx(1),
+       term::control_codes().erase_line
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::bgcolor_idx(1),
+            term::Raw::from("     "),
+            term::control_codes().bgcolor_default,
+            term::ControlCodes::cursor_position(1, 4),
+            term::control_codes().clear_attrs,
+            term::ControlCodes::bgcolor_idx(1)
+}
+
+frag! {
+    erase_line_bce_bg_only { scrollback_lines: 100, width: 3, height: 2 }
+    <= term::Raw::from("abc\r"),
+       term::control_codes().underline,
+       term::ControlCodes::bgcolor_idx(1),
+       term::control_codes().erase_line,
+       term::control_codes().clear_attrs
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::bgcolor_idx(1),
+            term::Raw::from("   "),
+            term::control_codes().bgcolor_default,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    selective_erase_line_keeps_protected { scrollback_lines: 100, width: 5, height: 2 }
+    <= term::Raw::from("a"),
+       term::ControlCodes::select_character_protection(true),
+       term::Raw::from("b"),
+       term::ControlCodes::select_character_protection(false),
+       term::Raw::from("c"),
+       term::ControlCodes::selective_erase_line(2)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from(" "),
+            term::ControlCodes::select_character_protection(true),
+            term::Raw::from("b"),
+            term::ControlCodes::select_character_protection(false),
+            term::ControlCodes::cursor_position(1, 4),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    line_size_survives_dump { scrollback_lines: 100, width: 5, height: 3 }
+    <= term::Raw::from("ab"),
+       term::LineSize::DoubleWidth.code(),
+       term::Crlf,
+       term::LineSize::DoubleHeightTop.code(),
+       term::Raw::from("cd"),
+       term::control_codes().erase_line
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::LineSize::DoubleWidth.code(),
+            term::Raw::from("ab"),
+            term::Crlf,
+            term::LineSize::DoubleHeightTop.code(),
+            term::ControlCodes::cursor_position(2, 3),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    erase_screen_clears_line_size { scrollback_lines: 100, width: 5, height: 3 }
+    <= term::Raw::from("ab"),
+       term::LineSize::DoubleWidth.code(),
+       term::Crlf,
+       term::Raw::from("cd"),
+       term::LineSize::DoubleHeightBottom.code(),
+       term::control_codes().erase_screen,
+       term::ControlCodes::cursor_position(1, 1),
+       term::Raw::from("x")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("x"),
+            term::Crlf,
+            term::ControlCodes::cursor_position(1, 2),
+            term::control_codes().clear_attrs
+}
+
+#[test]
+fn reset_clears_line_size() {
+    use shpool_vterm::{Size, Term};
+
+    let mut term = Term::new(100, Size { width: 5, height: 3 });
+    term.process(b"ab\x1b#6");
+    assert!(term.contents(ContentRegion::Screen).windows(2).any(|w| w == b"#6"));
+    term.process(b"\x1bcab");
+    assert!(!term.contents(ContentRegion::Screen).contains(&b'#'));
+
+    // Going back to single width can't be expressed by redrawing just
+    // the line, so damage tracking falls back to a full redraw.
+    term.process(b"\x1b#6");
+    term.flush_damage();
+    term.process(b"\x1b#5");
+    assert_eq!(term.flush_damage(), term.contents(ContentRegion::Screen));
+}
+
+frag! {
+    dump_trims_trailing_plain_blanks { scrollback_lines: 100, width: 5, height: 2 }
+    <= term::Raw::from("abcd"),
+       term::ControlCodes::cursor_position(1, 3),
+       term::control_codes().erase_to_end_of_line
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("ab"),
+            term::ControlCodes::cursor_position(1, 3),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    dump_keeps_trailing_bce_blanks { scrollback_lines: 100, width: 5, height: 2 }
+    <= term::Raw::from("abcd"),
+       term::ControlCodes::cursor_position(1, 3),
+       term::ControlCodes::bgcolor_idx(1),
+       term::control_codes().erase_to_end_of_line
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("ab"),
+            term::ControlCodes::bgcolor_idx(1),
+            term::Raw::from("   "),
+            term::control_codes().bgcolor_default,
+            term::ControlCodes::cursor_position(1, 3),
+            term::control_codes().clear_attrs,
+            term::ControlCodes::bgcolor_idx(1)
+}
+
+frag! {
+    erase_line_clears_protected { scrollback_lines: 100, width: 5, height: 2 }
+    <= term::Raw::from("a"),
+       term::ControlCodes::select_character_protection(true),
+       term::Raw::from("b"),
+       term::ControlCodes::select_character_protection(false),
+       term::Raw::from("c"),
+       term::control_codes().erase_line
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::cursor_position(1, 4),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    selective_erase_display_keeps_protected { scrollback_lines: 100, width: 5, height: 2 }
+    <= term::ControlCodes::select_character_protection(true),
+       term::Raw::from("a"),
+       term::ControlCodes::select_character_protection(false),
+       term::Raw::from("b\r\nc"),
+       term::ControlCodes::select_character_protection(true),
+       term::Raw::from("d"),
+       term::ControlCodes::selective_erase_display(2)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::select_character_protection(true),
+            term::Raw::from("a"),
+            term::Crlf,
+            term::ControlCodes::select_character_protection(false),
+            term::Raw::from(" "),
+            term::ControlCodes::select_character_protection(true),
+            term::Raw::from("d"),
+            term::ControlCodes::select_character_protection(false),
+            term::ControlCodes::cursor_position(2, 3),
+            term::control_codes().clear_attrs,
+            term::ControlCodes::select_character_protection(true)
+}
+
+#[test]
+fn render_with_overlay_top_row() {
+    use shpool_vterm::{Attrs, Cell, Pos, Size, Term};
+
+    let mut term = Term::new(100, Size { width: 5, height: 3 });
+    term.process(b"abc");
+    let plain = term.contents(ContentRegion::All);
+
+    let overlay = [
+        (Pos { row: 0, col: 1 }, Cell::new('X', Attrs::default())),
+        // Off screen, so ignored.
+        (Pos { row: 3, col: 0 }, Cell::new('Y', Attrs::default())),
+        (Pos { row: 0, col: 5 }, Cell::new('Z', Attrs::default())),
+    ];
+    let rendered = term.render_with_overlay(ContentRegion::All, &overlay);
+
+    let mut expected = vec![];
+    term::control_codes().clear_attrs.term_input_into(&mut expected);
+    term::ControlCodes::cursor_position(1, 1).term_input_into(&mut expected);
+    term::control_codes().clear_screen.term_input_into(&mut expected);
+    term::Raw::from("aXc").term_input_into(&mut expected);
+    term::ControlCodes::cursor_position(1, 4).term_input_into(&mut expected);
+    term::control_codes().clear_attrs.term_input_into(&mut expected);
+    assert_eq!(rendered, expected);
+
+    // The overlay never touches the real state.
+    assert_eq!(term.contents(ContentRegion::All), plain);
+    assert_eq!(term.cell_at(0, 1).unwrap().chars(), &['b']);
+}
+
+#[test]
+fn render_with_overlay_unwritten_row() {
+    use shpool_vterm::{Attrs, Cell, Pos, Size, Term};
+
+    let term = Term::new(100, Size { width: 5, height: 3 });
+    let rendered = term.render_with_overlay(
+        ContentRegion::Screen,
+        &[(Pos { row: 2, col: 0 }, Cell::new('S', Attrs::default()))],
+    );
+
+    let mut expected = vec![];
+    term::control_codes().clear_attrs.term_input_into(&mut expected);
+    term::ControlCodes::cursor_position(1, 1).term_input_into(&mut expected);
+    term::control_codes().clear_screen.term_input_into(&mut expected);
+    term::Crlf.term_input_into(&mut expected);
+    term::Crlf.term_input_into(&mut expected);
+    term::Raw::from("S").term_input_into(&mut expected);
+    term::ControlCodes::cursor_position(1, 1).term_input_into(&mut expected);
+    term::control_codes().clear_attrs.term_input_into(&mut expected);
+    assert_eq!(rendered, expected);
+}
+
+#[test]
+fn render_with_overlay_matches_writing_the_cells() {
+    use shpool_vterm::{Attrs, Cell, Pos, Size, Term};
+
+    let overlay = [
+        (Pos { row: 1, col: 2 }, Cell::new('X', Attrs::default())),
+        (Pos { row: 2, col: 0 }, Cell::new('中', Attrs::default())),
+    ];
+    for setup in [&b""[..], b"\x1b[?1049h"] {
+        for region in [
+            ContentRegion::All,
+            ContentRegion::Screen,
+            ContentRegion::CursorContext { above: 1, below: 0 },
+        ] {
+            let new_term = || {
+                let mut term = Term::new(100, Size { width: 5, height: 3 });
+                term.process(setup);
+                term.process(b"1\r\n2\r\n3\r\n4\r\n5");
+                term
+            };
+            let term = new_term();
+            let mut written = new_term();
+            written.process("\x1b7\x1b[2;3HX\x1b[3;1H中\x1b8".as_bytes());
+            assert_eq!(
+                term.render_with_overlay(region.clone(), &overlay),
+                written.contents(region.clone()),
+                "{region:?} {setup:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn set_viewport_height_keeps_wrapping() {
+    use shpool_vterm::{Size, Term, TextOptions};
+
+    let mut term = Term::new(100, Size { width: 5, height: 4 });
+    term.process(b"abcdefgh\r\n1\r\n2\r\n3");
+    let text = term.text(ContentRegion::All, TextOptions::default());
+
+    term.set_viewport_height(2);
+    assert_eq!(term.size(), Size { width: 5, height: 2 });
+    assert_eq!(term.text(ContentRegion::All, TextOptions::default()), text);
+    assert_eq!(term.cell_at(0, 0).unwrap().chars(), &['2']);
+    assert_eq!(term.cell_at(1, 0).unwrap().chars(), &['3']);
+    assert!(term.cell_at(2, 0).is_none());
+
+    // The cursor was clamped into the smaller window.
+    term.process(b"x");
+    assert_eq!(term.cell_at(1, 1).unwrap().chars(), &['x']);
+
+    term.set_viewport_height(4);
+    assert_eq!(term.cell_at(0, 0).unwrap().chars(), &['f']);
+    assert_eq!(term.text(ContentRegion::All, TextOptions::default()), "abcdefgh\n1\n2\n3x\n");
+}
+
+frag! {
+    cursor_context { scrollback_lines: 100, width: 10, height: 10 }
+    <= term::Raw::from("0\r\n1\r\n2\r\n3\r\n4\r\n5\r\n6\r\n7\r\n8\r\n9"),
+       term::ControlCodes::cursor_position(6, 3)
+    => ContentRegion::CursorContext { above: 2, below: 1 } =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("3"),
+            term::Crlf,
+            term::Raw::from("4"),
+            term::Crlf,
+            term::Raw::from("5"),
+            term::Crlf,
+            term::Raw::from("6"),
+            term::ControlCodes::cursor_position(3, 3),
+            term::control_codes().clear_attrs
+}
+
+#[test]
+fn cursor_context_clamped() {
+    use shpool_vterm::{Size, Term, TextOptions};
+
+    let mut term = Term::new(100, Size { width: 10, height: 6 });
+    term.process(b"0\r\n1\r\n2\r\n3\r\n4\r\n5\x1b[2;1H");
+    let near_top = ContentRegion::CursorContext { above: 5, below: 1 };
+    assert_eq!(term.text(near_top, TextOptions::default()), "0\n1\n2\n");
+
+    term.process(b"\x1b[6;1H");
+    let near_bottom = ContentRegion::CursorContext { above: 1, below: 100 };
+    assert_eq!(term.text(near_bottom, TextOptions::default()), "4\n5\n");
+}
+
+#[test]
+fn blank_rows_above_print_survive_reflow() {
+    use shpool_vterm::{Size, Term, TextOptions};
+
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    term.process(b"\x1b[5;1Hx");
+    term.resize(Size { width: 4, height: 10 });
+    term.resize(Size { width: 10, height: 10 });
+
+    for row in 0..4 {
+        assert!(term.cell_at(row, 0).unwrap().is_empty(), "row {row} should be blank");
+    }
+    assert_eq!(term.cell_at(4, 0).unwrap().chars(), &['x']);
+    // Exactly one line per row, nothing extra.
+    assert_eq!(term.text(ContentRegion::All, TextOptions::default()), "\n\n\n\nx\n");
+
+    // The cursor stays right after the x.
+    term.process(b"y");
+    assert_eq!(term.cell_at(4, 1).unwrap().chars(), &['y']);
+}
+
+#[test]
+fn grapheme_at_combining_accent() {
+    use shpool_vterm::{Pos, Size, Term};
+
+    let mut term = Term::new(100, Size { width: 10, height: 4 });
+    term.process("e\u{301}x".as_bytes());
+    assert_eq!(term.grapheme_at(Pos { row: 0, col: 0 }).as_deref(), Some("e\u{301}"));
+    assert_eq!(term.grapheme_at(Pos { row: 0, col: 1 }).as_deref(), Some("x"));
+    assert_eq!(term.grapheme_at(Pos { row: 0, col: 2 }).as_deref(), Some(""));
+    assert_eq!(term.grapheme_at(Pos { row: 9, col: 0 }), None);
+}
+
+#[test]
+fn leading_combining_mark_joins_next_char() {
+    use shpool_vterm::{Pos, Size, Term};
+
+    let mut term = Term::new(100, Size { width: 10, height: 4 });
+    term.process("\u{301}e".as_bytes());
+    assert_eq!(term.grapheme_at(Pos { row: 0, col: 0 }).as_deref(), Some("e\u{301}"));
+    assert_eq!(term.grapheme_at(Pos { row: 0, col: 1 }).as_deref(), Some(""));
+
+    // Same at the start of a later line, and the mark only goes to the
+    // one char.
+    term.process("\r\n\u{301}\u{308}ab".as_bytes());
+    assert_eq!(term.grapheme_at(Pos { row: 1, col: 0 }).as_deref(), Some("a\u{301}\u{308}"));
+    assert_eq!(term.grapheme_at(Pos { row: 1, col: 1 }).as_deref(), Some("b"));
+}
+
+#[test]
+fn combining_marks_are_capped() {
+    use shpool_vterm::{Pos, Size, Term};
+
+    let mut term = Term::new(100, Size { width: 10, height: 4 });
+    let mut input = String::from("a");
+    input.extend(std::iter::repeat('\u{301}').take(100));
+    input.push('x');
+    term.process(input.as_bytes());
+
+    let cluster = term.grapheme_at(Pos { row: 0, col: 0 }).unwrap();
+    assert!(cluster.starts_with("a\u{301}"));
+    assert_eq!(cluster.chars().count(), 17);
+    assert_eq!(term.grapheme_at(Pos { row: 0, col: 1 }).as_deref(), Some("x"));
+}
+
+#[test]
+fn grapheme_at_zwj_emoji() {
+    use shpool_vterm::{Pos, Size, Term};
+
+    let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}";
+    let mut term = Term::new(100, Size { width: 10, height: 4 });
+    term.process(format!("{family}x").as_bytes());
+    assert_eq!(term.grapheme_at(Pos { row: 0, col: 0 }).as_deref(), Some(family));
+    // The second column of the wide cell reports the same cluster.
+    assert_eq!(term.grapheme_at(Pos { row: 0, col: 1 }).as_deref(), Some(family));
+    assert_eq!(term.grapheme_at(Pos { row: 0, col: 2 }).as_deref(), Some("x"));
+}
+
+#[test]
+fn snapshot_pads_to_grid() {
+    use shpool_vterm::{Attrs, Cell, Color, Size, Term};
+
+    let mut term = Term::new(100, Size { width: 3, height: 3 });
+    term.process(b"\x1b[31mab\x1b[m\r\n\x1b[44mc");
+
+    let red = Attrs { fgcolor: Color::Idx(1), ..Attrs::default() };
+    let blue_bg = Attrs { bgcolor: Color::Idx(4), ..Attrs::default() };
+    let expected = vec![
+        vec![Cell::new('a', red.clone()), Cell::new('b', red), Cell::empty()],
+        vec![Cell::new('c', blue_bg), Cell::empty(), Cell::empty()],
+        vec![Cell::empty(), Cell::empty(), Cell::empty()],
+    ];
+    assert_eq!(term.snapshot(ContentRegion::Screen), expected);
+    assert_eq!(term.snapshot(ContentRegion::CursorContext { above: 0, below: 0 }), expected[1..2]);
+}
+
+#[test]
+fn cell_diff_reports_changed_cells() {
+    use shpool_vterm::{Attrs, Cell, FontWeight, Pos, Size, Term};
+
+    let mut term = Term::new(100, Size { width: 5, height: 3 });
+    term.process(b"abc\r\nde");
+    let prev = term.snapshot(ContentRegion::Screen);
+    assert!(term.cell_diff(&prev).is_empty());
+
+    term.process(b"\x1b[1;2Hx\x1b[3;5H\x1b[1my");
+    let bold = Attrs { font_weight: Some(FontWeight::Bold), ..Attrs::default() };
+    assert_eq!(
+        term.cell_diff(&prev),
+        vec![
+            (Pos { row: 0, col: 1 }, Cell::new('x', Attrs::default())),
+            (Pos { row: 2, col: 4 }, Cell::new('y', bold)),
+        ]
+    );
+
+    // A snapshot of a different size gets everything.
+    term.resize(Size { width: 6, height: 3 });
+    assert_eq!(term.cell_diff(&prev).len(), 18);
+}
+
+#[test]
+fn content_hash_tracks_changes() {
+    use shpool_vterm::{Size, Term};
+
+    let mut term = Term::new(100, Size { width: 5, height: 3 });
+    term.process(b"abc\r\nde");
+    let hash = term.content_hash(ContentRegion::Screen);
+
+    // Moving the cursor around or redrawing the same thing is a no-op.
+    term.process(b"\x1b[1;1Habc\x1b[H\x1b[31m\x1b[m");
+    assert_eq!(term.content_hash(ContentRegion::Screen), hash);
+
+    // Unwritten space hashes the same as explicitly erased space.
+    term.process(b"\x1b[3;1H\x1b[K\x1b[1;4H\x1b[K");
+    assert_eq!(term.content_hash(ContentRegion::Screen), hash);
+
+    // Changing a glyph or just its attrs changes the hash.
+    term.process(b"\x1b[1;2Hx");
+    let changed = term.content_hash(ContentRegion::Screen);
+    assert_ne!(changed, hash);
+    term.process(b"\x1b[1;2H\x1b[1mx");
+    assert_ne!(term.content_hash(ContentRegion::Screen), changed);
+
+    // The same content hashes the same on another terminal.
+    let mut other = Term::new(100, Size { width: 5, height: 3 });
+    other.process(b"abc\r\nde");
+    assert_eq!(other.content_hash(ContentRegion::Screen), hash);
+}
+
+#[test]
+fn clear_scrollback_keeps_screen() {
+    use shpool_vterm::{Size, Term, TextOptions};
+
+    let mut term = Term::new(100, Size { width: 10, height: 3 });
+    term.process(b"\x1b]133;A\x07");
+    for i in 0..10 {
+        term.process(format!("line{i}\r\n").as_bytes());
+    }
+    term.process(b"\x1b]133;A\x07line10");
+    let screen = term.text(ContentRegion::Screen, TextOptions::default());
+
+    term.clear_scrollback();
+    assert_eq!(term.text(ContentRegion::All, TextOptions::default()), screen);
+    assert_eq!(term.text(ContentRegion::Screen, TextOptions::default()), screen);
+    // Only the mark on the visible screen survives, and it still lines
+    // up with its row.
+    assert_eq!(term.prompt_marks().len(), 1);
+    assert_eq!(term.prompt_marks()[0].0, term.line_id_at(2).unwrap());
+
+    // The cursor is untouched, so writing carries on where it was.
+    term.process(b"!");
+    assert_eq!(term.cell_at(2, 6).unwrap().chars(), &['!']);
+}
+
+#[test]
+fn flush_damage_touches_dirty_rows() {
+    use shpool_vterm::{Size, Term};
+
+    let size = Size { width: 10, height: 6 };
+    let mut term = Term::new(100, size);
+    term.process(b"top\r\nline");
+    let full = term.flush_damage();
+    assert_eq!(full, term.contents(ContentRegion::Screen));
+    assert!(term.flush_damage().is_empty());
+
+    term.process(b"\x1b[2;1Hab\x1b[5;1H\x1b[1mcd");
+    let partial = term.flush_damage();
+    let mut expected = vec![];
+    term::control_codes().clear_attrs.term_input_into(&mut expected);
+    term::ControlCodes::cursor_position(2, 1).term_input_into(&mut expected);
+    term::control_codes().erase_line.term_input_into(&mut expected);
+    term::Raw::from("abne").term_input_into(&mut expected);
+    term::ControlCodes::cursor_position(5, 1).term_input_into(&mut expected);
+    term::control_codes().erase_line.term_input_into(&mut expected);
+    term::control_codes().bold.term_input_into(&mut expected);
+    term::Raw::from("cd").term_input_into(&mut expected);
+    term::control_codes().reset_font_weight.term_input_into(&mut expected);
+    term::ControlCodes::cursor_position(5, 3).term_input_into(&mut expected);
+    term::control_codes().bold.term_input_into(&mut expected);
+    assert_eq!(partial, expected);
+
+    // Replaying the flushes gets another terminal to the same place.
+    let mut mirror = Term::new(100, size);
+    mirror.process(&full);
+    mirror.process(&partial);
+    assert_eq!(mirror.contents(ContentRegion::Screen), term.contents(ContentRegion::Screen));
+
+    // Lots of damage falls back to a full redraw.
+    term.process(b"\x1b[1;1H1\r\n2\r\n3\r\n4");
+    assert_eq!(term.flush_damage(), term.contents(ContentRegion::Screen));
+}
+
+#[test]
+fn flush_damage_replays_onto_another_term() {
+    use shpool_vterm::{Size, Term};
+
+    fn assert_mirrors(term: &Term, mirror: &Term, what: &str) {
+        let size = term.size();
+        for row in 0..size.height {
+            for col in 0..size.width {
+                let pos = term::Pos { row, col };
+                assert_eq!(
+                    mirror.cell_at(row, col).map(|c| c.chars().to_vec()),
+                    term.cell_at(row, col).map(|c| c.chars().to_vec()),
+                    "{what}: chars at {pos:?}"
+                );
+                assert_eq!(mirror.attrs_at(pos), term.attrs_at(pos), "{what}: attrs at {pos:?}");
+            }
+        }
+    }
+
+    let size = Size { width: 5, height: 6 };
+    for (what, setup, change) in [
+        // The attrs in effect after the last flush don't leak into rows.
+        ("stale attrs", &b"\x1b[31m"[..], &b"\x1b[m\x1b[4;1Hplain\x1b[31m"[..]),
+        // A row that fills the width keeps its last column.
+        ("full row", b"", b"\x1b[3;1Hworld"),
+        // Neither origin mode nor margins confine the redrawn rows.
+        ("decom", b"\x1b[2;4r\x1b[?6h", b"\x1b[1;1Hab"),
+        ("margins", b"\x1b[?69h\x1b[2;3s", b"\x1b[2;1Habcde\x1b[6;1Hxy"),
+    ] {
+        let mut term = Term::new(100, size);
+        term.process(b"1\r\n2\r\n3\r\n4\r\n5\r\n6\x1b[H");
+        term.process(setup);
+        let mut mirror = Term::new(100, size);
+        mirror.process(&term.flush_damage());
+        assert_mirrors(&term, &mirror, what);
+
+        term.process(change);
+        let damage = term.flush_damage();
+        assert_ne!(damage, term.contents(ContentRegion::Screen), "{what}: full redraw");
+        mirror.process(&damage);
+        assert_mirrors(&term, &mirror, what);
+    }
+}
+
+#[test]
+fn reflow_moves_straddling_wide_char() {
+    use shpool_vterm::{Size, Term};
+
+    let mut term = Term::new(100, Size { width: 5, height: 4 });
+    term.process("abc中d".as_bytes());
+    term.resize(Size { width: 4, height: 4 });
+
+    assert_eq!(term.cell_at(0, 2).unwrap().chars(), &['c']);
+    assert!(term.cell_at(0, 3).unwrap().is_empty());
+    assert_eq!(term.cell_at(1, 0).unwrap().chars(), &['中']);
+    assert!(term.cell_at(1, 1).unwrap().is_wide_pad());
+    assert_eq!(term.cell_at(1, 2).unwrap().chars(), &['d']);
+}
+
+#[test]
+fn scroll_region_api_matches_decstbm() {
+    use shpool_vterm::{Size, Term};
+
+    let size = Size { width: 4, height: 5 };
+    let mut api = Term::new(100, size);
+    let mut parsed = Term::new(100, size);
+    api.process(b"1\r\n2\r\n3\r\n4\r\n5");
+    parsed.process(b"1\r\n2\r\n3\r\n4\r\n5");
+    assert_eq!(api.scroll_region(), None);
+
+    // The bottom gets clamped to the screen height.
+    assert!(api.set_scroll_region(1, 50));
+    parsed.process(b"\x1b[2;50r");
+    assert_eq!(api.scroll_region(), Some((1, 5)));
+    assert_eq!(api.contents(ContentRegion::All), parsed.contents(ContentRegion::All));
+
+    // Inverted and single row regions are rejected.
+    assert!(!api.set_scroll_region(3, 2));
+    assert!(!api.set_scroll_region(2, 3));
+    assert_eq!(api.scroll_region(), Some((1, 5)));
+
+    assert!(api.set_scroll_region(1, 3));
+    parsed.process(b"\x1b[2;3r");
+    api.process(b"\x1b[3;1H\n\nx");
+    parsed.process(b"\x1b[3;1H\n\nx");
+    assert_eq!(api.contents(ContentRegion::All), parsed.contents(ContentRegion::All));
+}
+
+#[test]
+fn newline_at_bottom_scrolls_into_scrollback() {
+    use shpool_vterm::{Size, Term, TextOptions};
+
+    let mut term = Term::new(100, Size { width: 5, height: 2 });
+    term.process(b"one\r\n");
+    assert!(term.visible_cursor_row_is_last());
+    term.process(b"two\r\nthree");
+    assert!(term.visible_cursor_row_is_last());
+
+    assert_eq!(term.text(ContentRegion::Screen, TextOptions::default()), "two\nthree\n");
+    assert_eq!(term.text(ContentRegion::All, TextOptions::default()), "one\ntwo\nthree\n");
+
+    // Below a scroll region that ends higher up, a line feed on the last
+    // row does not scroll.
+    let mut term = Term::new(100, Size { width: 5, height: 3 });
+    term.process(b"a\r\nb\r\nc\x1b[1;2r\x1b[3;1H\n");
+    assert!(term.visible_cursor_row_is_last());
+    assert_eq!(term.text(ContentRegion::All, TextOptions::default()), "a\nb\nc\n");
+}
+
+#[test]
+fn max_line_len_chunks_long_lines() {
+    use shpool_vterm::{Size, Term, TextOptions};
+
+    let mut term = Term::new(1000, Size { width: 5, height: 3 });
+    assert_eq!(term.max_line_len(), None);
+    // Rounded down to two rows of five.
+    term.set_max_line_len(Some(12));
+    term.process("x".repeat(10_000).as_bytes());
+
+    let text = term.text(ContentRegion::All, TextOptions::default());
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 500);
+    assert!(lines.iter().all(|line| *line == "xxxxxxxxxx"));
+
+    // The cap survives a reset, and a newline starts the count over.
+    term.reset();
+    assert_eq!(term.max_line_len(), Some(12));
+    term.process(b"abcdefg\r\nhijklmnopqrstu");
+    assert_eq!(
+        term.text(ContentRegion::All, TextOptions::default()),
+        "abcdefg\nhijklmnopq\nrstu\n"
+    );
+
+    // Without a cap the whole thing is one logical line.
+    let mut term = Term::new(1000, Size { width: 5, height: 3 });
+    term.process("x".repeat(100).as_bytes());
+    assert_eq!(term.text(ContentRegion::All, TextOptions::default()), "x".repeat(100) + "\n");
+}
+
+#[test]
+fn bulk_ascii_matches_per_char() {
+    use shpool_vterm::{Size, Term};
+
+    // Plain text with the odd bit of styling, a combining mark, a wide
+    // char and some line breaks, so runs start and stop all over the place.
+    let mut input = vec![];
+    for i in 0..20_000 {
+        input.extend_from_slice(b"the quick brown fox ");
+        match i % 7 {
+            0 => input.extend_from_slice(b"\x1b[1mjumps\x1b[m "),
+            1 => input.extend_from_slice("e\u{301} ".as_bytes()),
+            2 => input.extend_from_slice("\u{1F600}".as_bytes()),
+            3 => input.extend_from_slice(b"\r\n"),
+            _ => {}
+        }
+    }
+
+    let size = Size { width: 33, height: 10 };
+    let mut bulk = Term::new(200, size);
+    let start = std::time::Instant::now();
+    bulk.process(&input);
+    // Very loose, just to catch the fast path going quadratic.
+    assert!(start.elapsed() < std::time::Duration::from_secs(30));
+
+    // One byte at a time never builds up a run longer than a char.
+    let mut per_char = Term::new(200, size);
+    for byte in input.iter() {
+        per_char.process(std::slice::from_ref(byte));
+    }
+    assert_eq!(bulk.contents(ContentRegion::All), per_char.contents(ContentRegion::All));
+}
+
+#[test]
+fn logical_line_joins_wraps() {
+    use shpool_vterm::{Size, Term, TextOptions};
+
+    let mut term = Term::new(100, Size { width: 4, height: 3 });
+    term.process("one\r\nabc\u{1F600}de\u{301}fgh\r\n\r\nlast".as_bytes());
+
+    assert_eq!(term.logical_line(0).as_deref(), Some("one"));
+    // The wide char doesn't fit on the first row, so that row wraps early
+    // without leaving a gap in the text.
+    assert_eq!(term.logical_line(1).as_deref(), Some("abc\u{1F600}de\u{301}fgh"));
+    assert_eq!(term.logical_line(2).as_deref(), Some(""));
+    assert_eq!(term.logical_line(3).as_deref(), Some("last"));
+    assert_eq!(term.logical_line(4), None);
+
+    let text = term.text(ContentRegion::All, TextOptions::default());
+    let lines: Vec<String> = (0..).map_while(|i| term.logical_line(i)).collect();
+    assert_eq!(lines, text.lines().collect::<Vec<_>>());
+}
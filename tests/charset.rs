@@ -0,0 +1,146 @@
+#[macro_use]
+#[path = "support/mod.rs"]
+mod support;
+
+use shpool_vterm::{term, ContentRegion};
+
+frag! {
+    g0_special_graphics { scrollback_lines: 100, width: 10, height: 10 }
+    <= term::ControlCodes::designate_charset(0, term::Charset::DecSpecialGraphics),
+       term::Raw::from("lqk")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("┌─┐"),
+            term::ControlCodes::cursor_position(1, 4),
+            term::control_codes().clear_attrs,
+            term::ControlCodes::designate_charset(0, term::Charset::DecSpecialGraphics)
+}
+
+frag! {
+    g0_back_to_ascii { scrollback_lines: 100, width: 10, height: 10 }
+    <= term::ControlCodes::designate_charset(0, term::Charset::DecSpecialGraphics),
+       term::Raw::from("x"),
+       term::ControlCodes::designate_charset(0, term::Charset::Ascii),
+       term::Raw::from("x")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("│x"),
+            term::ControlCodes::cursor_position(1, 3),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    shift_out_g1 { scrollback_lines: 100, width: 10, height: 10 }
+    <= term::ControlCodes::designate_charset(1, term::Charset::DecSpecialGraphics),
+       term::control_codes().shift_out,
+       term::Raw::from("lqk"),
+       term::control_codes().shift_in,
+       term::Raw::from("lqk")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("┌─┐lqk"),
+            term::ControlCodes::cursor_position(1, 7),
+            term::control_codes().clear_attrs,
+            term::ControlCodes::designate_charset(1, term::Charset::DecSpecialGraphics)
+}
+
+frag! {
+    shift_out_persists { scrollback_lines: 100, width: 10, height: 10 }
+    <= term::ControlCodes::designate_charset(1, term::Charset::DecSpecialGraphics),
+       term::control_codes().shift_out,
+       term::Raw::from("q"),
+       term::ControlCodes::cursor_position(2, 1),
+       term::Raw::from("q")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("─"),
+            term::Crlf,
+            term::Raw::from("─"),
+            term::ControlCodes::cursor_position(2, 2),
+            term::control_codes().clear_attrs,
+            term::ControlCodes::designate_charset(1, term::Charset::DecSpecialGraphics),
+            term::control_codes().shift_out
+}
+
+frag! {
+    shift_out_reset_ris { scrollback_lines: 100, width: 10, height: 10 }
+    <= term::ControlCodes::designate_charset(1, term::Charset::DecSpecialGraphics),
+       term::control_codes().shift_out,
+       term::control_codes().hard_reset,
+       term::Raw::from("q")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("q"),
+            term::ControlCodes::cursor_position(1, 2),
+            term::control_codes().clear_attrs
+}
+
+#[test]
+fn latin1_mode() {
+    use shpool_vterm::{CharsetMode, Size, Term};
+
+    let mut term = Term::new(100, Size { width: 10, height: 2 });
+    term.process(b"\x1b%@\xe9");
+    assert_eq!(term.charset_mode(), CharsetMode::Latin1);
+    assert_eq!(term.cell_at(0, 0).unwrap().chars(), &['é']);
+    assert!(term.cell_at(0, 1).unwrap().is_empty());
+
+    // Switching back mid-buffer decodes the rest as UTF-8.
+    term.process("\x1b%Gé".as_bytes());
+    assert_eq!(term.charset_mode(), CharsetMode::Utf8);
+    assert_eq!(term.cell_at(0, 1).unwrap().chars(), &['é']);
+    assert!(term.cell_at(0, 2).unwrap().is_empty());
+}
+
+#[test]
+fn latin1_mode_api() {
+    use shpool_vterm::{CharsetMode, Size, Term};
+
+    let mut term = Term::new(100, Size { width: 10, height: 2 });
+    term.set_charset_mode(CharsetMode::Latin1);
+    term.process(b"\xe9\xe8");
+    assert_eq!(term.cell_at(0, 0).unwrap().chars(), &['é']);
+    assert_eq!(term.cell_at(0, 1).unwrap().chars(), &['è']);
+}
+
+frag! {
+    latin1_mode_dump { scrollback_lines: 100, width: 10, height: 2 }
+    <= term::control_codes().select_latin1,
+       term::Raw::new(vec![0xe9])
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("é"),
+            term::ControlCodes::cursor_position(1, 2),
+            term::control_codes().clear_attrs,
+            term::control_codes().select_latin1
+}
+
+#[test]
+fn save_restore_charsets_and_origin_mode() {
+    use shpool_vterm::{Size, Term};
+
+    for (save, restore) in [(&b"\x1b7"[..], &b"\x1b8"[..]), (b"\x1b[s", b"\x1b[u")] {
+        let mut term = Term::new(100, Size { width: 10, height: 10 });
+        // Special graphics in G0, origin mode on inside rows 3 through 8.
+        term.process(b"\x1b(0\x1b[3;8r\x1b[?6h");
+        term.process(save);
+        term.process(b"\x1b(B\x1b[?6l");
+        term.process(restore);
+
+        // CUP is relative to the scroll region again, and q draws a line.
+        term.process(b"\x1b[1;1Hq");
+        assert_eq!(term.cell_at(2, 0).unwrap().chars(), &['─']);
+    }
+}
@@ -0,0 +1,492 @@
+#[macro_use]
+#[path = "support/mod.rs"]
+mod support;
+
+use shpool_vterm::{term, term::AsTermInput, ContentRegion, Key, Modifiers, Size, Term};
+
+frag! {
+    column_mode_clears { scrollback_lines: 10, width: 10, height: 3 }
+    <= term::Raw::from("abc\r\ndef"),
+       term::ControlCodes::set_scroll_region(2, 3),
+       term::control_codes().enable_132_column_mode
+    => ContentRegion::Screen =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Crlf,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs
+}
+
+#[test]
+fn height_request() {
+    let mut term = Term::new(10, Size { width: 10, height: 3 });
+    assert_eq!(term.take_height_request(), None);
+
+    // DECSLPP
+    term.process(b"\x1b[36t");
+    assert_eq!(term.take_height_request(), Some(36));
+    assert_eq!(term.take_height_request(), None);
+
+    // XTWINOPS resize, where 0 keeps the current size.
+    term.process(b"\x1b[8;40;100t");
+    assert_eq!(term.take_height_request(), Some(40));
+    assert_eq!(term.take_column_request(), Some(100));
+    term.process(b"\x1b[8;0;90t");
+    assert_eq!(term.take_height_request(), None);
+    assert_eq!(term.take_column_request(), Some(90));
+
+    // Other window ops are not size requests.
+    term.process(b"\x1b[18t\x1b[22;0t\x1b[>2t");
+    assert_eq!(term.take_height_request(), None);
+    assert_eq!(term.take_column_request(), None);
+}
+
+#[test]
+fn column_mode_request() {
+    let mut term = Term::new(10, Size { width: 10, height: 3 });
+    assert_eq!(term.take_column_request(), None);
+
+    let mut input = vec![];
+    term::control_codes().enable_132_column_mode.term_input_into(&mut input);
+    term.process(input.as_slice());
+    assert_eq!(term.take_column_request(), Some(132));
+    assert_eq!(term.take_column_request(), None);
+
+    let mut input = vec![];
+    term::control_codes().disable_132_column_mode.term_input_into(&mut input);
+    term.process(input.as_slice());
+    assert_eq!(term.take_column_request(), Some(80));
+
+    // We never resize on our own, that is up to the embedder.
+    assert_eq!(term.size(), Size { width: 10, height: 3 });
+}
+
+frag! {
+    reverse_video { scrollback_lines: 10, width: 10, height: 3 }
+    <= term::Raw::from("a"),
+       term::control_codes().enable_reverse_video
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("a"),
+            term::ControlCodes::cursor_position(1, 2),
+            term::control_codes().clear_attrs,
+            term::control_codes().enable_reverse_video
+}
+
+frag! {
+    disable_reverse_video { scrollback_lines: 10, width: 10, height: 3 }
+    <= term::control_codes().enable_reverse_video,
+       term::control_codes().disable_reverse_video
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs
+}
+
+#[test]
+fn reverse_video_accessor() {
+    let mut term = Term::new(10, Size { width: 10, height: 3 });
+    assert!(!term.reverse_video());
+
+    let mut input = vec![];
+    term::control_codes().enable_reverse_video.term_input_into(&mut input);
+    term.process(input.as_slice());
+    assert!(term.reverse_video());
+
+    let mut input = vec![];
+    term::control_codes().disable_reverse_video.term_input_into(&mut input);
+    term.process(input.as_slice());
+    assert!(!term.reverse_video());
+}
+
+frag! {
+    modify_other_keys { scrollback_lines: 100, width: 10, height: 2 }
+    <= term::ControlCodes::set_modify_other_keys(2),
+       term::Raw::from("a")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("a"),
+            term::ControlCodes::cursor_position(1, 2),
+            term::control_codes().clear_attrs,
+            term::ControlCodes::set_modify_other_keys(2)
+}
+
+frag! {
+    reset_key_modifier_options_keeps_attrs { scrollback_lines: 100, width: 10, height: 2 }
+    <= term::control_codes().bold,
+       term::ControlCodes::set_modify_other_keys(2),
+       term::control_codes().reset_key_modifier_options,
+       term::Raw::from("a")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::control_codes().bold,
+            term::Raw::from("a"),
+            term::control_codes().reset_font_weight,
+            term::ControlCodes::cursor_position(1, 2),
+            term::control_codes().clear_attrs,
+            term::control_codes().bold
+}
+
+#[test]
+fn reset_key_modifier_options() {
+    let mut term = Term::new(100, Size { width: 10, height: 2 });
+    let mut input = vec![];
+    term::ControlCodes::set_modify_other_keys(2).term_input_into(&mut input);
+    term.process(&input);
+    assert_eq!(term.modify_other_keys(), 2);
+
+    input.clear();
+    term::control_codes().reset_key_modifier_options.term_input_into(&mut input);
+    term.process(&input);
+    assert_eq!(term.modify_other_keys(), 0);
+}
+
+#[test]
+fn key_modifier_options_for_other_resources() {
+    let mut term = Term::new(100, Size { width: 10, height: 2 });
+    term.process(b"\x1b[>4;2m");
+    assert_eq!(term.modify_other_keys(), 2);
+
+    // Resource 0 is modifyKeyboard, not a missing resource, so it must
+    // not reset modifyOtherKeys.
+    term.process(b"\x1b[>0;1m");
+    assert_eq!(term.modify_other_keys(), 2);
+    term.process(b"\x1b[>1;2m");
+    assert_eq!(term.modify_other_keys(), 2);
+
+    term.process(b"\x1b[>4m");
+    assert_eq!(term.modify_other_keys(), 0);
+}
+
+frag! {
+    keyboard_flags_stack { scrollback_lines: 100, width: 10, height: 2 }
+    <= term::ControlCodes::push_keyboard_flags(1),
+       term::ControlCodes::push_keyboard_flags(3),
+       term::ControlCodes::push_keyboard_flags(7),
+       term::ControlCodes::pop_keyboard_flags(1)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs,
+            term::ControlCodes::push_keyboard_flags(1),
+            term::ControlCodes::push_keyboard_flags(3)
+}
+
+frag! {
+    newline_mode_lf_returns_carriage { scrollback_lines: 10, width: 10, height: 3 }
+    <= term::control_codes().enable_newline_mode,
+       term::Raw::from("ab\ncd")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("ab"),
+            term::Crlf,
+            term::Raw::from("cd"),
+            term::ControlCodes::cursor_position(2, 3),
+            term::control_codes().clear_attrs,
+            term::control_codes().enable_newline_mode
+}
+
+frag! {
+    newline_mode_off { scrollback_lines: 10, width: 10, height: 3 }
+    <= term::control_codes().enable_newline_mode,
+       term::control_codes().disable_newline_mode,
+       term::Raw::from("ab\ncd")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("ab"),
+            term::Crlf,
+            term::Raw::from("  cd"),
+            term::ControlCodes::cursor_position(2, 5),
+            term::control_codes().clear_attrs
+}
+
+#[test]
+fn newline_mode_accessor() {
+    let mut term = Term::new(10, Size { width: 10, height: 3 });
+    assert!(!term.newline_mode());
+    term.process(b"\x1b[20h");
+    assert!(term.newline_mode());
+    term.process(b"\x1b[20l");
+    assert!(!term.newline_mode());
+}
+
+frag! {
+    insert_mode_rep_inserts { scrollback_lines: 10, width: 10, height: 3 }
+    <= term::control_codes().enable_insert_mode,
+       term::Raw::from("ab"),
+       term::ControlCodes::cursor_backwards(1),
+       term::Raw::from("x"),
+       term::ControlCodes::repeat_char(2)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("axxxb"),
+            term::ControlCodes::cursor_position(1, 5),
+            term::control_codes().clear_attrs,
+            term::control_codes().enable_insert_mode
+}
+
+frag! {
+    replace_mode_rep_overwrites { scrollback_lines: 10, width: 10, height: 3 }
+    <= term::control_codes().enable_insert_mode,
+       term::control_codes().disable_insert_mode,
+       term::Raw::from("abcd"),
+       term::ControlCodes::cursor_backwards(3),
+       term::Raw::from("x"),
+       term::ControlCodes::repeat_char(2)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("axxx"),
+            term::ControlCodes::cursor_position(1, 5),
+            term::control_codes().clear_attrs
+}
+
+#[test]
+fn insert_mode_pushes_tail_off_the_edge() {
+    let mut term = Term::new(10, Size { width: 5, height: 2 });
+    term.process(b"abcde\x1b[4h\x1b[1;2Hx\x1b[b");
+    assert!(term.insert_mode());
+    let row: String = (0..5).map(|col| term.cell_at(0, col).unwrap().chars()[0]).collect();
+    assert_eq!(row, "axxbc");
+
+    // DECSTR turns it back off.
+    term.process(b"\x1b[!p");
+    assert!(!term.insert_mode());
+}
+
+#[test]
+fn cursor_style_resets() {
+    use shpool_vterm::CursorStyle;
+
+    let mut term = Term::new(10, Size { width: 10, height: 3 });
+    assert_eq!(term.cursor_style(), CursorStyle::BlinkingBlock);
+    term.process(b"\x1b[6 q");
+    assert_eq!(term.cursor_style(), CursorStyle::SteadyBar);
+    // 0 picks the default, and unknown params are ignored.
+    term.process(b"\x1b[0 q\x1b[9 q");
+    assert_eq!(term.cursor_style(), CursorStyle::BlinkingBlock);
+
+    term.process(b"\x1b[5 q\x1bc");
+    assert_eq!(term.cursor_style(), CursorStyle::BlinkingBlock);
+    term.process(b"\x1b[4 q\x1b[!p");
+    assert_eq!(term.cursor_style(), CursorStyle::BlinkingBlock);
+}
+
+#[test]
+fn cursor_style_shape_and_blink() {
+    use shpool_vterm::{CursorShape, CursorStyle};
+
+    let mut term = Term::new(10, Size { width: 10, height: 3 });
+    term.process(b"\x1b[5 q");
+    assert_eq!(term.cursor_style().shape(), CursorShape::Bar);
+    assert!(term.cursor_style().is_blinking());
+    term.process(b"\x1b[4 q");
+    assert_eq!(term.cursor_style().shape(), CursorShape::Underline);
+    assert!(!term.cursor_style().is_blinking());
+
+    // The blink mode and reverse video don't touch the style, or each
+    // other, and all of them survive a dump.
+    term.process(b"\x1b[?12h\x1b[?5h");
+    assert_eq!(term.cursor_style(), CursorStyle::SteadyUnderline);
+    assert!(term.cursor_blink_mode());
+    let restored =
+        Term::from_contents(10, Size { width: 10, height: 3 }, &term.contents(ContentRegion::All));
+    assert_eq!(restored.cursor_style(), CursorStyle::SteadyUnderline);
+    assert!(restored.cursor_blink_mode());
+    assert!(restored.reverse_video());
+
+    term.process(b"\x1b[?12l\x1b[3 q");
+    assert!(!term.cursor_blink_mode());
+    assert!(term.reverse_video());
+    assert_eq!(term.cursor_style(), CursorStyle::BlinkingUnderline);
+}
+
+#[test]
+fn reverse_wraparound() {
+    let mut term = Term::new(10, Size { width: 5, height: 3 });
+    assert!(!term.reverse_wraparound());
+    // Off by default, so a backspace in the first column stays put.
+    term.process(b"abcde\r\nfghij\r\n\x08x");
+    assert_eq!(term.cell_at(2, 0).unwrap().chars(), &['x']);
+
+    term.process(b"\x1b[?45h\x1b[2;1H\x08y");
+    assert!(term.reverse_wraparound());
+    assert_eq!(term.cell_at(0, 4).unwrap().chars(), &['y']);
+
+    // Nothing comes before the top row.
+    term.process(b"\x1b[1;1H\x08z");
+    assert_eq!(term.cell_at(0, 0).unwrap().chars(), &['z']);
+
+    let restored =
+        Term::from_contents(10, Size { width: 5, height: 3 }, &term.contents(ContentRegion::All));
+    assert!(restored.reverse_wraparound());
+    term.process(b"\x1b[?45l");
+    assert!(!term.reverse_wraparound());
+}
+
+#[test]
+fn fused_private_modes() {
+    let mut term = Term::new(10, Size { width: 10, height: 3 });
+    term.process(b"\x1b[?1;1049;2004h");
+    assert_eq!(term.encode_key(Key::Up, Modifiers::default()), b"\x1bOA");
+    assert!(term.is_alt_screen());
+    assert!(term.paste_mode());
+
+    // An unknown mode in the middle doesn't stop the ones after it.
+    term.process(b"\x1b[?2004;9999;1049;1l");
+    assert_eq!(term.encode_key(Key::Up, Modifiers::default()), b"\x1b[A");
+    assert!(!term.is_alt_screen());
+    assert!(!term.paste_mode());
+}
+
+#[test]
+fn up_arrow_normal_mode() {
+    let term = Term::new(100, Size { width: 10, height: 10 });
+    assert_eq!(term.encode_key(Key::Up, Modifiers::default()), b"\x1b[A");
+    assert_eq!(term.encode_key(Key::End, Modifiers::default()), b"\x1b[F");
+}
+
+#[test]
+fn up_arrow_application_mode() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    let mut input = vec![];
+    term::control_codes().enable_application_cursor_keys.term_input_into(&mut input);
+    term.process(&input);
+    assert_eq!(term.encode_key(Key::Up, Modifiers::default()), b"\x1bOA");
+    assert_eq!(term.encode_key(Key::Home, Modifiers::default()), b"\x1bOH");
+
+    // The mode survives a dump and restore.
+    let restored = Term::from_contents(
+        100,
+        Size { width: 10, height: 10 },
+        &term.contents(ContentRegion::All),
+    );
+    assert_eq!(restored.encode_key(Key::Up, Modifiers::default()), b"\x1bOA");
+
+    term.process(b"\x1b[?1l");
+    assert_eq!(term.encode_key(Key::Up, Modifiers::default()), b"\x1b[A");
+}
+
+#[test]
+fn modified_arrows_ignore_mode() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    term.process(b"\x1b[?1h");
+    let ctrl_shift = Modifiers { shift: true, ctrl: true, ..Modifiers::default() };
+    assert_eq!(term.encode_key(Key::Left, ctrl_shift), b"\x1b[1;6D");
+}
+
+#[test]
+fn function_keys() {
+    let term = Term::new(100, Size { width: 10, height: 10 });
+    assert_eq!(term.encode_key(Key::F(1), Modifiers::default()), b"\x1bOP");
+    let shift = Modifiers { shift: true, ..Modifiers::default() };
+    assert_eq!(term.encode_key(Key::F(1), shift), b"\x1b[1;2P");
+    assert_eq!(term.encode_key(Key::F(5), Modifiers::default()), b"\x1b[15~");
+    assert_eq!(term.encode_key(Key::F(12), shift), b"\x1b[24;2~");
+    assert_eq!(term.encode_key(Key::PageUp, Modifiers::default()), b"\x1b[5~");
+    assert_eq!(term.encode_key(Key::F(13), Modifiers::default()), b"");
+}
+
+#[test]
+fn shift_up() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    let shift = Modifiers { shift: true, ..Modifiers::default() };
+    assert_eq!(term.encode_key(Key::Up, shift), b"\x1b[1;2A");
+    term.process(b"\x1b[?1h");
+    assert_eq!(term.encode_key(Key::Up, shift), b"\x1b[1;2A");
+}
+
+#[test]
+fn keypad_mode_survives_screen_switches() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    let keypad_enter = |term: &Term| term.encode_key(Key::KeypadEnter, Modifiers::default());
+
+    // Like xterm, DECKPAM is terminal wide rather than per screen.
+    term.process(b"\x1b=\x1b[?1049h");
+    assert_eq!(keypad_enter(&term), b"\x1bOM");
+    let restored = Term::from_contents(
+        100,
+        Size { width: 10, height: 10 },
+        &term.contents(ContentRegion::Screen),
+    );
+    assert_eq!(keypad_enter(&restored), b"\x1bOM");
+
+    term.process(b"\x1b[?1049l");
+    assert_eq!(keypad_enter(&term), b"\x1bOM");
+    term.process(b"\x1b>");
+    assert_eq!(keypad_enter(&term), b"\r");
+
+    // RIS puts it back to numeric mode.
+    term.process(b"\x1b=\x1bc");
+    assert_eq!(keypad_enter(&term), b"\r");
+}
+
+#[test]
+fn keypad_enter_application_mode() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    assert_eq!(term.encode_key(Key::KeypadEnter, Modifiers::default()), b"\r");
+    assert_eq!(term.encode_key(Key::KeypadDigit(7), Modifiers::default()), b"7");
+
+    let mut input = vec![];
+    term::control_codes().keypad_application_mode.term_input_into(&mut input);
+    term.process(&input);
+    assert_eq!(term.encode_key(Key::KeypadEnter, Modifiers::default()), b"\x1bOM");
+    assert_eq!(term.encode_key(Key::KeypadDigit(7), Modifiers::default()), b"\x1bOw");
+    // DECKPAM leaves the cursor keys alone.
+    assert_eq!(term.encode_key(Key::Up, Modifiers::default()), b"\x1b[A");
+
+    let restored = Term::from_contents(
+        100,
+        Size { width: 10, height: 10 },
+        &term.contents(ContentRegion::All),
+    );
+    assert_eq!(restored.encode_key(Key::KeypadEnter, Modifiers::default()), b"\x1bOM");
+
+    // Kitty's disambiguate flag gives keypad keys their own codes.
+    term.process(b"\x1b[>1u");
+    assert_eq!(term.encode_key(Key::KeypadEnter, Modifiers::default()), b"\x1b[57414u");
+
+    term.process(b"\x1b[<u");
+    let mut input = vec![];
+    term::control_codes().keypad_numeric_mode.term_input_into(&mut input);
+    term.process(&input);
+    assert_eq!(term.encode_key(Key::KeypadEnter, Modifiers::default()), b"\r");
+}
+
+#[test]
+fn enter_in_newline_mode() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    assert_eq!(term.encode_key(Key::Enter, Modifiers::default()), b"\r");
+    assert_eq!(term.encode_key(Key::KeypadEnter, Modifiers::default()), b"\r");
+
+    term.process(b"\x1b[20h");
+    assert_eq!(term.encode_key(Key::Enter, Modifiers::default()), b"\r\n");
+    assert_eq!(term.encode_key(Key::KeypadEnter, Modifiers::default()), b"\r\n");
+
+    // Application keypad mode still wins for the keypad Enter.
+    term.process(b"\x1b=");
+    assert_eq!(term.encode_key(Key::Enter, Modifiers::default()), b"\r\n");
+    assert_eq!(term.encode_key(Key::KeypadEnter, Modifiers::default()), b"\x1bOM");
+
+    term.process(b"\x1b>\x1b[20l");
+    assert_eq!(term.encode_key(Key::Enter, Modifiers::default()), b"\r");
+    assert_eq!(term.encode_key(Key::KeypadEnter, Modifiers::default()), b"\r");
+}
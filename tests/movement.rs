@@ -87,7 +87,7 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("A"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("B"),
             term::ControlCodes::cursor_position(2, 2),
             term::control_codes().clear_attrs
@@ -104,8 +104,8 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("A"),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("B"),
             term::ControlCodes::cursor_position(3, 2),
             term::control_codes().clear_attrs
@@ -114,7 +114,7 @@ frag! {
 frag! {
     cursor_up { scrollback_lines: 100, width: 10, height: 10 }
     <= term::Raw::from("A"),
-       term::Crlf::default(),
+       term::Crlf,
        term::Raw::from("B"),
        term::ControlCodes::cursor_up(1),
        term::Raw::from("C")
@@ -124,7 +124,7 @@ frag! {
             term::control_codes().clear_screen,
             term::Raw::from("A"),
             term::Raw::from("C"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("B"),
             term::ControlCodes::cursor_position(1, 3),
             term::control_codes().clear_attrs
@@ -133,8 +133,8 @@ frag! {
 frag! {
     cursor_up_multi { scrollback_lines: 100, width: 10, height: 10 }
     <= term::Raw::from("A"),
-       term::Crlf::default(),
-       term::Crlf::default(),
+       term::Crlf,
+       term::Crlf,
        term::Raw::from("B"),
        term::ControlCodes::cursor_up(2),
        term::Raw::from("C")
@@ -144,8 +144,8 @@ frag! {
             term::control_codes().clear_screen,
             term::Raw::from("A"),
             term::Raw::from("C"),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("B"),
             term::ControlCodes::cursor_position(1, 3),
             term::control_codes().clear_attrs
@@ -161,7 +161,7 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("A"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("B"),
             term::ControlCodes::cursor_position(2, 2),
             term::control_codes().clear_attrs
@@ -177,8 +177,8 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("A"),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("B"),
             term::ControlCodes::cursor_position(3, 2),
             term::control_codes().clear_attrs
@@ -187,7 +187,7 @@ frag! {
 frag! {
     cursor_prev_line { scrollback_lines: 100, width: 10, height: 10 }
     <= term::Raw::from("A"),
-       term::Crlf::default(),
+       term::Crlf,
        term::Raw::from("B"),
        term::ControlCodes::cursor_prev_line(1),
        term::Raw::from("C")
@@ -196,7 +196,7 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("C"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("B"),
             term::ControlCodes::cursor_position(1, 2),
             term::control_codes().clear_attrs
@@ -205,8 +205,8 @@ frag! {
 frag! {
     cursor_prev_line_multi { scrollback_lines: 100, width: 10, height: 10 }
     <= term::Raw::from("A"),
-       term::Crlf::default(),
-       term::Crlf::default(),
+       term::Crlf,
+       term::Crlf,
        term::Raw::from("B"),
        term::ControlCodes::cursor_prev_line(2),
        term::Raw::from("C")
@@ -215,8 +215,8 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("C"),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("B"),
             term::ControlCodes::cursor_position(1, 2),
             term::control_codes().clear_attrs
@@ -232,8 +232,8 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("A"),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("  B"),
             term::ControlCodes::cursor_position(3, 4),
             term::control_codes().clear_attrs
@@ -243,7 +243,7 @@ frag! {
     scp_rcp { scrollback_lines: 100, width: 10, height: 10 }
     <= term::Raw::from("A"),
        term::control_codes().save_cursor_position,
-       term::Crlf::default(),
+       term::Crlf,
        term::Raw::from("B"),
        term::control_codes().restore_cursor_position,
        term::Raw::from("C")
@@ -252,7 +252,7 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("AC"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("B"),
             term::ControlCodes::cursor_position(1, 3),
             term::control_codes().clear_attrs
@@ -293,12 +293,56 @@ frag! {
             term::control_codes().clear_attrs
 }
 
+frag! {
+    cursor_position_zero_params { scrollback_lines: 100, width: 10, height: 10 }
+    <= term::Raw::from("123\r\n456"),
+       // explicit zeros mean the same thing as missing params
+       term::ControlCode::CSI {
+           params: smallvec![smallvec![0], smallvec![0]],
+           intermediates: smallvec![],
+           action: 'H',
+       },
+       term::Raw::from("X"),
+       term::ControlCode::CSI {
+           params: smallvec![smallvec![2], smallvec![0]],
+           intermediates: smallvec![],
+           action: 'H',
+       },
+       term::Raw::from("Y")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("X23"),
+            term::Crlf,
+            term::Raw::from("Y56"),
+            term::ControlCodes::cursor_position(2, 2),
+            term::control_codes().clear_attrs
+}
+
+#[test]
+fn cursor_position_per_param_defaults() {
+    use shpool_vterm::{Size, Term};
+
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    term.process(b"\x1b[;5HX");
+    assert_eq!(term.cell_at(0, 4).unwrap().chars(), &['X']);
+
+    term.process(b"\x1b[5;HY");
+    assert_eq!(term.cell_at(4, 0).unwrap().chars(), &['Y']);
+
+    // HVP takes the same params
+    term.process(b"\x1b[3;7fZ\x1b[;2fW");
+    assert_eq!(term.cell_at(2, 6).unwrap().chars(), &['Z']);
+    assert_eq!(term.cell_at(0, 1).unwrap().chars(), &['W']);
+}
+
 frag! {
     scroll_up { scrollback_lines: 100, width: 10, height: 2 }
     <= term::Raw::from("A"),
-       term::Crlf::default(),
+       term::Crlf,
        term::Raw::from("B"),
-       term::Crlf::default(),
+       term::Crlf,
        term::Raw::from("C"),
        term::ControlCodes::scroll_up(1)
     => ContentRegion::All =>
@@ -306,9 +350,9 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("A"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("B"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("C"),
             term::ControlCodes::scroll_up(1),
             term::ControlCodes::cursor_position(2, 2),
@@ -318,9 +362,9 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("A"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("B"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("C"),
             term::ControlCodes::scroll_up(1),
             term::ControlCodes::cursor_position(2, 2),
@@ -330,7 +374,7 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("A"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("B"),
             term::ControlCodes::cursor_position(2, 2),
             term::control_codes().clear_attrs
@@ -346,11 +390,11 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("A"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("B"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("C"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("D"),
             term::ControlCodes::scroll_up(1),
             term::ControlCodes::cursor_position(2, 2),
@@ -360,11 +404,11 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("A"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("B"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("C"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("D"),
             term::ControlCodes::scroll_up(1),
             term::ControlCodes::cursor_position(2, 2),
@@ -374,7 +418,7 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("B"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("C"),
             term::ControlCodes::cursor_position(2, 2),
             term::control_codes().clear_attrs
@@ -402,7 +446,36 @@ frag! {
             term::control_codes().clear_screen,
             term::Raw::from("A"),
             term::ControlCodes::set_scroll_region(2, 5),
-            term::ControlCodes::cursor_position(1, 2),
+            // DECSTBM homes the cursor.
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    scroll_region_homes_cursor { scrollback_lines: 100, width: 10, height: 10 }
+    <= term::ControlCodes::cursor_position(7, 4),
+       term::ControlCodes::set_scroll_region(2, 5)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::set_scroll_region(2, 5),
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    scroll_region_homes_cursor_origin_mode { scrollback_lines: 100, width: 10, height: 10 }
+    <= term::control_codes().enable_scroll_region_origin_mode,
+       term::ControlCodes::cursor_position(2, 4),
+       term::ControlCodes::set_scroll_region(3, 6)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::set_scroll_region(3, 6),
+            term::ControlCodes::cursor_position(3, 1),
+            term::control_codes().enable_scroll_region_origin_mode,
             term::control_codes().clear_attrs
 }
 
@@ -418,6 +491,42 @@ frag! {
             term::control_codes().clear_attrs
 }
 
+frag! {
+    scroll_region_inverted_ignored { scrollback_lines: 100, width: 10, height: 10 }
+    <= term::ControlCodes::set_scroll_region(2, 5),
+       term::ControlCodes::set_scroll_region(5, 2)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::set_scroll_region(2, 5),
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    scroll_region_single_line_ignored { scrollback_lines: 100, width: 10, height: 10 }
+    <= term::ControlCodes::set_scroll_region(3, 3)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    scroll_region_bottom_clamped { scrollback_lines: 100, width: 10, height: 10 }
+    <= term::ControlCodes::set_scroll_region(2, 50)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::set_scroll_region(2, 10),
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs
+}
+
 frag! {
     origin_mode_clamp_top { scrollback_lines: 100, width: 4, height: 4 }
     <= term::ControlCodes::set_scroll_region(2, 3),
@@ -461,8 +570,8 @@ frag! {
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::Raw::from(" X"),
             term::ControlCodes::set_scroll_region(2, 3),
             term::ControlCodes::cursor_position(3, 3),
@@ -479,8 +588,8 @@ frag! {
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::Raw::from(" X"),
             term::ControlCodes::set_scroll_region(2, 3),
             term::ControlCodes::cursor_position(3, 3),
@@ -499,8 +608,8 @@ frag! {
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::Raw::from(" X"),
             term::ControlCodes::set_scroll_region(2, 3),
             term::ControlCodes::cursor_position(3, 3),
@@ -536,10 +645,10 @@ frag! {
 
 frag! {
     scrollback_scroll_region_on_newline { scrollback_lines: 100, width: 5, height: 5 }
-    <= term::Raw::from("0"), term::Crlf::default(),
-       term::Raw::from("1"), term::Crlf::default(),
-       term::Raw::from("2"), term::Crlf::default(),
-       term::Raw::from("3"), term::Crlf::default(),
+    <= term::Raw::from("0"), term::Crlf,
+       term::Raw::from("1"), term::Crlf,
+       term::Raw::from("2"), term::Crlf,
+       term::Raw::from("3"), term::Crlf,
        term::Raw::from("4"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::ControlCodes::cursor_position(4, 1),
@@ -550,13 +659,13 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("0"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("2"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("3"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("X"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("4"),
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(4, 2),
@@ -565,10 +674,10 @@ frag! {
 
 frag! {
     scrollback_newline_below_scroll_region { scrollback_lines: 100, width: 5, height: 5 }
-    <= term::Raw::from("0"), term::Crlf::default(),
-       term::Raw::from("1"), term::Crlf::default(),
-       term::Raw::from("2"), term::Crlf::default(),
-       term::Raw::from("3"), term::Crlf::default(),
+    <= term::Raw::from("0"), term::Crlf,
+       term::Raw::from("1"), term::Crlf,
+       term::Raw::from("2"), term::Crlf,
+       term::Raw::from("3"), term::Crlf,
        term::Raw::from("4"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::ControlCodes::cursor_position(5, 1),
@@ -579,13 +688,13 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("0"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("1"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("2"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("3"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("X"),
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(5, 2),
@@ -594,10 +703,10 @@ frag! {
 
 frag! {
     scrollback_newline_above_scroll_region { scrollback_lines: 100, width: 5, height: 5 }
-    <= term::Raw::from("0"), term::Crlf::default(),
-       term::Raw::from("1"), term::Crlf::default(),
-       term::Raw::from("2"), term::Crlf::default(),
-       term::Raw::from("3"), term::Crlf::default(),
+    <= term::Raw::from("0"), term::Crlf,
+       term::Raw::from("1"), term::Crlf,
+       term::Raw::from("2"), term::Crlf,
+       term::Raw::from("3"), term::Crlf,
        term::Raw::from("4"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::ControlCodes::cursor_position(1, 1),
@@ -608,13 +717,13 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("0"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("X"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("2"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("3"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("4"),
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(2, 2),
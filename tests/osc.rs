@@ -2,7 +2,10 @@
 #[path = "support/mod.rs"]
 mod support;
 
-use shpool_vterm::{term, ContentRegion};
+use shpool_vterm::{
+    term::{self, AsTermInput},
+    ContentRegion, Pos, PromptMark, Rgb, Size, Term,
+};
 use smallvec::smallvec;
 
 frag! {
@@ -126,6 +129,21 @@ frag! {
             term::ControlCodes::set_color_indices(std::iter::once((2, smallvec![b'g', b'r', b'e', b'e', b'n'])))
 }
 
+frag! {
+    osc_reset_whole_palette { scrollback_lines: 10, width: 10, height: 10 }
+    <= term::ControlCodes::set_color_indices(vec![
+           (1, smallvec![b'r', b'e', b'd']),
+           (2, smallvec![b'g', b'r', b'e', b'e', b'n']),
+       ]),
+       term::ControlCodes::reset_color_indices(std::iter::empty())
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs
+}
+
 frag! {
     osc_functional_colors { scrollback_lines: 10, width: 10, height: 10 }
     <= term::ControlCodes::set_functional_color(0, vec![b"red".as_slice(), b"blue".as_slice()])
@@ -176,6 +194,21 @@ frag! {
             term::ControlCodes::set_functional_color(9, vec![b"red".as_slice()])
 }
 
+frag! {
+    osc_reset_functional_colors { scrollback_lines: 10, width: 10, height: 10 }
+    <= term::ControlCodes::set_functional_color(0, vec![b"red".as_slice(), b"blue".as_slice()]),
+       term::ControlCodes::set_functional_color(2, vec![b"green".as_slice()]),
+       term::ControlCodes::reset_functional_color(1),
+       term::ControlCodes::reset_functional_color(2)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs,
+            term::ControlCodes::set_functional_color(0, vec![b"red".as_slice()])
+}
+
 frag! {
     osc_functional_colors_query { scrollback_lines: 10, width: 10, height: 10 }
     <= term::ControlCodes::set_functional_color(0, vec![b"?".as_slice()])
@@ -186,3 +219,237 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_attrs
 }
+
+frag! {
+    osc_title_raw_st { scrollback_lines: 10, width: 10, height: 10 }
+    <= term::Raw::from("\x1b]2;title\x1b\\")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs,
+            term::Raw::from("\x1b]2;title\x1b\\")
+}
+
+// We always re-emit with ST, regardless of how the input was terminated.
+frag! {
+    osc_title_raw_bel { scrollback_lines: 10, width: 10, height: 10 }
+    <= term::Raw::from("\x1b]2;title\x07")
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs,
+            term::Raw::from("\x1b]2;title\x1b\\")
+}
+
+#[test]
+fn title_changed_notification() {
+    let mut term = Term::new(10, Size { width: 10, height: 3 });
+    assert!(!term.take_title_changed());
+
+    let mut buf = vec![];
+    term::ControlCodes::set_title(smallvec![b'a']).term_input_into(&mut buf);
+    term.process(&buf);
+    assert_eq!(term.title(), Some(&b"a"[..]));
+    assert!(term.take_title_changed());
+    assert!(!term.take_title_changed());
+
+    let mut buf = vec![];
+    term::ControlCodes::set_title(smallvec![b'b']).term_input_into(&mut buf);
+    term.process(&buf);
+    assert_eq!(term.title(), Some(&b"b"[..]));
+    assert!(term.take_title_changed());
+    assert!(!term.take_title_changed());
+
+    // Setting the same title again is not a change.
+    term.process(&buf);
+    assert!(!term.take_title_changed());
+
+    let mut buf = vec![];
+    term::ControlCodes::set_icon_name(smallvec![b'c']).term_input_into(&mut buf);
+    term.process(&buf);
+    assert_eq!(term.icon_name(), Some(&b"c"[..]));
+    assert!(term.take_title_changed());
+}
+
+#[test]
+fn shell_integration_marks() {
+    let mut term = Term::new(5, Size { width: 10, height: 3 });
+    term.process(b"\x1b]133;A\x07$ \x1b]133;B\x07ls\r\n\x1b]133;C\x07x\r\n\x1b]133;D;1\x07");
+
+    let prompt = term.line_id_at(0).unwrap();
+    let output = term.line_id_at(1).unwrap();
+    let done = term.line_id_at(2).unwrap();
+    assert_eq!(
+        term.prompt_marks(),
+        &[
+            (prompt, PromptMark::PromptStart),
+            (prompt, PromptMark::CommandStart),
+            (output, PromptMark::OutputStart),
+            (done, PromptMark::CommandFinished(Some(1))),
+        ]
+    );
+
+    // Ids stick with their lines as they scroll up.
+    term.process(b"\r\n\r\n\x1b]133;D\x07");
+    assert_eq!(term.line_id_at(0), Some(done));
+    assert_eq!(
+        term.prompt_marks()[4],
+        (term.line_id_at(2).unwrap(), PromptMark::CommandFinished(None))
+    );
+
+    // Marks for lines that fell out of the scrollback get dropped.
+    term.process(b"\r\n\r\n\r\n\r\n\r\n\r\n\x1b]133;A\x07");
+    assert_eq!(term.prompt_marks(), &[(term.line_id_at(2).unwrap(), PromptMark::PromptStart)]);
+}
+
+#[test]
+fn erase_scrollback_drops_marks() {
+    let mut term = Term::new(100, Size { width: 10, height: 3 });
+    term.process(b"\x1b]133;A\x07$ ls\r\na\r\nb\r\nc\r\n");
+    assert_eq!(term.prompt_marks().len(), 1);
+
+    // CSI 3 J throws away the lines the mark was on.
+    term.process(b"\x1b[3J");
+    assert_eq!(term.prompt_marks(), &[]);
+
+    // Marks on lines written afterwards stick around.
+    term.process(b"\x1b]133;A\x07$ ");
+    assert_eq!(term.prompt_marks().len(), 1);
+}
+
+#[test]
+fn osc7_file_uri() {
+    let mut term = Term::new(10, Size { width: 10, height: 3 });
+    assert!(!term.take_working_dir_changed());
+
+    term.process(b"\x1b]7;file://box/home/me\x07");
+    assert_eq!(term.working_dir(), Some(&b"/home/me"[..]));
+    assert_eq!(term.working_dir_host(), Some(&b"box"[..]));
+    assert!(term.take_working_dir_changed());
+    assert!(!term.take_working_dir_changed());
+
+    // Setting the same dir again is not a change.
+    term.process(b"\x1b]7;file://box/home/me\x07");
+    assert!(!term.take_working_dir_changed());
+
+    term.process(b"\x1b]7;file:///tmp\x1b\\");
+    assert_eq!(term.working_dir(), Some(&b"/tmp"[..]));
+    assert_eq!(term.working_dir_host(), None);
+    assert!(term.take_working_dir_changed());
+
+    // Anything that isn't a file URI is ignored.
+    term.process(b"\x1b]7;http://box/x\x07");
+    assert_eq!(term.working_dir(), Some(&b"/tmp"[..]));
+    assert!(!term.take_working_dir_changed());
+
+    // The host survives a dump and restore.
+    term.process(b"\x1b]7;file://box/srv\x07");
+    let restored =
+        Term::from_contents(10, Size { width: 10, height: 3 }, &term.contents(ContentRegion::All));
+    assert_eq!(restored.working_dir(), Some(&b"/srv"[..]));
+    assert_eq!(restored.working_dir_host(), Some(&b"box"[..]));
+}
+
+#[test]
+fn iterm2_current_dir() {
+    let mut term = Term::new(10, Size { width: 10, height: 3 });
+    term.process(b"\x1b]1337;CurrentDir=/home/me\x07");
+    assert_eq!(term.working_dir(), Some(&b"/home/me"[..]));
+
+    let mut expected = vec![];
+    term::ControlCodes::set_working_dir(
+        smallvec![],
+        smallvec![b'/', b'h', b'o', b'm', b'e', b'/', b'm', b'e'],
+    )
+    .term_input_into(&mut expected);
+    let dump = term.contents(ContentRegion::All);
+    assert!(dump.windows(expected.len()).any(|w| w == expected));
+}
+
+#[test]
+fn iterm2_user_var() {
+    let mut term = Term::new(10, Size { width: 10, height: 3 });
+    assert_eq!(term.user_var(b"foo"), None);
+    term.process(b"\x1b]1337;SetUserVar=foo=YmFy\x07");
+    assert_eq!(term.user_var(b"foo"), Some(&b"YmFy"[..]));
+
+    // File transfers are ignored.
+    term.process(b"\x1b]1337;File=name=eA==:eA==\x07");
+    assert_eq!(term.user_var(b"name"), None);
+
+    // User vars survive a dump and restore.
+    let mut restored = Term::new(10, Size { width: 10, height: 3 });
+    restored.process(&term.contents(ContentRegion::All));
+    assert_eq!(restored.user_var(b"foo"), Some(&b"YmFy"[..]));
+}
+
+#[test]
+fn oversized_osc_dropped() {
+    let mut term = Term::new(10, Size { width: 10, height: 3 });
+    term.process(b"\x1b]2;");
+    // Arrive in chunks, the way a pty read loop would feed it.
+    for _ in 0..100 {
+        term.process(&[b'a'; 1024]);
+    }
+    term.process(b"\x1b\\");
+    assert_eq!(term.title(), None);
+
+    term.process(b"\x1b]2;ok\x07hi");
+    assert_eq!(term.title(), Some(&b"ok"[..]));
+    assert_eq!(term.cell_at(0, 0).unwrap().chars(), &['h']);
+    assert_eq!(term.cell_at(0, 1).unwrap().chars(), &['i']);
+}
+
+#[test]
+fn resolved_colors_palette_override() {
+    let mut term = Term::new(10, Size { width: 10, height: 10 });
+    term.process(b"\x1b[31ma\x1b[38;5;196mb\x1b]4;1;rgb:12/34/56\x1b\\");
+    let origin = Pos { row: 0, col: 0 };
+    assert_eq!(
+        term.resolved_colors(origin),
+        Some((Rgb { r: 0x12, g: 0x34, b: 0x56 }, Rgb::DEFAULT_BG))
+    );
+    assert_eq!(
+        term.resolved_colors(Pos { row: 0, col: 1 }),
+        Some((Rgb { r: 255, g: 0, b: 0 }, Rgb::DEFAULT_BG))
+    );
+    assert_eq!(term.resolved_colors(Pos { row: 10, col: 0 }), None);
+
+    term.process(b"\x1b]104;1\x1b\\");
+    assert_eq!(term.resolved_colors(origin), Some((Rgb::xterm_palette(1), Rgb::DEFAULT_BG)));
+}
+
+#[test]
+fn resolved_colors_inverse() {
+    let mut term = Term::new(10, Size { width: 10, height: 10 });
+    term.process(b"\x1b]11;#102030\x1b\\\x1b[7;32ma");
+    assert_eq!(
+        term.resolved_colors(Pos { row: 0, col: 0 }),
+        Some((Rgb { r: 0x10, g: 0x20, b: 0x30 }, Rgb::xterm_palette(2)))
+    );
+}
+
+#[test]
+fn resolved_colors_reverse_video() {
+    let mut term = Term::new(10, Size { width: 10, height: 10 });
+    term.process(b"a\x1b[7mb\x1b[?5h");
+    assert_eq!(
+        term.resolved_colors(Pos { row: 0, col: 0 }),
+        Some((Rgb::DEFAULT_BG, Rgb::DEFAULT_FG))
+    );
+    // Inverse cells flip back to the normal defaults.
+    assert_eq!(
+        term.resolved_colors(Pos { row: 0, col: 1 }),
+        Some((Rgb::DEFAULT_FG, Rgb::DEFAULT_BG))
+    );
+
+    term.process(b"\x1b[?5l");
+    assert_eq!(
+        term.resolved_colors(Pos { row: 0, col: 0 }),
+        Some((Rgb::DEFAULT_FG, Rgb::DEFAULT_BG))
+    );
+}
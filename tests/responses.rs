@@ -0,0 +1,278 @@
+use shpool_vterm::{term, term::AsTermInput, Size, Term};
+
+fn reply_to(query: &impl AsTermInput) -> Vec<u8> {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    let mut input = vec![];
+    query.term_input_into(&mut input);
+    term.process(&input);
+    term.take_responses()
+}
+
+#[test]
+fn primary_device_attributes() {
+    let mut expected = vec![];
+    term::control_codes().primary_device_attributes_reply.term_input_into(&mut expected);
+    assert_eq!(reply_to(&term::control_codes().primary_device_attributes), expected);
+}
+
+#[test]
+fn decid_matches_primary_device_attributes() {
+    assert_eq!(
+        reply_to(&term::control_codes().identify_terminal),
+        reply_to(&term::control_codes().primary_device_attributes)
+    );
+}
+
+#[test]
+fn take_responses_drains() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    term.process(b"\x1bZ");
+    assert!(!term.take_responses().is_empty());
+    assert!(term.take_responses().is_empty());
+}
+
+fn keyboard_flags(term: &mut Term) -> Vec<u8> {
+    let mut input = vec![];
+    term::control_codes().query_keyboard_flags.term_input_into(&mut input);
+    term.process(&input);
+    term.take_responses()
+}
+
+fn keyboard_flags_reply(flags: u16) -> Vec<u8> {
+    let mut reply = vec![];
+    term::ControlCodes::keyboard_flags_reply(flags).term_input_into(&mut reply);
+    reply
+}
+
+#[test]
+fn keyboard_flags_push_pop() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    assert_eq!(keyboard_flags(&mut term), keyboard_flags_reply(0));
+
+    term.process(b"\x1b[>1u");
+    assert_eq!(keyboard_flags(&mut term), keyboard_flags_reply(1));
+    term.process(b"\x1b[>3u");
+    assert_eq!(keyboard_flags(&mut term), keyboard_flags_reply(3));
+
+    term.process(b"\x1b[<u");
+    assert_eq!(keyboard_flags(&mut term), keyboard_flags_reply(1));
+    term.process(b"\x1b[<u");
+    assert_eq!(keyboard_flags(&mut term), keyboard_flags_reply(0));
+
+    // popping an empty stack is a no-op
+    term.process(b"\x1b[<5u");
+    assert_eq!(keyboard_flags(&mut term), keyboard_flags_reply(0));
+}
+
+#[test]
+fn keyboard_flags_set_modes() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    term.process(b"\x1b[>1u");
+
+    term.process(b"\x1b[=4;2u");
+    assert_eq!(keyboard_flags(&mut term), keyboard_flags_reply(5));
+    term.process(b"\x1b[=1;3u");
+    assert_eq!(keyboard_flags(&mut term), keyboard_flags_reply(4));
+    term.process(b"\x1b[=8u");
+    assert_eq!(keyboard_flags(&mut term), keyboard_flags_reply(8));
+
+    // set modifies the top entry rather than pushing a new one
+    term.process(b"\x1b[<u");
+    assert_eq!(keyboard_flags(&mut term), keyboard_flags_reply(0));
+}
+
+#[test]
+fn keyboard_flags_per_screen() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    term.process(b"\x1b[>1u\x1b[?1049h");
+    assert_eq!(keyboard_flags(&mut term), keyboard_flags_reply(0));
+    term.process(b"\x1b[>2u");
+    assert_eq!(keyboard_flags(&mut term), keyboard_flags_reply(2));
+    term.process(b"\x1b[?1049l");
+    assert_eq!(keyboard_flags(&mut term), keyboard_flags_reply(1));
+}
+
+fn status_string(term: &mut Term, setting: &[u8]) -> Vec<u8> {
+    let mut input = vec![];
+    term::ControlCodes::request_status_string(setting).term_input_into(&mut input);
+    term.process(&input);
+    term.take_responses()
+}
+
+fn status_string_reply(valid: bool, data: &[u8]) -> Vec<u8> {
+    let mut reply = vec![];
+    term::ControlCodes::status_string_reply(valid, data).term_input_into(&mut reply);
+    reply
+}
+
+#[test]
+fn decrqss_scroll_region() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    assert_eq!(status_string(&mut term, b"r"), status_string_reply(true, b"1;10r"));
+
+    let mut input = vec![];
+    term::ControlCodes::set_scroll_region(3, 7).term_input_into(&mut input);
+    term.process(&input);
+    assert_eq!(status_string(&mut term, b"r"), status_string_reply(true, b"3;7r"));
+}
+
+#[test]
+fn decrqss_cursor_style() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    assert_eq!(status_string(&mut term, b" q"), status_string_reply(true, b"1 q"));
+
+    let mut input = vec![];
+    term::ControlCodes::set_cursor_style(4).term_input_into(&mut input);
+    term.process(&input);
+    assert_eq!(status_string(&mut term, b" q"), status_string_reply(true, b"4 q"));
+}
+
+#[test]
+fn decrqss_sgr() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    assert_eq!(status_string(&mut term, b"m"), status_string_reply(true, b"0m"));
+
+    term.process(b"\x1b[1;31;4m");
+    let reply = status_string(&mut term, b"m");
+    assert_eq!(reply, status_string_reply(true, b"0;31;4;1m"));
+
+    // Feeding the reply's params back in after a reset gets the same attrs.
+    term.process(b"\x1b[m\x1b[0;31;4;1mx");
+    assert_eq!(status_string(&mut term, b"m"), reply);
+
+    term.process(b"\x1b[m\x1b[48;2;1;2;3;5m");
+    assert_eq!(status_string(&mut term, b"m"), status_string_reply(true, b"0;48;2;1;2;3;5m"));
+}
+
+#[test]
+fn decrqss_unknown_setting() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    assert_eq!(status_string(&mut term, b"xyz"), status_string_reply(false, b""));
+    assert_eq!(status_string(&mut term, b"r"), status_string_reply(true, b"1;10r"));
+}
+
+#[test]
+fn tertiary_device_attributes() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    term.process(b"ab");
+    let before = term.contents(shpool_vterm::ContentRegion::All);
+
+    let mut input = vec![];
+    term::control_codes().tertiary_device_attributes.term_input_into(&mut input);
+    term.process(&input);
+    assert_eq!(term.take_responses(), b"\x1bP!|00000000\x1b\\");
+    assert_eq!(term.contents(shpool_vterm::ContentRegion::All), before);
+}
+
+#[test]
+fn cursor_position_report() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    term.process(b"\x1b[3;4H");
+    let summary = term.process(b"\x1b[6n");
+    assert!(summary.responses_pending);
+
+    let mut expected = vec![];
+    term::ControlCodes::cursor_position_report(3, 4).term_input_into(&mut expected);
+    assert_eq!(term.take_responses(), expected);
+
+    // In origin mode the row is relative to the scroll region.
+    term.process(b"\x1b[2;8r\x1b[?6h\x1b[2;1H");
+    let mut input = vec![];
+    term::control_codes().request_cursor_position.term_input_into(&mut input);
+    term.process(&input);
+    let mut expected = vec![];
+    term::ControlCodes::cursor_position_report(2, 1).term_input_into(&mut expected);
+    assert_eq!(term.take_responses(), expected);
+}
+
+#[test]
+fn process_summary() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    assert_eq!(term.process(b"plain text"), shpool_vterm::ProcessSummary::default());
+
+    let summary = term.process(b"\x07\x1b]2;hi\x07");
+    assert!(summary.bell);
+    assert!(summary.title_changed);
+    assert!(!summary.responses_pending);
+    // The sticky title flag is left for take_title_changed.
+    assert!(term.take_title_changed());
+
+    // Undrained responses are still pending on the next call.
+    assert!(term.process(b"\x1b[c").responses_pending);
+    let summary = term.process(b"x");
+    assert!(summary.responses_pending);
+    assert!(!summary.bell);
+    assert!(!summary.title_changed);
+}
+
+#[test]
+fn decscl_vt220_seven_bit() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    let mut input = vec![];
+    term::ControlCodes::set_conformance_level(62, false).term_input_into(&mut input);
+    term::control_codes().primary_device_attributes.term_input_into(&mut input);
+    term.process(&input);
+
+    let mut expected = vec![];
+    term::control_codes().primary_device_attributes_reply.term_input_into(&mut expected);
+    assert!(expected.starts_with(b"\x1b["));
+    assert_eq!(term.take_responses(), expected);
+}
+
+#[test]
+fn decscl_restored_from_dump() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    let mut decscl = vec![];
+    term::ControlCodes::set_conformance_level(64, true).term_input_into(&mut decscl);
+    term.process(&decscl);
+    let dump = term.contents(shpool_vterm::ContentRegion::All);
+    assert!(dump.windows(decscl.len()).any(|w| w == decscl.as_slice()));
+
+    // Unknown levels are ignored, so nothing gets dumped for them.
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    term.process(b"\x1b[70;1\"p");
+    assert!(!term.contents(shpool_vterm::ContentRegion::All).windows(2).any(|w| w == b"\"p"));
+}
+
+#[test]
+fn eight_bit_replies() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    let mut da = vec![];
+    term::control_codes().primary_device_attributes.term_input_into(&mut da);
+
+    term.process(&da);
+    assert_eq!(term.take_responses(), b"\x1b[?62;22c");
+
+    let mut input = vec![];
+    term::ControlCodes::set_conformance_level(62, true).term_input_into(&mut input);
+    term.process(&input);
+    term.process(&da);
+    assert_eq!(term.take_responses(), b"\x9b?62;22c");
+    term.process(b"\x1b[=c");
+    assert_eq!(term.take_responses(), b"\x90!|00000000\x9c");
+
+    // A VT100 has no 8-bit controls, whatever the second param says.
+    term.process(b"\x1b[61;2\"p");
+    term.process(&da);
+    assert_eq!(term.take_responses(), b"\x1b[?62;22c");
+}
+
+#[test]
+fn s8c1t_switches_replies() {
+    let mut term = Term::new(100, Size { width: 10, height: 10 });
+    let mut input = vec![];
+    term::control_codes().select_8bit_controls.term_input_into(&mut input);
+    term::control_codes().request_cursor_position.term_input_into(&mut input);
+    term.process(&input);
+    assert_eq!(term.take_responses(), b"\x9b1;1R");
+
+    let mut input = vec![];
+    term::control_codes().select_7bit_controls.term_input_into(&mut input);
+    term::control_codes().request_cursor_position.term_input_into(&mut input);
+    term.process(&input);
+    assert_eq!(term.take_responses(), b"\x1b[1;1R");
+
+    // VT100 level terminals stay 7-bit.
+    term.process(b"\x1b[61\"p\x1b G\x1b[6n");
+    assert_eq!(term.take_responses(), b"\x1b[1;1R");
+}
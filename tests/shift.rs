@@ -2,13 +2,17 @@
 #[path = "support/mod.rs"]
 mod support;
 
-use shpool_vterm::{term, ContentRegion};
+use shpool_vterm::{
+    term::{self, AsTermInput},
+    ContentRegion,
+};
+use support::screens::assert_screens_agree;
 
 frag! {
     scrollback_insert_line_basic { scrollback_lines: 100, width: 5, height: 4 }
-    <= term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
+    <= term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
        term::Raw::from("44"),
        term::ControlCodes::cursor_position(2, 1),
        term::ControlCodes::insert_lines(1)
@@ -17,10 +21,10 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11"),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("22"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("33"),
             term::ControlCodes::cursor_position(2, 1),
             term::control_codes().clear_attrs
@@ -29,10 +33,10 @@ frag! {
 frag! {
     scrollback_insert_lines_scroll_region { scrollback_lines: 100, width: 5, height: 5 }
     <= term::ControlCodes::cursor_position(1, 1),
-       term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
-       term::Raw::from("44"), term::Crlf::default(),
+       term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
+       term::Raw::from("44"), term::Crlf,
        term::Raw::from("55"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::ControlCodes::cursor_position(3, 1),
@@ -42,12 +46,12 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("22"),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("33"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("55"),
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(3, 1),
@@ -56,9 +60,9 @@ frag! {
 
 frag! {
     scrollback_insert_many_lines { scrollback_lines: 100, width: 5, height: 4 }
-    <= term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
+    <= term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
        term::Raw::from("44"),
        term::ControlCodes::cursor_position(2, 1),
        term::ControlCodes::insert_lines(10)
@@ -67,19 +71,19 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11"),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::cursor_position(2, 1),
             term::control_codes().clear_attrs
 }
 
 frag! {
     scrollback_insert_medium_lines { scrollback_lines: 100, width: 5, height: 5 }
-    <= term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
-       term::Raw::from("44"), term::Crlf::default(),
+    <= term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
+       term::Raw::from("44"), term::Crlf,
        term::Raw::from("55"),
        term::ControlCodes::cursor_position(2, 1),
        term::ControlCodes::insert_lines(2)
@@ -88,11 +92,11 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11"),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("22"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("33"),
             term::ControlCodes::cursor_position(2, 1),
             term::control_codes().clear_attrs
@@ -100,10 +104,10 @@ frag! {
 
 frag! {
     scrollback_insert_outside_scroll_region { scrollback_lines: 100, width: 5, height: 5 }
-    <= term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
-       term::Raw::from("44"), term::Crlf::default(),
+    <= term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
+       term::Raw::from("44"), term::Crlf,
        term::Raw::from("55"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::ControlCodes::cursor_position(1, 1),
@@ -113,13 +117,13 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("22"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("33"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("44"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("55"),
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(1, 1),
@@ -128,7 +132,7 @@ frag! {
 
 frag! {
     scrollback_insert_past_text { scrollback_lines: 100, width: 5, height: 5 }
-    <= term::Raw::from("11"), term::Crlf::default(),
+    <= term::Raw::from("11"), term::Crlf,
        term::Raw::from("22"),
        term::ControlCodes::cursor_position(4, 1),
        term::ControlCodes::insert_lines(1)
@@ -137,7 +141,7 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("22"),
             term::ControlCodes::cursor_position(4, 1),
             term::control_codes().clear_attrs
@@ -145,11 +149,11 @@ frag! {
 
 frag! {
     scrollback_insert_with_scroll_region_reassembly { scrollback_lines: 100, width: 5, height: 6 }
-    <= term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
-       term::Raw::from("44"), term::Crlf::default(),
-       term::Raw::from("55"), term::Crlf::default(),
+    <= term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
+       term::Raw::from("44"), term::Crlf,
+       term::Raw::from("55"), term::Crlf,
        term::Raw::from("66"),
        term::ControlCodes::set_scroll_region(2, 5),
        term::ControlCodes::cursor_position(3, 1),
@@ -159,14 +163,14 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("22"),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("33"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("44"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("66"),
             term::ControlCodes::set_scroll_region(2, 5),
             term::ControlCodes::cursor_position(3, 1),
@@ -175,9 +179,9 @@ frag! {
 
 frag! {
     scrollback_delete_line_basic { scrollback_lines: 100, width: 5, height: 4 }
-    <= term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
+    <= term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
        term::Raw::from("44"),
        term::ControlCodes::cursor_position(2, 1),
        term::ControlCodes::delete_lines(1)
@@ -186,11 +190,11 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("33"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("44"),
-            term::Crlf::default(),
+            term::Crlf,
             term::ControlCodes::cursor_position(2, 1),
             term::control_codes().clear_attrs
 }
@@ -198,10 +202,10 @@ frag! {
 frag! {
     scrollback_delete_lines_scroll_region { scrollback_lines: 100, width: 5, height: 5 }
     <= term::ControlCodes::cursor_position(1, 1),
-       term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
-       term::Raw::from("44"), term::Crlf::default(),
+       term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
+       term::Raw::from("44"), term::Crlf,
        term::Raw::from("55"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::ControlCodes::cursor_position(3, 1),
@@ -211,12 +215,12 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("22"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("44"),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
             term::Raw::from("55"),
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(3, 1),
@@ -225,9 +229,9 @@ frag! {
 
 frag! {
     scrollback_delete_many_lines { scrollback_lines: 100, width: 5, height: 4 }
-    <= term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
+    <= term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
        term::Raw::from("44"),
        term::ControlCodes::cursor_position(2, 1),
        term::ControlCodes::delete_lines(10)
@@ -236,19 +240,19 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11"),
-            term::Crlf::default(),
-            term::Crlf::default(),
-            term::Crlf::default(),
+            term::Crlf,
+            term::Crlf,
+            term::Crlf,
             term::ControlCodes::cursor_position(2, 1),
             term::control_codes().clear_attrs
 }
 
 frag! {
     scrollback_delete_outside_scroll_region { scrollback_lines: 100, width: 5, height: 5 }
-    <= term::Raw::from("11"), term::Crlf::default(),
-       term::Raw::from("22"), term::Crlf::default(),
-       term::Raw::from("33"), term::Crlf::default(),
-       term::Raw::from("44"), term::Crlf::default(),
+    <= term::Raw::from("11"), term::Crlf,
+       term::Raw::from("22"), term::Crlf,
+       term::Raw::from("33"), term::Crlf,
+       term::Raw::from("44"), term::Crlf,
        term::Raw::from("55"),
        term::ControlCodes::set_scroll_region(2, 4),
        term::ControlCodes::cursor_position(1, 1),
@@ -258,13 +262,13 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("22"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("33"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("44"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("55"),
             term::ControlCodes::set_scroll_region(2, 4),
             term::ControlCodes::cursor_position(1, 1),
@@ -273,7 +277,7 @@ frag! {
 
 frag! {
     scrollback_delete_past_text { scrollback_lines: 100, width: 5, height: 5 }
-    <= term::Raw::from("11"), term::Crlf::default(),
+    <= term::Raw::from("11"), term::Crlf,
        term::Raw::from("22"),
        term::ControlCodes::cursor_position(4, 1),
        term::ControlCodes::delete_lines(1)
@@ -282,7 +286,7 @@ frag! {
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("11"),
-            term::Crlf::default(),
+            term::Crlf,
             term::Raw::from("22"),
             term::ControlCodes::cursor_position(4, 1),
             term::control_codes().clear_attrs
@@ -339,7 +343,7 @@ frag! {
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
-            term::Raw::from("13   "),
+            term::Raw::from("13"),
             term::ControlCodes::cursor_position(1, 2),
             term::control_codes().clear_attrs
 }
@@ -353,7 +357,7 @@ frag! {
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
-            term::Raw::from("3    "),
+            term::Raw::from("3"),
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_attrs
 }
@@ -367,7 +371,7 @@ frag! {
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
-            term::Raw::from("12   "),
+            term::Raw::from("12"),
             term::ControlCodes::cursor_position(1, 3),
             term::control_codes().clear_attrs
 }
@@ -376,17 +380,439 @@ frag! {
     delete_char_with_backfill_attrs { scrollback_lines: 100, width: 5, height: 4 }
     <= term::Raw::from("123"),
        term::ControlCodes::cursor_position(1, 2),
-       term::ControlCodes::fgcolor_idx(1),
+       term::ControlCodes::bgcolor_idx(1),
        term::ControlCodes::delete_character(1)
     => ContentRegion::All =>
             term::control_codes().clear_attrs,
             term::ControlCodes::cursor_position(1, 1),
             term::control_codes().clear_screen,
             term::Raw::from("13  "),
-            term::ControlCodes::fgcolor_idx(1),
+            term::ControlCodes::bgcolor_idx(1),
             term::Raw::from(" "),
-            term::control_codes().fgcolor_default,
+            term::control_codes().bgcolor_default,
             term::ControlCodes::cursor_position(1, 2),
             term::control_codes().clear_attrs,
-            term::ControlCodes::fgcolor_idx(1)
+            term::ControlCodes::bgcolor_idx(1)
+}
+
+frag! {
+    insert_column_basic { scrollback_lines: 100, width: 5, height: 3 }
+    <= term::Raw::from("123\r\n456\r\n789"),
+       term::ControlCodes::cursor_position(2, 2),
+       term::ControlCodes::insert_columns(1)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("1 23"),
+            term::Crlf,
+            term::Raw::from("4 56"),
+            term::Crlf,
+            term::Raw::from("7 89"),
+            term::ControlCodes::cursor_position(2, 2),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    insert_column_shift_off { scrollback_lines: 100, width: 3, height: 2 }
+    <= term::Raw::from("123\r\n45"),
+       term::ControlCodes::cursor_position(1, 1),
+       term::ControlCodes::insert_columns(2)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("  1"),
+            term::Crlf,
+            term::Raw::from("  4"),
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    delete_column_basic { scrollback_lines: 100, width: 5, height: 3 }
+    <= term::Raw::from("123\r\n456\r\n789"),
+       term::ControlCodes::cursor_position(3, 2),
+       term::ControlCodes::delete_columns(1)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("13"),
+            term::Crlf,
+            term::Raw::from("46"),
+            term::Crlf,
+            term::Raw::from("79"),
+            term::ControlCodes::cursor_position(3, 2),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    delete_column_scroll_region { scrollback_lines: 100, width: 5, height: 3 }
+    <= term::Raw::from("123\r\n456\r\n789"),
+       term::ControlCodes::set_scroll_region(2, 3),
+       term::ControlCodes::cursor_position(2, 1),
+       term::ControlCodes::delete_columns(2)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("123"),
+            term::Crlf,
+            term::Raw::from("6"),
+            term::Crlf,
+            term::Raw::from("9"),
+            term::ControlCodes::set_scroll_region(2, 3),
+            term::ControlCodes::cursor_position(2, 1),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    alt_screen_insert_column { scrollback_lines: 100, width: 4, height: 2 }
+    <= term::control_codes().enable_alt_screen,
+       term::Raw::from("ab\r\ncd"),
+       term::ControlCodes::cursor_position(1, 1),
+       term::ControlCodes::insert_columns(1)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from(" ab"),
+            term::Crlf,
+            term::Raw::from(" cd"),
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_attrs
+}
+
+#[test]
+fn insert_delete_columns_backfill_with_erase_attrs() {
+    use shpool_vterm::{Color, Size, Term};
+
+    for (input, backfill) in [(&b"\x1b['}"[..], 0), (b"\x1b['~", 3)] {
+        let mut term = Term::new(100, Size { width: 4, height: 2 });
+        term.process(b"abcd\r\nefgh\x1b[1;1H\x1b[4;7;41m");
+        term.process(input);
+        for row in 0..2 {
+            let attrs = term.cell_at(row, backfill).unwrap().attrs().clone();
+            assert_eq!(attrs.bgcolor, Color::Idx(1), "{}", String::from_utf8_lossy(input));
+            assert_eq!(attrs.underline, None, "{}", String::from_utf8_lossy(input));
+            assert!(!attrs.inverse, "{}", String::from_utf8_lossy(input));
+        }
+    }
+}
+
+#[test]
+fn insert_delete_lines_main_matches_alt() {
+    let size = shpool_vterm::Size { width: 5, height: 6 };
+    assert_screens_agree(
+        size,
+        &[
+            &term::Raw::from("111\r\n222\r\n333"),
+            &term::ControlCodes::cursor_position(1, 1),
+            &term::ControlCodes::insert_lines(2),
+        ],
+    );
+    assert_screens_agree(
+        size,
+        &[
+            &term::Raw::from("111\r\n222\r\n333\r\n444"),
+            &term::ControlCodes::set_scroll_region(2, 5),
+            &term::ControlCodes::cursor_position(3, 1),
+            &term::ControlCodes::insert_lines(1),
+            &term::ControlCodes::cursor_position(2, 1),
+            &term::ControlCodes::delete_lines(3),
+        ],
+    );
+    assert_screens_agree(
+        size,
+        &[
+            &term::Raw::from("111\r\n222\r\n333\r\n444\r\n555\r\n666"),
+            &term::ControlCodes::set_scroll_region(2, 4),
+            &term::ControlCodes::cursor_position(2, 1),
+            &term::ControlCodes::delete_lines(1),
+            &term::ControlCodes::insert_lines(10),
+        ],
+    );
+}
+
+#[test]
+fn insert_delete_chars_main_matches_alt() {
+    let size = shpool_vterm::Size { width: 6, height: 3 };
+    for code in [
+        term::ControlCodes::insert_character(1000),
+        term::ControlCodes::delete_character(1000),
+        term::ControlCodes::insert_character(7),
+        term::ControlCodes::delete_character(7),
+    ] {
+        assert_screens_agree(
+            size,
+            &[
+                &term::Raw::from("abcde\r\nab"),
+                &term::ControlCodes::bgcolor_idx(1),
+                &term::control_codes().underline,
+                &term::ControlCodes::cursor_position(1, 4),
+                &code,
+                &term::ControlCodes::cursor_position(2, 2),
+                &code,
+                &term::ControlCodes::cursor_position(3, 3),
+                &code,
+            ],
+        );
+    }
+}
+
+#[test]
+fn insert_chars_backfill_with_erase_attrs() {
+    let mut term = shpool_vterm::Term::new(100, shpool_vterm::Size { width: 6, height: 2 });
+    term.process(b"abc\x1b[4;44m\x1b[1;2H\x1b[2@\x1b[2;3H\x1b[@");
+    assert_eq!(row_text(&term, 0), "a  bc ");
+    for (row, col) in [(0, 1), (0, 2), (1, 2)] {
+        let attrs = term.cell_at(row, col).unwrap().attrs().clone();
+        assert_eq!(attrs.bgcolor, shpool_vterm::Color::Idx(4), "({row}, {col})");
+        assert_eq!(attrs.underline, None, "({row}, {col})");
+    }
+    assert_eq!(term.cell_at(0, 3).unwrap().attrs(), &shpool_vterm::Attrs::default());
+}
+
+#[test]
+fn insert_delete_chars_default_and_clamp() {
+    let mut term = shpool_vterm::Term::new(100, shpool_vterm::Size { width: 6, height: 2 });
+    term.process(b"abcdef\r\nabcdef");
+
+    // An omitted or zero count means 1.
+    term.process(b"\x1b[1;2H\x1b[@\x1b[1;4H\x1b[0P");
+    assert_eq!(row_text(&term, 0), "a bde ");
+
+    // A count past the end of the line just clears to the end.
+    term.process(b"\x1b[1;4H\x1b[1000@\x1b[2;3H\x1b[1000P");
+    assert_eq!(row_text(&term, 0), "a b   ");
+    assert_eq!(row_text(&term, 1), "ab    ");
+}
+
+#[test]
+fn delete_chars_on_short_line_backfills_deleted_columns() {
+    let mut term = shpool_vterm::Term::new(100, shpool_vterm::Size { width: 6, height: 1 });
+    term.process(b"abc\x1b[4;7;41m\x1b[1;2H\x1b[4P");
+    let red = term.resolved_colors(term::Pos { row: 0, col: 5 }).unwrap().1;
+    for col in 0..6 {
+        let bg = term.resolved_colors(term::Pos { row: 0, col }).unwrap().1;
+        assert_eq!(bg == red, col >= 2, "col {col}");
+        let attrs = term.cell_at(0, col).unwrap().attrs();
+        assert_eq!(attrs.underline, None, "col {col}");
+        assert!(!attrs.inverse, "col {col}");
+    }
+}
+
+#[test]
+fn reverse_index_at_top_margin() {
+    let size = shpool_vterm::Size { width: 3, height: 5 };
+    let input: [&dyn AsTermInput; 4] = [
+        &term::Raw::from("1\r\n2\r\n3\r\n4\r\n5"),
+        &term::ControlCodes::set_scroll_region(2, 4),
+        &term::ControlCodes::cursor_position(2, 2),
+        &term::control_codes().reverse_index,
+    ];
+    assert_screens_agree(size, &input);
+
+    let mut term = shpool_vterm::Term::new(100, size);
+    term.process(&term::term_input_all(&input));
+    let rows: Vec<String> = (0..size.height).map(|row| row_text(&term, row)).collect();
+    assert_eq!(rows, ["1  ", "   ", "2  ", "3  ", "5  "]);
+
+    // The cursor stays put.
+    term.process(b"x");
+    assert_eq!(row_text(&term, 1), " x ");
+
+    // Below the top margin it is just a cursor move.
+    term.process(b"\x1b[4;1H\x1bMy");
+    assert_eq!(row_text(&term, 2), "y  ");
+    assert_eq!(row_text(&term, 3), "3  ");
+}
+
+frag! {
+    scroll_left_one { scrollback_lines: 100, width: 5, height: 2 }
+    <= term::Raw::from("abcde"), term::Crlf,
+       term::Raw::from("fgh"),
+       term::ControlCodes::scroll_left(1)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("bcde"),
+            term::Crlf,
+            term::Raw::from("gh"),
+            term::ControlCodes::cursor_position(2, 4),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    scroll_left_two { scrollback_lines: 100, width: 5, height: 2 }
+    <= term::Raw::from("abcde"), term::Crlf,
+       term::Raw::from("fgh"),
+       term::ControlCodes::scroll_left(2)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from("cde"),
+            term::Crlf,
+            term::Raw::from("h"),
+            term::ControlCodes::cursor_position(2, 4),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    scroll_right_one { scrollback_lines: 100, width: 5, height: 2 }
+    <= term::Raw::from("abcde"), term::Crlf,
+       term::Raw::from("fgh"),
+       term::ControlCodes::scroll_right(1)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::Raw::from(" abcd"),
+            term::Crlf,
+            term::Raw::from(" fgh"),
+            term::ControlCodes::cursor_position(2, 4),
+            term::control_codes().clear_attrs
+}
+
+frag! {
+    scroll_right_two_bce { scrollback_lines: 100, width: 5, height: 2 }
+    <= term::Raw::from("abcde"), term::Crlf,
+       term::Raw::from("fgh"),
+       term::ControlCodes::bgcolor_idx(1),
+       term::ControlCodes::scroll_right(2)
+    => ContentRegion::All =>
+            term::control_codes().clear_attrs,
+            term::ControlCodes::cursor_position(1, 1),
+            term::control_codes().clear_screen,
+            term::ControlCodes::bgcolor_idx(1),
+            term::Raw::from("  "),
+            term::control_codes().bgcolor_default,
+            term::Raw::from("abc"),
+            term::Crlf,
+            term::ControlCodes::bgcolor_idx(1),
+            term::Raw::from("  "),
+            term::control_codes().bgcolor_default,
+            term::Raw::from("fgh"),
+            term::ControlCodes::cursor_position(2, 4),
+            term::control_codes().clear_attrs,
+            term::ControlCodes::bgcolor_idx(1)
+}
+
+fn contents_after(input: &[u8]) -> Vec<u8> {
+    let mut term = shpool_vterm::Term::new(100, shpool_vterm::Size { width: 5, height: 4 });
+    term.process(input);
+    term.contents(ContentRegion::All)
+}
+
+#[test]
+fn huge_counts_are_clamped() {
+    let setup = b"11\r\n22\r\n33\r\n44\x1b[2;2H\x1b[41m";
+    // vte caps params at u16::MAX, so this is the biggest count a program
+    // can ask for. Repeating it a bunch makes sure each one is cheap.
+    for (huge, clamped) in [
+        (&b"\x1b[65535@"[..], &b"\x1b[4@"[..]),
+        (b"\x1b[65535 A", b"\x1b[5 A"),
+        (b"\x1b[65535 @", b"\x1b[5 @"),
+        (b"\x1b[65535P", b"\x1b[4P"),
+        (b"\x1b[65535L", b"\x1b[3L"),
+        (b"\x1b[65535M", b"\x1b[3M"),
+        (b"\x1b[65535C", b"\x1b[4C"),
+        (b"\x1b[65535B", b"\x1b[3B"),
+        (b"\x1b[65535T", b"\x1b[100T"),
+        (b"\x1b[65535b", b"\x1b[2500b"),
+    ] {
+        let mut huge_input = setup.to_vec();
+        let mut clamped_input = setup.to_vec();
+        for _ in 0..1000 {
+            huge_input.extend_from_slice(huge);
+            clamped_input.extend_from_slice(clamped);
+        }
+        assert_eq!(
+            contents_after(&huge_input),
+            contents_after(&clamped_input),
+            "{}",
+            String::from_utf8_lossy(huge)
+        );
+    }
+}
+
+fn row_text(term: &shpool_vterm::Term, row: usize) -> String {
+    (0..term.size().width)
+        .map(|col| match term.cell_at(row, col).unwrap().chars() {
+            [] => ' ',
+            chars => chars[0],
+        })
+        .collect()
+}
+
+#[test]
+fn insert_delete_chars_within_margins() {
+    let size = shpool_vterm::Size { width: 6, height: 2 };
+    let mut term = shpool_vterm::Term::new(100, size);
+    // Margins on columns 2 through 4.
+    term.process(b"abcdef\r\nabcdef\x1b[?69h\x1b[2;4s");
+
+    term.process(b"\x1b[1;2H\x1b[@");
+    assert_eq!(row_text(&term, 0), "a bcef");
+    term.process(b"\x1b[2;2H\x1b[P");
+    assert_eq!(row_text(&term, 1), "acd ef");
+
+    // Outside the margins, nothing happens.
+    term.process(b"\x1b[1;5H\x1b[@\x1b[1;1H\x1b[P");
+    assert_eq!(row_text(&term, 0), "a bcef");
+
+    // The margins survive a dump and restore.
+    let mut restored =
+        shpool_vterm::Term::from_contents(100, size, &term.contents(ContentRegion::All));
+    restored.process(b"\x1b[1;3H\x1b[2P");
+    assert_eq!(row_text(&restored, 0), "a   ef");
+
+    // Turning DECLRMM off clears the margins.
+    term.process(b"\x1b[?69l\x1b[1;2H\x1b[P");
+    assert_eq!(row_text(&term, 0), "abcef ");
+}
+
+#[test]
+fn scroll_left_right_within_margins() {
+    let mut term = shpool_vterm::Term::new(100, shpool_vterm::Size { width: 6, height: 2 });
+    term.process(b"abcdef\r\nabcdef\x1b[?69h\x1b[2;4s");
+
+    term.process(b"\x1b[ @");
+    assert_eq!(row_text(&term, 0), "acd ef");
+    assert_eq!(row_text(&term, 1), "acd ef");
+    term.process(b"\x1b[2 A");
+    assert_eq!(row_text(&term, 0), "a  cef");
+}
+
+#[test]
+fn back_and_forward_index_at_margins() {
+    let size = shpool_vterm::Size { width: 6, height: 2 };
+    let input: [&dyn AsTermInput; 5] = [
+        &term::Raw::from("abcdef\r\nabcdef\x1b[?69h\x1b[2;5s"),
+        &term::ControlCodes::cursor_position(1, 2),
+        &term::control_codes().back_index,
+        &term::ControlCodes::cursor_position(2, 5),
+        &term::control_codes().forward_index,
+    ];
+    assert_screens_agree(size, &input);
+
+    let mut term = shpool_vterm::Term::new(100, size);
+    term.process(&term::term_input_all(&input[..3]));
+    // DECBI at the left margin and DECFI at the right one scroll the
+    // whole region, leaving the columns outside the margins alone.
+    assert_eq!(row_text(&term, 0), "a bcdf");
+    assert_eq!(row_text(&term, 1), "a bcdf");
+    term.process(&term::term_input_all(&input[3..]));
+    assert_eq!(row_text(&term, 0), "abcd f");
+
+    // The cursor stays on the margin.
+    term.process(b"x");
+    assert_eq!(row_text(&term, 1), "abcdxf");
+
+    // Away from the margins they just move the cursor.
+    term.process(b"\x1b[1;3H\x1b6y\x1b9\x1b9z");
+    assert_eq!(row_text(&term, 0), "aycdzf");
 }
@@ -16,18 +16,11 @@ macro_rules! frag {
     } => {
         #[test]
         fn $test_name() {
-            use shpool_vterm::term::AsTermInput;
-            let mut input: Vec<u8> = vec![];
-            $(
-                $input_expr.term_input_into(&mut input);
-            )*
+            let input = shpool_vterm::term::term_input_all(&[ $( &$input_expr ),* ]);
 
             $(
                 {
-                    let mut output: Vec<u8> = vec![];
-                    $(
-                        $output_expr.term_input_into(&mut output);
-                    )*
+                    let output = shpool_vterm::term::term_input_all(&[ $( &$output_expr ),* ]);
                     crate::support::frag::round_trip_frag(
                         input.as_slice(), output.as_slice(),
                         $scrollback_lines,
@@ -84,11 +77,9 @@ impl vte::Perform for PrettyTerm {
 
     fn execute(&mut self, byte: u8) {
         match byte {
-            b'\n' => write!(self.into, "<\\n>\n").unwrap(),
+            b'\n' => writeln!(self.into, "<\\n>").unwrap(),
             b'\r' => write!(self.into, "<\\r>").unwrap(),
-            _ => {
-                eprintln!("pp: unhandled byte {}", byte);
-            }
+            _ => write!(self.into, "<{}>", term::ControlCode::C0 { byte }).unwrap(),
         }
     }
 
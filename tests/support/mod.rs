@@ -1,2 +1,3 @@
 #[macro_use]
 pub mod frag;
+pub mod screens;
@@ -0,0 +1,28 @@
+use shpool_vterm::term::{self, AsTermInput};
+
+/// Run the same input on a main screen terminal and an alt screen
+/// terminal and check that they end up showing the same cells with
+/// the same attributes.
+// Not every test binary that pulls in the support module uses this.
+#[allow(dead_code)]
+pub fn assert_screens_agree(size: shpool_vterm::Size, input: &[&dyn AsTermInput]) {
+    let buf = term::term_input_all(input);
+
+    let mut main_term = shpool_vterm::Term::new(100, size);
+    main_term.process(&buf);
+    let mut alt_term = shpool_vterm::Term::new(100, size);
+    let mut alt_buf = vec![];
+    term::control_codes().enable_alt_screen.term_input_into(&mut alt_buf);
+    alt_term.process(&alt_buf);
+    alt_term.process(&buf);
+
+    for row in 0..size.height {
+        for col in 0..size.width {
+            assert_eq!(
+                main_term.cell_at(row, col).map(|c| (c.chars().to_vec(), c.attrs().clone())),
+                alt_term.cell_at(row, col).map(|c| (c.chars().to_vec(), c.attrs().clone())),
+                "mismatch at ({row}, {col})"
+            );
+        }
+    }
+}